@@ -0,0 +1,148 @@
+/**
+* This file defines subcommands for managing the API keys
+* that CLI and bot clients use to talk to the dataserver.
+* Keys are managed directly in the dataserver database.
+*/
+use structopt::StructOpt;
+
+// Local imports
+use crate::dataserver::{
+    auth::create_api_key,
+    models::User,
+    storage::{
+        Storage,
+        open_migrated_storage
+    }
+};
+
+/// Passthrough command for the apikey subcommand of `rd`
+#[derive(Debug, StructOpt)]
+#[structopt(name = "apikey")]
+pub struct ApiKeyCLI {
+
+    #[structopt(subcommand)]
+    pub cmd: ApiKeyCommand
+}
+
+/// Command arg options for creating an API key.
+#[derive(Debug, StructOpt)]
+pub struct CreateApiKeyCLI {
+    #[structopt(
+        short,
+        long,
+        help = "Username of the user the key acts as."
+    )]
+    user: String,
+
+    #[structopt(
+        short,
+        long,
+        help = "Label to tell the key apart from others."
+    )]
+    name: String
+}
+
+/// Command arg options for listing API keys.
+#[derive(Debug, StructOpt)]
+pub struct ListApiKeysCLI {
+    #[structopt(
+        short,
+        long,
+        help = "Username of the user owning the keys."
+    )]
+    user: String
+}
+
+/// Command arg options for revoking an API key.
+#[derive(Debug, StructOpt)]
+pub struct RevokeApiKeyCLI {
+    #[structopt(
+        short,
+        long,
+        help = "Username of the user owning the key."
+    )]
+    user: String,
+
+    #[structopt(
+        short,
+        long,
+        help = "ID of the key to revoke."
+    )]
+    id: i32
+}
+
+/// Enum listing the various subcommands of the `apikey` command.
+#[derive(Debug, StructOpt)]
+pub enum ApiKeyCommand {
+
+    #[structopt(about = "Creates a new API key for a user.")]
+    Create(CreateApiKeyCLI),
+
+    #[structopt(about = "Lists the API keys of a user.")]
+    List(ListApiKeysCLI),
+
+    #[structopt(about = "Revokes an API key.")]
+    Revoke(RevokeApiKeyCLI)
+}
+
+/// Look up a user by username.
+pub fn find_user(storage: &dyn Storage, username: &str) -> Result<User, String> {
+    storage.get_user_by_username(username)?.ok_or_else(|| format!("User {} does not exist.", username))
+}
+
+/// Run an apikey command.
+///
+/// # Arguments
+/// * `command` - Command to run.
+///
+/// # Examples
+/// ```
+/// run_apikey_command(&command);
+/// ```
+pub fn run_apikey_command(command: &ApiKeyCLI) {
+    let storage = match open_migrated_storage() {
+        Ok(storage) => storage,
+        Err(msg) => {
+            error!("{}", msg);
+            return;
+        }
+    };
+
+    match &command.cmd {
+
+        // Create a new key
+        ApiKeyCommand::Create(cmd) => {
+            match find_user(storage.as_ref(), &cmd.user).and_then(|user| create_api_key(storage.as_ref(), user.id, &cmd.name)) {
+                Ok(created) => {
+                    info!("Created API key {} ({}). Store it now: it will not be shown again.", created.api_key.id, created.api_key.prefix);
+
+                    // Printed bare so scripts can capture it
+                    println!("{}", created.key);
+                },
+                Err(msg) => error!("{}", msg)
+            }
+        },
+
+        // List a user's keys
+        ApiKeyCommand::List(cmd) => {
+            match find_user(storage.as_ref(), &cmd.user).and_then(|user| storage.list_api_keys(user.id)) {
+                Ok(keys) => keys.iter().for_each(|k| {
+                    let status = match k.revoked_at {
+                        Some(revoked_at) => format!("revoked at {}", revoked_at),
+                        None => String::from("active")
+                    };
+                    info!("{}: {} ({}) created at {}, {}", k.id, k.name, k.prefix, k.created_at, status);
+                }),
+                Err(msg) => error!("{}", msg)
+            }
+        },
+
+        // Revoke a key
+        ApiKeyCommand::Revoke(cmd) => {
+            match find_user(storage.as_ref(), &cmd.user).and_then(|user| storage.revoke_api_key(user.id, cmd.id)) {
+                Ok(key) => info!("Revoked API key {} ({}).", key.id, key.prefix),
+                Err(msg) => error!("{}", msg)
+            }
+        }
+    }
+}
@@ -0,0 +1,645 @@
+/**
+* This file layers the configuration of `rd`. Every setting
+* may come from its default, rd.toml in RD_HOME, base.env,
+* the instance's instance.env, the environment or a flag,
+* each taking precedence over the ones before it.
+*/
+use std::{
+    collections::HashMap,
+    env,
+    fmt,
+    fs::read_to_string,
+    path::{
+        Path,
+        PathBuf
+    },
+    str::FromStr,
+    time::Duration
+};
+use log::LevelFilter;
+use structopt::StructOpt;
+use toml::Value;
+
+// Local imports
+use crate::cli::{
+    environment::{
+        current_instance,
+        get_env,
+        get_instance_env,
+        get_selected_profile_env,
+        get_or_create_rd_home,
+        process_env_var
+    },
+    logging::{
+        DEFAULT_LOG_LEVEL,
+        LogFormat,
+        LogOutput,
+        LoggingCLI,
+        LoggingConfig,
+        parse_module_levels
+    },
+    logs::{
+        DEFAULT_LOG_COMPRESS,
+        DEFAULT_LOG_MAX_AGE_HOURS,
+        DEFAULT_LOG_MAX_SIZE_MB,
+        DEFAULT_LOG_RETENTION_DAYS,
+        DEFAULT_LOG_RETENTION_FILES,
+        RotationPolicy
+    },
+    secrets::{
+        MASKED_SECRET,
+        reveal_secrets
+    }
+};
+use crate::dataserver::{
+    auth::SESSION_LIFETIME_HOURS,
+    health::DEFAULT_MIN_FREE_MB,
+    models::MAX_PAGE_SIZE,
+    signals::DRAIN_TIMEOUT_SECS
+};
+
+/// Name of the configuration file kept in RD_HOME.
+pub const CONFIG_FILE: &str = "rd.toml";
+
+/// Where the value of a setting came from, lowest precedence first.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Source {
+    Default,
+    File,
+    BaseEnv,
+    Profile,
+    InstanceEnv,
+    Environment,
+    Flag
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Source::Default => "default",
+            Source::File => CONFIG_FILE,
+            Source::BaseEnv => "base.env",
+            Source::Profile => "profile",
+            Source::InstanceEnv => "instance.env",
+            Source::Environment => "environment",
+            Source::Flag => "flag"
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// How a setting is written in rd.toml.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Text,
+    Number,
+    Boolean,
+
+    /// Written as an array in rd.toml and comma separated everywhere else.
+    List
+}
+
+/// A setting which may be given in rd.toml.
+#[derive(Debug)]
+pub struct Setting {
+    /// Name in rd.toml, as `<section>.<name>`.
+    pub key: &'static str,
+
+    /// Environment variable holding the setting.
+    pub var: &'static str,
+
+    /// Whether the value must never be shown.
+    pub secret: bool,
+
+    kind: Kind,
+    default: Option<String>
+}
+
+/// Describe a setting which is not secret.
+fn setting<T: ToString>(key: &'static str, var: &'static str, kind: Kind, default: Option<T>) -> Setting {
+    Setting {
+        key,
+        var,
+        secret: false,
+        kind,
+        default: default.map(|d| d.to_string())
+    }
+}
+
+/// Every setting rd.toml may hold, in the order they are shown.
+pub fn settings() -> Vec<Setting> {
+    vec![
+        setting("server.host", "RD_HOST", Kind::Text, Some("0.0.0.0")),
+        setting("server.port", "RD_PORT", Kind::Number, Some(5555)),
+        setting("server.workers", "RD_WORKERS", Kind::Number, Some(3)),
+        setting("server.metrics_port", "RD_METRICS_PORT", Kind::Number, None::<u16>),
+        setting("server.tls_cert", "RD_TLS_CERT", Kind::Text, None::<String>),
+        setting("server.tls_key", "RD_TLS_KEY", Kind::Text, None::<String>),
+        setting("storage.backend", "RD_STORAGE", Kind::Text, Some("sqlite")),
+        setting("logging.level", "RD_LOG_LEVEL", Kind::Text, Some(DEFAULT_LOG_LEVEL.to_string().to_lowercase())),
+        setting("logging.format", "RD_LOG_FORMAT", Kind::Text, Some("human")),
+        setting("logging.output", "RD_LOG_OUTPUT", Kind::Text, Some("stdout")),
+        setting("logging.modules", "RD_LOG_MODULES", Kind::List, None::<String>),
+        setting("logging.max_size_mb", "RD_LOG_MAX_SIZE_MB", Kind::Number, Some(DEFAULT_LOG_MAX_SIZE_MB)),
+        setting("logging.max_age_hours", "RD_LOG_MAX_AGE_HOURS", Kind::Number, Some(DEFAULT_LOG_MAX_AGE_HOURS)),
+        setting("logging.compress", "RD_LOG_COMPRESS", Kind::Boolean, Some(DEFAULT_LOG_COMPRESS)),
+        setting("logging.retention_days", "RD_LOG_RETENTION_DAYS", Kind::Number, Some(DEFAULT_LOG_RETENTION_DAYS)),
+        setting("logging.retention_files", "RD_LOG_RETENTION_FILES", Kind::Number, Some(DEFAULT_LOG_RETENTION_FILES)),
+        Setting {
+            secret: true,
+            ..setting("auth.secret_key", "RD_SECRET_KEY", Kind::Text, None::<String>)
+        },
+        setting("auth.session_lifetime_hours", "RD_SESSION_LIFETIME_HOURS", Kind::Number, Some(SESSION_LIFETIME_HOURS)),
+        setting("limits.max_page_size", "RD_MAX_PAGE_SIZE", Kind::Number, Some(MAX_PAGE_SIZE)),
+        setting("limits.drain_timeout_secs", "RD_DRAIN_TIMEOUT_SECS", Kind::Number, Some(DRAIN_TIMEOUT_SECS)),
+        setting("limits.health_min_free_mb", "RD_HEALTH_MIN_FREE_MB", Kind::Number, Some(DEFAULT_MIN_FREE_MB))
+    ]
+}
+
+/// Get the path of rd.toml in RD_HOME.
+///
+/// # Examples
+/// ```
+/// let config_file = get_config_file_path().unwrap();
+/// ```
+pub fn get_config_file_path() -> Result<PathBuf, String> {
+    let mut config_path = PathBuf::from(get_or_create_rd_home()?);
+    config_path.push(CONFIG_FILE);
+    Ok(config_path)
+}
+
+/// Write a value of rd.toml the way it would be written in an env file.
+fn toml_to_string(value: &Value) -> Option<String> {
+    match value {
+        Value::String(text) => Some(text.clone()),
+        Value::Integer(number) => Some(number.to_string()),
+        Value::Float(number) => Some(number.to_string()),
+        Value::Boolean(flag) => Some(flag.to_string()),
+        Value::Array(items) => items.iter()
+                                    .map(|item| item.as_str().map(String::from))
+                                    .collect::<Option<Vec<String>>>()
+                                    .map(|items| items.join(",")),
+        _ => None
+    }
+}
+
+/// Read the settings of rd.toml, keyed by their name in the file.
+/// Having no rd.toml is the same as having an empty one.
+///
+/// # Examples
+/// ```
+/// let values = read_config_file().unwrap();
+/// ```
+pub fn read_config_file() -> Result<HashMap<String, String>, String> {
+    let config_path = get_config_file_path()?;
+    if !config_path.exists() {
+        return Ok(HashMap::new());
+    }
+
+    let contents = read_to_string(&config_path).map_err(|err| err.to_string())?;
+    let document = contents.parse::<Value>().map_err(|err| format!("Invalid {}: {}", CONFIG_FILE, err))?;
+    let known = settings();
+
+    let mut values = HashMap::new();
+    for (section, table) in document.as_table().into_iter().flatten() {
+        let table = table.as_table().ok_or_else(|| format!("{} in {} must be a section.", section, CONFIG_FILE))?;
+        for (name, value) in table {
+            let key = format!("{}.{}", section, name);
+            if !known.iter().any(|s| s.key == key) {
+                return Err(format!("Unknown setting {} in {}.", key, CONFIG_FILE));
+            }
+
+            let value = toml_to_string(value).ok_or_else(|| format!("Setting {} in {} has an unsupported type.", key, CONFIG_FILE))?;
+            values.insert(key, value);
+        }
+    }
+    Ok(values)
+}
+
+/// Get the settings of rd.toml keyed by the environment variable
+/// each one is held in.
+///
+/// # Examples
+/// ```
+/// let variables = get_config_env().unwrap();
+/// ```
+pub fn get_config_env() -> Result<HashMap<String, String>, String> {
+    let values = read_config_file()?;
+    Ok(settings().into_iter()
+                 .filter_map(|s| values.get(s.key).map(|value| (s.var.to_string(), value.clone())))
+                 .collect())
+}
+
+/// Load the settings of rd.toml into the process environment,
+/// leaving alone anything already set by the environment or
+/// base.env, so everything reading the environment sees them.
+///
+/// # Examples
+/// ```
+/// if set_config_environment().is_ok() {
+///     // Do stuff here
+/// }
+/// ```
+pub fn set_config_environment() -> Result<(), String> {
+    get_config_env()?.iter()
+                     .filter(|(key, _)| env::var(key).is_err())
+                     .for_each(|(key, value)| env::set_var(key, value));
+    Ok(())
+}
+
+/// A setting once every layer has been merged.
+#[derive(Debug)]
+pub struct EffectiveSetting {
+    pub setting: Setting,
+    pub value: Option<String>,
+    pub source: Source
+}
+
+/// Every setting once every layer has been merged.
+#[derive(Debug)]
+pub struct EffectiveConfig {
+    pub settings: Vec<EffectiveSetting>
+}
+
+impl EffectiveConfig {
+
+    /// Merge the layers of configuration of an instance.
+    ///
+    /// # Arguments
+    /// * `instance` - Instance whose instance.env is used.
+    /// * `flags` - Settings given as flags, as `(key, value)`.
+    ///
+    /// # Examples
+    /// ```
+    /// let effective = EffectiveConfig::resolve("default", &[("server.port", String::from("8080"))])?;
+    /// ```
+    pub fn resolve(instance: &str, flags: &[(&str, String)]) -> Result<EffectiveConfig, String> {
+        let file = read_config_file()?;
+        let mut base_env = get_env()?;
+        let mut profile_env = get_selected_profile_env(&base_env)?;
+        let mut instance_env = get_instance_env(instance)?;
+
+        // Secrets that cannot be decrypted were already reported when the
+        // environment was loaded, and are left out here as they were there
+        for variables in vec![&mut base_env, &mut profile_env, &mut instance_env] {
+            let _ = reveal_secrets(variables);
+        }
+
+        let settings = settings().into_iter().map(|setting| {
+            let layers = [
+                (file.get(setting.key), Source::File),
+                (base_env.get(setting.var), Source::BaseEnv),
+                (profile_env.get(setting.var), Source::Profile),
+                (instance_env.get(setting.var), Source::InstanceEnv),
+                (process_env_var(setting.var), Source::Environment),
+                (flags.iter().find(|(key, _)| *key == setting.key).map(|(_, value)| value), Source::Flag)
+            ];
+
+            let mut value = setting.default.clone();
+            let mut source = Source::Default;
+            for (layer_value, layer_source) in layers.iter() {
+                if let Some(layer_value) = layer_value {
+                    // Lists given as flags add to the list rather than replace it
+                    value = match (&value, setting.kind, layer_source) {
+                        (Some(current), Kind::List, Source::Flag) if !current.is_empty() => Some(format!("{},{}", current, layer_value)),
+                        _ => Some(layer_value.to_string())
+                    };
+                    source = *layer_source;
+                }
+            }
+            EffectiveSetting {
+                setting,
+                value,
+                source
+            }
+        }).collect();
+
+        Ok(EffectiveConfig {
+            settings
+        })
+    }
+
+    /// Parse a setting, if it has a value.
+    fn get<T: FromStr>(&self, key: &str) -> Result<Option<T>, String> where T::Err: ToString {
+        let effective = self.settings.iter()
+                                     .find(|e| e.setting.key == key)
+                                     .ok_or_else(|| format!("Unknown setting {}.", key))?;
+        match &effective.value {
+            Some(value) if !value.trim().is_empty() => value.trim()
+                                                            .parse()
+                                                            .map(Some)
+                                                            .map_err(|err: T::Err| format!("Invalid {} from {}: {}", key, effective.source, err.to_string())),
+            _ => Ok(None)
+        }
+    }
+
+    /// Parse a setting which must have a value.
+    fn require<T: FromStr>(&self, key: &str) -> Result<T, String> where T::Err: ToString {
+        self.get(key)?.ok_or_else(|| format!("{} must be set.", key))
+    }
+
+    /// Parse and check every setting.
+    ///
+    /// # Examples
+    /// ```
+    /// let config = effective.typed()?;
+    /// ```
+    pub fn typed(&self) -> Result<RdConfig, String> {
+        let server = ServerConfig {
+            host: self.require("server.host")?,
+            port: self.require("server.port")?,
+            workers: self.require("server.workers")?,
+            metrics_port: self.get("server.metrics_port")?,
+            tls_cert: self.get("server.tls_cert")?,
+            tls_key: self.get("server.tls_key")?
+        };
+        if server.port == 0 {
+            return Err(String::from("server.port must not be 0."));
+        }
+        if server.workers == 0 {
+            return Err(String::from("server.workers must be at least 1."));
+        }
+        if server.metrics_port == Some(server.port) {
+            return Err(String::from("server.metrics_port must differ from server.port."));
+        }
+        match (&server.tls_cert, &server.tls_key) {
+            (Some(_), None) | (None, Some(_)) => return Err(String::from("server.tls_cert and server.tls_key must be set together.")),
+            _ => {}
+        }
+        if server.tls_enabled() && !cfg!(feature = "tls") {
+            return Err(String::from("Serving HTTPS needs rd built with the tls feature: cargo build --features tls"));
+        }
+        for path in server.tls_cert.iter().chain(server.tls_key.iter()) {
+            if !Path::new(path).is_file() {
+                return Err(format!("TLS file {} does not exist.", path));
+            }
+        }
+
+        let storage = StorageConfig {
+            backend: self.require("storage.backend")?
+        };
+        if !["sqlite", "memory"].contains(&storage.backend.as_str()) {
+            return Err(format!("Unknown storage.backend {}. Use sqlite or memory.", storage.backend));
+        }
+
+        let modules = self.get::<String>("logging.modules")?.unwrap_or_default();
+        let logging = LoggingConfig {
+            level: self.require::<LevelFilter>("logging.level")?,
+            format: self.require::<LogFormat>("logging.format")?,
+            output: self.require::<LogOutput>("logging.output")?,
+            modules: parse_module_levels(&modules.split(',').collect::<Vec<&str>>())
+                         .map_err(|msg| format!("Invalid logging.modules: {}", msg))?
+        };
+        let log_rotation = RotationPolicy {
+            max_size: self.require::<u64>("logging.max_size_mb")? * 1024 * 1024,
+            max_age: Duration::from_secs(self.require::<u64>("logging.max_age_hours")? * 3600),
+            compress: self.require("logging.compress")?,
+            retention_days: self.require("logging.retention_days")?,
+            retention_files: self.require("logging.retention_files")?
+        };
+
+        let auth = AuthConfig {
+            secret_key: self.get("auth.secret_key")?,
+            session_lifetime_hours: self.require("auth.session_lifetime_hours")?
+        };
+        if auth.session_lifetime_hours <= 0 {
+            return Err(String::from("auth.session_lifetime_hours must be positive."));
+        }
+
+        let limits = LimitsConfig {
+            max_page_size: self.require("limits.max_page_size")?,
+            drain_timeout_secs: self.require("limits.drain_timeout_secs")?,
+            health_min_free_mb: self.require("limits.health_min_free_mb")?
+        };
+        if limits.max_page_size <= 0 {
+            return Err(String::from("limits.max_page_size must be positive."));
+        }
+
+        Ok(RdConfig {
+            server,
+            storage,
+            logging,
+            log_rotation,
+            auth,
+            limits
+        })
+    }
+}
+
+/// Where and how the dataserver listens.
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    pub host: String,
+    pub port: u16,
+    pub workers: u16,
+    pub metrics_port: Option<u16>,
+
+    /// Certificate chain to serve HTTPS with, in PEM.
+    pub tls_cert: Option<String>,
+
+    /// Private key of the certificate, in PEM.
+    pub tls_key: Option<String>
+}
+
+impl ServerConfig {
+
+    /// Whether the server serves HTTPS rather than plain HTTP.
+    pub fn tls_enabled(&self) -> bool {
+        self.tls_cert.is_some() && self.tls_key.is_some()
+    }
+}
+
+/// Where the dataserver keeps its data.
+#[derive(Debug, Clone)]
+pub struct StorageConfig {
+    pub backend: String
+}
+
+/// How the dataserver authenticates requests.
+#[derive(Debug, Clone)]
+pub struct AuthConfig {
+    pub secret_key: Option<String>,
+    pub session_lifetime_hours: i64
+}
+
+/// Limits the dataserver keeps to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LimitsConfig {
+    pub max_page_size: i32,
+    pub drain_timeout_secs: u64,
+    pub health_min_free_mb: u64
+}
+
+/// Settings the dataserver reads while serving, which SIGHUP
+/// reloads without restarting the server.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReloadableConfig {
+    pub session_lifetime_hours: i64,
+    pub limits: LimitsConfig
+}
+
+/// Checked configuration of `rd`.
+#[derive(Debug)]
+pub struct RdConfig {
+    pub server: ServerConfig,
+    pub storage: StorageConfig,
+    pub logging: LoggingConfig,
+    pub log_rotation: RotationPolicy,
+    pub auth: AuthConfig,
+    pub limits: LimitsConfig
+}
+
+impl RdConfig {
+
+    /// Merge and check the configuration of an instance.
+    ///
+    /// # Arguments
+    /// * `instance` - Instance whose instance.env is used.
+    /// * `flags` - Settings given as flags, as `(key, value)`.
+    ///
+    /// # Examples
+    /// ```
+    /// let config = RdConfig::load(instance, &cmd.flag_values())?;
+    /// ```
+    pub fn load(instance: &str, flags: &[(&str, String)]) -> Result<RdConfig, String> {
+        EffectiveConfig::resolve(instance, flags)?.typed()
+    }
+
+    /// The settings a running dataserver can reload.
+    pub fn reloadable(&self) -> ReloadableConfig {
+        ReloadableConfig {
+            session_lifetime_hours: self.auth.session_lifetime_hours,
+            limits: self.limits.clone()
+        }
+    }
+}
+
+/// Passthrough command for the config subcommand of `rd`
+#[derive(Debug, StructOpt)]
+#[structopt(name = "config")]
+pub struct ConfigCLI {
+
+    #[structopt(subcommand)]
+    pub cmd: ConfigCommand
+}
+
+/// Command arg options for showing the configuration.
+#[derive(Debug, StructOpt)]
+pub struct ShowConfigCLI {
+    #[structopt(
+        help = "Show every setting once all layers are merged, and where each came from.",
+        long
+    )]
+    effective: bool
+}
+
+/// Enum listing the various subcommands of the `config` command.
+#[derive(Debug, StructOpt)]
+pub enum ConfigCommand {
+
+    #[structopt(about = "Shows the settings of rd.toml.")]
+    Show(ShowConfigCLI)
+}
+
+/// Write a value the way it is written in rd.toml.
+fn format_value(setting: &Setting, value: &str) -> String {
+    if setting.secret {
+        return format!("\"{}\"", MASKED_SECRET);
+    }
+
+    match setting.kind {
+        Kind::Text => format!("{:?}", value),
+        Kind::Number | Kind::Boolean => value.to_string(),
+        Kind::List => format!("[{}]", value.split(',')
+                                             .map(|item| item.trim())
+                                             .filter(|item| !item.is_empty())
+                                             .map(|item| format!("{:?}", item))
+                                             .collect::<Vec<String>>()
+                                             .join(", "))
+    }
+}
+
+/// Print settings as rd.toml, one section at a time, with an optional
+/// comment after each value.
+///
+/// # Arguments
+/// * `entries` - Each setting with its value, if any, and comment.
+fn print_settings(entries: &[(&Setting, Option<&String>, Option<String>)]) {
+    let mut current_section = "";
+    for (setting, value, comment) in entries {
+        let mut parts = setting.key.splitn(2, '.');
+        let section = parts.next().unwrap_or_default();
+        let name = parts.next().unwrap_or_default();
+        if section != current_section {
+            if !current_section.is_empty() {
+                println!();
+            }
+            println!("[{}]", section);
+            current_section = section;
+        }
+
+        let line = match value {
+            Some(value) => format!("{} = {}", name, format_value(setting, value)),
+            None => format!("# {} is not set", name)
+        };
+        match comment {
+            Some(comment) => println!("{}  # {}", line, comment),
+            None => println!("{}", line)
+        }
+    }
+}
+
+/// Run a config command.
+///
+/// # Arguments
+/// * `command` - Command to run.
+/// * `logging` - Logging flags, which count as settings given as flags.
+///
+/// # Examples
+/// ```
+/// run_config_command(&command, &opts.logging);
+/// ```
+pub fn run_config_command(command: &ConfigCLI, logging: &LoggingCLI) {
+    match &command.cmd {
+
+        // Show the merged settings and where they came from
+        ConfigCommand::Show(cmd) if cmd.effective => {
+            let effective = match EffectiveConfig::resolve(&current_instance(), &logging.flag_values()) {
+                Ok(effective) => effective,
+                Err(msg) => {
+                    error!("{}", msg);
+                    return;
+                }
+            };
+
+            let entries = effective.settings.iter()
+                                            .map(|e| (&e.setting, e.value.as_ref(), Some(format!("{} ({})", e.source, e.setting.var))))
+                                            .collect::<Vec<_>>();
+            print_settings(&entries);
+
+            if let Err(msg) = effective.typed() {
+                error!("Configuration is invalid: {}", msg);
+            }
+        },
+
+        // Show rd.toml alone
+        ConfigCommand::Show(_) => {
+            let values = match read_config_file() {
+                Ok(values) => values,
+                Err(msg) => {
+                    error!("{}", msg);
+                    return;
+                }
+            };
+
+            let known = settings();
+            let entries = known.iter()
+                               .filter_map(|s| values.get(s.key).map(|value| (s, Some(value), None)))
+                               .collect::<Vec<_>>();
+            if entries.is_empty() {
+                info!("No settings in {}.", CONFIG_FILE);
+            }
+            print_settings(&entries);
+        }
+    }
+}
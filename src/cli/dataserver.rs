@@ -3,32 +3,113 @@
 * the dataserver for Rubber Duck
 */
 use structopt::StructOpt;
+use chrono::{
+    DateTime,
+    Utc
+};
 use std::{
+    collections::HashMap,
+    env,
+    io::{
+        Read,
+        Write
+    },
+    net::{
+        IpAddr,
+        TcpStream,
+        ToSocketAddrs
+    },
+    path::{
+        Path,
+        PathBuf
+    },
     process::{
         Command,
         Stdio
     },
-    fs::File,
-    os::unix::io::{
-        AsRawFd,
-        FromRawFd
+    fs::{
+        File,
+        Permissions,
+        set_permissions
+    },
+    os::unix::fs::PermissionsExt,
+    thread,
+    time::{
+        Duration,
+        Instant
     }
 };
 
 // Local imports
-use crate::dataserver;
+use crate::dataserver::{
+    self,
+    migrations::{
+        MIGRATIONS,
+        applied_migrations,
+        pending_migrations,
+        run_migrations
+    },
+    signals::DRAIN_TIMEOUT_SECS,
+    storage::{
+        open_database,
+        storage_backend
+    }
+};
+use crate::cli::config::{
+    RdConfig,
+    ServerConfig
+};
 use crate::cli::environment::{
+    DEFAULT_INSTANCE,
+    list_instances,
+    process_environment,
+    set_instance_environment,
+    validate_instance_name,
     write_server_pid_file,
     get_server_pid_file,
-    get_or_create_log_file,
-    remove_pid_file
+    get_or_create_server_output_file,
+    get_or_create_tls_dir,
+    read_server_pid,
+    remove_pid_file,
+    write_server_state,
+    read_server_state,
+    remove_server_state
 };
 
+/// How often `stop` checks whether the server has exited.
+const STOP_POLL_MILLIS: u64 = 100;
+
+/// Seconds `stop` waits beyond the drain timeout by default, so a
+/// server that drains in time is never killed.
+const STOP_TIMEOUT_MARGIN_SECS: u64 = 5;
+
+/// Longest `status` waits on the server's health check.
+const HEALTH_CHECK_TIMEOUT_SECS: u64 = 2;
+
+/// Names of the certificate and key created by `gen-cert`.
+const TLS_CERT_FILE: &str = "cert.pem";
+const TLS_KEY_FILE: &str = "key.pem";
+
+/// Oldest OpenSSL whose `req` takes `-addext`, which `gen-cert` needs
+/// to add subject alternative names.
+const MIN_OPENSSL_VERSION: (u32, u32, u32) = (1, 1, 1);
+
+/// Names a development certificate is valid for unless told otherwise.
+const DEFAULT_CERT_NAMES: &[&str] = &["localhost", "127.0.0.1"];
+
 /// Passthrough command for the dataserver subcommand of `rd`
 #[derive(Debug, StructOpt)]
 #[structopt(name = "dataserver")]
 pub struct DataserverCLI {
 
+    #[structopt(
+        default_value = DEFAULT_INSTANCE,
+        help = "Name of the dataserver instance to act on.",
+        long,
+        global = true
+    )]
+    pub instance: String,
+
     #[structopt(subcommand)]
     pub cmd: DataserverCommand
 }
@@ -37,33 +118,122 @@ pub struct DataserverCLI {
 #[structopt(about = "Start the Rubber Ducks dataserver.")]
 pub struct StartCLI {
     #[structopt(
-        default_value = "0.0.0.0",
-        help = "Host on which the server will be exposed.",
+        help = "Host on which the server will be exposed. Overrides server.host.",
         short,
         long
     )]
-    host: String,
+    host: Option<String>,
 
     #[structopt(
-        default_value = "5555",
-        help = "Port on which the server will be exposed.",
+        help = "Port on which the server will be exposed. Overrides server.port.",
         short,
         long
     )]
-    port: u16,
+    port: Option<u16>,
 
     #[structopt(
-        default_value = "3",
-        help = "Number of threads to spawn on start.",
+        help = "Number of threads to spawn on start. Overrides server.workers.",
         short,
         long
     )]
-    workers: u16
+    workers: Option<u16>,
+
+    #[structopt(
+        help = "Serve /metrics on this port instead of alongside the API. Overrides server.metrics_port.",
+        long
+    )]
+    metrics_port: Option<u16>,
+
+    #[structopt(
+        help = "Certificate chain in PEM to serve HTTPS with. Overrides server.tls_cert.",
+        long
+    )]
+    tls_cert: Option<String>,
+
+    #[structopt(
+        help = "Private key in PEM of the certificate. Overrides server.tls_key.",
+        long
+    )]
+    tls_key: Option<String>,
+
+    #[structopt(
+        help = "Launch even when database migrations are pending.",
+        long
+    )]
+    allow_pending_migrations: bool
 }
 
 #[derive(Debug, StructOpt)]
 #[structopt(about = "Stop the Rubber Ducks dataserver.")]
-pub struct StopCLI {}
+pub struct StopCLI {
+    #[structopt(
+        help = "Seconds to wait for the server to drain before killing it. Defaults to a few seconds more than limits.drain_timeout_secs.",
+        short,
+        long
+    )]
+    timeout: Option<u64>
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(about = "Restart the Rubber Ducks dataserver with the options it was started with.")]
+pub struct RestartCLI {
+    #[structopt(
+        help = "Seconds to wait for the server to drain before killing it. Defaults to a few seconds more than limits.drain_timeout_secs.",
+        short,
+        long
+    )]
+    timeout: Option<u64>,
+
+    #[structopt(
+        help = "Launch even when database migrations are pending.",
+        long
+    )]
+    allow_pending_migrations: bool
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(about = "Make the running Rubber Ducks dataserver reload the settings it reads while serving.")]
+pub struct ReloadCLI {}
+
+#[derive(Debug, StructOpt)]
+#[structopt(about = "Report whether the Rubber Ducks dataserver is running and healthy.")]
+pub struct StatusCLI {}
+
+#[derive(Debug, StructOpt)]
+#[structopt(about = "List dataserver instances and whether each is running.")]
+pub struct ListCLI {}
+
+#[derive(Debug, StructOpt)]
+#[structopt(about = "Create a self-signed certificate for serving HTTPS during development.")]
+pub struct GenCertCLI {
+    #[structopt(
+        help = "Host name or IP address the certificate is valid for. May be given more than once.",
+        long = "name",
+        number_of_values = 1
+    )]
+    names: Vec<String>,
+
+    #[structopt(
+        default_value = "365",
+        help = "Number of days the certificate stays valid.",
+        long
+    )]
+    days: u32,
+
+    #[structopt(
+        help = "Replace an existing certificate.",
+        long
+    )]
+    force: bool
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(about = "Apply pending database migrations.")]
+pub struct MigrateCLI {}
+
+#[derive(Debug, StructOpt)]
+#[structopt(about = "List database migrations and whether they have been applied.")]
+pub struct MigrateStatusCLI {}
 
 /// Listing all options for the the dataserver subcommand.
 #[derive(Debug, StructOpt)]
@@ -76,7 +246,529 @@ pub enum DataserverCommand {
     RawStart(StartCLI),
 
     // Stop the server
-    Stop(StopCLI)
+    Stop(StopCLI),
+
+    // Stop the server and start it again
+    Restart(RestartCLI),
+
+    // Reload the server's configuration
+    Reload(ReloadCLI),
+
+    // Check on the server
+    Status(StatusCLI),
+
+    // Show every instance
+    List(ListCLI),
+
+    // Create a development certificate
+    GenCert(GenCertCLI),
+
+    // Apply pending migrations
+    Migrate(MigrateCLI),
+
+    // Show which migrations are applied
+    MigrateStatus(MigrateStatusCLI)
+}
+
+impl StartCLI {
+
+    /// The server settings given as flags, keyed by their name in rd.toml.
+    pub fn flag_values(&self) -> Vec<(&'static str, String)> {
+        let mut flags = Vec::new();
+        if let Some(host) = &self.host {
+            flags.push(("server.host", host.clone()));
+        }
+        if let Some(port) = self.port {
+            flags.push(("server.port", port.to_string()));
+        }
+        if let Some(workers) = self.workers {
+            flags.push(("server.workers", workers.to_string()));
+        }
+        if let Some(metrics_port) = self.metrics_port {
+            flags.push(("server.metrics_port", metrics_port.to_string()));
+        }
+        if let Some(tls_cert) = &self.tls_cert {
+            flags.push(("server.tls_cert", tls_cert.clone()));
+        }
+        if let Some(tls_key) = &self.tls_key {
+            flags.push(("server.tls_key", tls_key.clone()));
+        }
+        flags
+    }
+}
+
+/// How a running server was launched, persisted next to its PID file.
+#[derive(Debug)]
+pub struct ServerState {
+    pub host: String,
+    pub port: u16,
+    pub workers: u16,
+    pub metrics_port: Option<u16>,
+    pub tls_cert: Option<String>,
+    pub tls_key: Option<String>,
+    pub started_at: DateTime<Utc>
+}
+
+impl ServerState {
+
+    /// Record the launch details of a newly spawned server.
+    ///
+    /// # Arguments
+    /// * `instance` - Name of the instance the server belongs to.
+    ///
+    /// # Examples
+    /// ```
+    /// state.save(instance)?;
+    /// ```
+    pub fn save(&self, instance: &str) -> Result<(), String> {
+        let mut state = HashMap::new();
+        state.insert(String::from("host"), self.host.clone());
+        state.insert(String::from("port"), self.port.to_string());
+        state.insert(String::from("workers"), self.workers.to_string());
+        if let Some(metrics_port) = self.metrics_port {
+            state.insert(String::from("metrics_port"), metrics_port.to_string());
+        }
+        if let (Some(tls_cert), Some(tls_key)) = (&self.tls_cert, &self.tls_key) {
+            state.insert(String::from("tls_cert"), tls_cert.clone());
+            state.insert(String::from("tls_key"), tls_key.clone());
+        }
+        state.insert(String::from("started_at"), self.started_at.to_rfc3339());
+        write_server_state(instance, &state)
+    }
+
+    /// Read the launch details of the running server.
+    ///
+    /// # Arguments
+    /// * `instance` - Name of the instance the server belongs to.
+    ///
+    /// # Examples
+    /// ```
+    /// let state = ServerState::load(instance)?;
+    /// ```
+    pub fn load(instance: &str) -> Result<ServerState, String> {
+        let state = read_server_state(instance)?;
+        let field = |key: &str| state.get(key).ok_or_else(|| format!("Server state is missing {}.", key));
+
+        Ok(ServerState {
+            host: field("host")?.clone(),
+            port: field("port")?.parse().map_err(|_| String::from("Server state has an invalid port."))?,
+            workers: field("workers")?.parse().map_err(|_| String::from("Server state has an invalid worker count."))?,
+            metrics_port: match state.get("metrics_port") {
+                Some(metrics_port) => Some(metrics_port.parse().map_err(|_| String::from("Server state has an invalid metrics port."))?),
+                None => None
+            },
+            tls_cert: state.get("tls_cert").cloned(),
+            tls_key: state.get("tls_key").cloned(),
+            started_at: DateTime::parse_from_rfc3339(field("started_at")?)
+                            .map_err(|_| String::from("Server state has an invalid start time."))?
+                            .with_timezone(&Utc)
+        })
+    }
+}
+
+/// Check whether the dataserver may launch against the current
+/// database schema, logging why not if it may not.
+///
+/// # Arguments
+/// * `allow_pending` - Launch even when migrations are pending.
+fn migrations_allow_launch(allow_pending: bool) -> bool {
+    // Only the SQLite backend has a schema to migrate
+    if storage_backend() != "sqlite" {
+        return true;
+    }
+
+    let pending = match open_database().and_then(|conn| pending_migrations(&conn)) {
+        Ok(pending) => pending,
+        Err(msg) => {
+            error!("Failed to check database migrations: {}", msg);
+            return false;
+        }
+    };
+
+    if pending.is_empty() {
+        true
+    } else if allow_pending {
+        warn!("Launching with {} pending migration(s).", pending.len());
+        true
+    } else {
+        error!("Cannot start server: {} migration(s) pending. Run `rd dataserver migrate` or pass --allow-pending-migrations.", pending.len());
+        false
+    }
+}
+
+/// Whether a process with the given PID is still running.
+///
+/// # Arguments
+/// * `pid` - ID of the process.
+fn process_alive(pid: u32) -> bool {
+    // Signal 0 only checks that the process exists
+    unsafe { libc::kill(pid as libc::pid_t, 0) == 0 }
+}
+
+/// Whether a process with the given PID is a running `rd` process,
+/// rather than an unrelated process that reused a stale PID.
+///
+/// # Arguments
+/// * `pid` - ID of the process.
+fn is_rd_process(pid: u32) -> bool {
+    if !process_alive(pid) {
+        return false;
+    }
+
+    let output = match Command::new("ps").arg("-p").arg(pid.to_string()).arg("-o").arg("comm=").output() {
+        Ok(output) => output,
+        Err(_) => return false
+    };
+    let command = String::from_utf8_lossy(&output.stdout);
+    Path::new(command.trim()).file_name().map_or(false, |name| name == "rd")
+}
+
+/// Get the PID of an instance's running server, if any. PID files left
+/// behind by a server that is no longer running are removed along the way.
+///
+/// # Arguments
+/// * `instance` - Name of the instance.
+///
+/// # Examples
+/// ```
+/// if running_server_pid(instance)?.is_none() {
+///     // Safe to start
+/// }
+/// ```
+fn running_server_pid(instance: &str) -> Result<Option<u32>, String> {
+    if get_server_pid_file(instance).is_err() {
+        return Ok(None);
+    }
+
+    match read_server_pid(instance) {
+        Ok(pid) if is_rd_process(pid) => Ok(Some(pid)),
+        Ok(pid) => {
+            warn!("Removing stale PID file of server process {}.", pid);
+            remove_pid_file(instance)?;
+            remove_server_state(instance)?;
+            Ok(None)
+        },
+        Err(msg) => {
+            warn!("Removing unreadable PID file: {}", msg);
+            remove_pid_file(instance)?;
+            remove_server_state(instance)?;
+            Ok(None)
+        }
+    }
+}
+
+/// Ask the server whether it is ready over HTTP.
+///
+/// # Arguments
+/// * `host` - Host the server listens on.
+/// * `port` - Port the server listens on.
+///
+/// # Examples
+/// ```
+/// if probe_health("0.0.0.0", 5555).is_ok() {
+///     // Server is answering
+/// }
+/// ```
+fn probe_health(host: &str, port: u16) -> Result<(), String> {
+    // A server listening on every interface is reachable locally
+    let host = match host {
+        "0.0.0.0" => "127.0.0.1",
+        "::" => "::1",
+        other => other
+    };
+    let timeout = Duration::from_secs(HEALTH_CHECK_TIMEOUT_SECS);
+
+    let address = (host, port).to_socket_addrs()
+                              .map_err(|err| err.to_string())?
+                              .next()
+                              .ok_or_else(|| format!("Could not resolve {}.", host))?;
+    let mut stream = TcpStream::connect_timeout(&address, timeout).map_err(|err| err.to_string())?;
+    stream.set_read_timeout(Some(timeout)).map_err(|err| err.to_string())?;
+    stream.write_all(format!("GET /health/ready HTTP/1.0\r\nHost: {}\r\n\r\n", host).as_bytes()).map_err(|err| err.to_string())?;
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).map_err(|err| err.to_string())?;
+    let status_line = response.lines().next().unwrap_or("");
+    if status_line.split_whitespace().nth(1) == Some("200") {
+        Ok(())
+    } else {
+        Err(format!("Unexpected response \"{}\".", status_line))
+    }
+}
+
+/// Format how long the server has been up, such as `2d 3h 4m 5s`.
+fn format_uptime(started_at: DateTime<Utc>) -> String {
+    let seconds = (Utc::now() - started_at).num_seconds().max(0);
+    format!("{}d {}h {}m {}s", seconds / 86400, seconds % 86400 / 3600, seconds % 3600 / 60, seconds % 60)
+}
+
+/// Wait for a process to exit, returning whether it did within the timeout.
+///
+/// # Arguments
+/// * `pid` - ID of the process.
+/// * `timeout` - Longest to wait.
+fn wait_for_exit(pid: u32, timeout: Duration) -> bool {
+    let deadline = Instant::now() + timeout;
+    while process_alive(pid) {
+        if Instant::now() >= deadline {
+            return false;
+        }
+        thread::sleep(Duration::from_millis(STOP_POLL_MILLIS));
+    }
+    true
+}
+
+/// Seconds to give the server of an instance to drain before killing
+/// it. Unless given, this is a little longer than the server itself
+/// waits for requests in flight.
+///
+/// # Arguments
+/// * `instance` - Instance whose server is stopped.
+/// * `timeout` - Timeout given on the command line, if any.
+///
+/// # Examples
+/// ```
+/// stop_server(pid, stop_timeout(instance, cmd.timeout))?;
+/// ```
+fn stop_timeout(instance: &str, timeout: Option<u64>) -> u64 {
+    let drain_timeout = match RdConfig::load(instance, &[]) {
+        Ok(config) => config.limits.drain_timeout_secs,
+        Err(msg) => {
+            warn!("Assuming a drain timeout of {}s: {}", DRAIN_TIMEOUT_SECS, msg);
+            DRAIN_TIMEOUT_SECS
+        }
+    };
+
+    match timeout {
+        Some(timeout) => {
+            if timeout <= drain_timeout {
+                warn!("Timeout of {}s is not above limits.drain_timeout_secs ({}s), so the server may be killed while draining.", timeout, drain_timeout);
+            }
+            timeout
+        },
+        None => drain_timeout + STOP_TIMEOUT_MARGIN_SECS
+    }
+}
+
+/// Stop the server process, asking it to drain with SIGTERM and
+/// only resorting to SIGKILL once the grace timeout has passed.
+///
+/// # Arguments
+/// * `pid` - ID of the server process.
+/// * `timeout` - Seconds the server is given to drain.
+///
+/// # Examples
+/// ```
+/// stop_server(pid, 30)?;
+/// ```
+fn stop_server(pid: u32, timeout: u64) -> Result<(), String> {
+    if !process_alive(pid) {
+        warn!("Server process {} is not running.", pid);
+        return Ok(());
+    }
+
+    info!("Sending SIGTERM to server process {}...", pid);
+    if unsafe { libc::kill(pid as libc::pid_t, libc::SIGTERM) } != 0 {
+        return Err(format!("Failed to signal server process {}: {}", pid, std::io::Error::last_os_error()));
+    }
+
+    if wait_for_exit(pid, Duration::from_secs(timeout)) {
+        info!("Server process {} stopped.", pid);
+        return Ok(());
+    }
+
+    warn!("Server process {} did not stop within {}s, sending SIGKILL...", pid, timeout);
+    if unsafe { libc::kill(pid as libc::pid_t, libc::SIGKILL) } != 0 {
+        return Err(format!("Failed to kill server process {}: {}", pid, std::io::Error::last_os_error()));
+    }
+
+    if wait_for_exit(pid, Duration::from_secs(timeout)) {
+        info!("Server process {} killed.", pid);
+        Ok(())
+    } else {
+        Err(format!("Server process {} is still running.", pid))
+    }
+}
+
+/// Spawn the server as a separate process, recording its PID
+/// and how it was launched.
+///
+/// # Arguments
+/// * `instance` - Name of the instance the server belongs to.
+/// * `server` - Where and how the server listens.
+/// * `allow_pending_migrations` - Launch even when migrations are pending.
+///
+/// # Examples
+/// ```
+/// spawn_server(instance, &config.server, false);
+/// ```
+fn spawn_server(instance: &str, server: &ServerConfig, allow_pending_migrations: bool) {
+    if migrations_allow_launch(allow_pending_migrations) {
+        // The server writes its own log file, so its output only
+        // catches anything printed outside the logger
+        let output_file_path = get_or_create_server_output_file(instance).expect("Failed to create server output file.");
+        let output_file = File::with_options().append(true).open(output_file_path).expect("Failed to open server output file.");
+        let out = output_file.try_clone().map(Stdio::from).expect("Failed to open server output file.");
+        let err = Stdio::from(output_file);
+
+        // Spawn the process. It starts from the environment this
+        // command was started in and loads the env files itself,
+        // so it can reload them later.
+        let mut server_command = Command::new("rd");
+        server_command.env_clear()
+                      .envs(process_environment())
+                      .arg("--log-output")
+                      .arg("file")
+                      .arg("dataserver")
+                      .arg("--instance")
+                      .arg(instance)
+                      .arg("raw-start")
+                      .arg("-h")
+                      .arg(format!("{}", server.host))
+                      .arg("-p")
+                      .arg(format!("{}", server.port))
+                      .arg("-w")
+                      .arg(format!("{}", server.workers))
+                      .stdout(out)
+                      .stderr(err);
+        if let Some(metrics_port) = server.metrics_port {
+            server_command.arg("--metrics-port").arg(format!("{}", metrics_port));
+        }
+        if let (Some(tls_cert), Some(tls_key)) = (&server.tls_cert, &server.tls_key) {
+            server_command.arg("--tls-cert").arg(tls_cert).arg("--tls-key").arg(tls_key);
+        }
+        if allow_pending_migrations {
+            server_command.arg("--allow-pending-migrations");
+        }
+        let mut server_process = server_command.spawn().expect("Failed to start server process.");
+        
+        // Write the server process ID
+        if write_server_pid_file(instance, server_process.id()).is_err() {
+            server_process.kill().expect("Failed to kill process on failure to write new process ID.");
+            panic!("Failed to write process ID to file.");
+        }
+
+        // Remember how it was launched for status checks and restarts
+        let state = ServerState {
+            host: server.host.clone(),
+            port: server.port,
+            workers: server.workers,
+            metrics_port: server.metrics_port,
+            tls_cert: server.tls_cert.clone(),
+            tls_key: server.tls_key.clone(),
+            started_at: Utc::now()
+        };
+        if let Err(msg) = state.save(instance) {
+            warn!("Failed to record server state: {}", msg);
+        }
+
+        info!("Spawned server process with PID {}.", server_process.id());
+    }
+}
+
+/// Whether the output of `openssl version` names an OpenSSL recent
+/// enough to create certificates with. LibreSSL and older OpenSSL
+/// releases do not take `-addext`.
+///
+/// # Arguments
+/// * `version` - Output of `openssl version`.
+///
+/// # Examples
+/// ```
+/// assert!(supports_addext("OpenSSL 1.1.1k  25 Mar 2021"));
+/// ```
+fn supports_addext(version: &str) -> bool {
+    let mut words = version.split_whitespace();
+    if words.next() != Some("OpenSSL") {
+        return false;
+    }
+
+    // Patch releases carry a letter, as in 1.1.1k
+    let numbers = words.next()
+                       .unwrap_or("")
+                       .split('.')
+                       .map(|part| part.trim_end_matches(|c: char| c.is_ascii_alphabetic()).parse::<u32>())
+                       .collect::<Result<Vec<u32>, _>>();
+    match numbers.as_ref().map(Vec::as_slice) {
+        Ok([major, minor, patch]) => (*major, *minor, *patch) >= MIN_OPENSSL_VERSION,
+        _ => false
+    }
+}
+
+/// Create a self-signed certificate and its key in the instance's
+/// home with openssl, returning their paths. The key is RSA, since
+/// that is what the server can load.
+///
+/// # Arguments
+/// * `instance` - Name of the instance the certificate is for.
+/// * `cmd` - Options to create the certificate with.
+///
+/// # Examples
+/// ```
+/// let (cert_path, key_path) = generate_certificate(instance, &cmd)?;
+/// ```
+fn generate_certificate(instance: &str, cmd: &GenCertCLI) -> Result<(PathBuf, PathBuf), String> {
+    let tls_dir = get_or_create_tls_dir(instance)?;
+    let cert_path = tls_dir.join(TLS_CERT_FILE);
+    let key_path = tls_dir.join(TLS_KEY_FILE);
+    if !cmd.force && (cert_path.exists() || key_path.exists()) {
+        return Err(format!("A certificate already exists in {}. Pass --force to replace it.", tls_dir.display()));
+    }
+
+    let names = if cmd.names.is_empty() {
+        DEFAULT_CERT_NAMES.iter().map(|name| name.to_string()).collect::<Vec<String>>()
+    } else {
+        cmd.names.clone()
+    };
+    let alt_names = names.iter()
+                         .map(|name| match name.parse::<IpAddr>() {
+                             Ok(_) => format!("IP:{}", name),
+                             Err(_) => format!("DNS:{}", name)
+                         })
+                         .collect::<Vec<String>>()
+                         .join(",");
+
+    let version = Command::new("openssl")
+                          .arg("version")
+                          .output()
+                          .map_err(|err| format!("Failed to run openssl, is it installed? {}", err))?;
+    let version = String::from_utf8_lossy(&version.stdout).trim().to_string();
+    if !supports_addext(&version) {
+        return Err(format!(
+            "gen-cert needs OpenSSL {}.{}.{} or newer, but openssl is {}.",
+            MIN_OPENSSL_VERSION.0,
+            MIN_OPENSSL_VERSION.1,
+            MIN_OPENSSL_VERSION.2,
+            if version.is_empty() { "of an unknown version" } else { version.as_str() }
+        ));
+    }
+
+    let output = Command::new("openssl")
+                         .arg("req")
+                         .arg("-x509")
+                         .arg("-newkey")
+                         .arg("rsa:2048")
+                         .arg("-nodes")
+                         .arg("-sha256")
+                         .arg("-days")
+                         .arg(cmd.days.to_string())
+                         .arg("-subj")
+                         .arg(format!("/CN={}", names[0]))
+                         .arg("-addext")
+                         .arg(format!("subjectAltName={}", alt_names))
+                         .arg("-keyout")
+                         .arg(&key_path)
+                         .arg("-out")
+                         .arg(&cert_path)
+                         .output()
+                         .map_err(|err| format!("Failed to run openssl: {}", err))?;
+    if !output.status.success() {
+        return Err(format!("openssl failed: {}", String::from_utf8_lossy(&output.stderr).trim()));
+    }
+
+    // Only the owner should be able to read the key
+    set_permissions(&key_path, Permissions::from_mode(0o600)).map_err(|err| err.to_string())?;
+    Ok((cert_path, key_path))
 }
 
 /// Run a dataserver command.
@@ -89,70 +781,251 @@ pub enum DataserverCommand {
 /// run_dataserver_command(&command);
 /// ```
 pub fn run_dataserver_command(command: &DataserverCLI) {
+    let instance = command.instance.as_str();
+    if let Err(msg) = validate_instance_name(instance) {
+        error!("{}", msg);
+        return;
+    }
+
+    // Everything after this, including migrations and the server
+    // itself, works against the instance's own files and variables
+    env::set_var("RD_INSTANCE", instance);
+    if let Err(msg) = set_instance_environment(instance) {
+        error!("Failed to load environment of instance {}: {}", instance, msg);
+        return;
+    }
+
     match &command.cmd {
 
         // Start as separate process
         DataserverCommand::Start(cmd) => {
-            info!("Spawning server process at {}:{}...", cmd.host, cmd.port);
-
-            // We only want to spawn a process if there's not already a running process
-            if get_server_pid_file().is_ok() {
-                error!("Cannot start server: process already exists.");
-            } else {
-                // Get the log file to out to
-                let log_file_path = get_or_create_log_file().expect("Failed to create log file.");
-                let log_file = File::with_options().write(true).open(log_file_path).expect("Failed to open log file.");
-                let log_file_fd = log_file.as_raw_fd();
-                let out = unsafe {Stdio::from_raw_fd(log_file_fd)};
-
-                // Spawn the process
-                let mut server_process = Command::new("rd")
-                                                .arg("dataserver")
-                                                .arg("raw-start")
-                                                .arg("-h")
-                                                .arg(format!("{}", cmd.host))
-                                                .arg("-p")
-                                                .arg(format!("{}", cmd.port))
-                                                .arg("-w")
-                                                .arg(format!("{}", cmd.workers))
-                                                .stdout(out)
-                                                .spawn()
-                                                .expect("Failed to start server process.");
-                
-                // Write the server process ID
-                if write_server_pid_file(server_process.id()).is_err() {
-                    server_process.kill().expect("Failed to kill process on failure to write new process ID.");
-                    panic!("Failed to write process ID to file.");
+            let config = match RdConfig::load(instance, &cmd.flag_values()) {
+                Ok(config) => config,
+                Err(msg) => {
+                    error!("Invalid configuration: {}", msg);
+                    return;
                 }
+            };
+            info!("Spawning server process of instance {} at {}:{}...", instance, config.server.host, config.server.port);
 
-                info!("Spawned server process with PID {}.", server_process.id());
+            // We only want to spawn a process if there's not already a running process
+            match running_server_pid(instance) {
+                Ok(Some(pid)) => error!("Cannot start server: process {} already exists.", pid),
+                Ok(None) => spawn_server(instance, &config.server, cmd.allow_pending_migrations),
+                Err(msg) => error!("{}", msg)
             }
         },
 
         // Start server and wait
         DataserverCommand::RawStart(cmd) => {
-            info!("Starting server at host {}:{}...", cmd.host, cmd.port);
-            dataserver::start_dataserver(&cmd.host, cmd.port, cmd.workers);
+            let config = match RdConfig::load(instance, &cmd.flag_values()) {
+                Ok(config) => config,
+                Err(msg) => {
+                    error!("Invalid configuration: {}", msg);
+                    return;
+                }
+            };
+            if migrations_allow_launch(cmd.allow_pending_migrations) {
+                info!("Starting server at host {}:{}...", config.server.host, config.server.port);
+                dataserver::start_dataserver(instance, &config, cmd.flag_values());
+            }
         },
 
         // Stop the server
-        DataserverCommand::Stop{..} => {
-            // Remove the known PID file
-            match remove_pid_file() {
-                Ok(pid) => {
-                    let mut stop_command = Command::new("kill")
-                                            .arg("-9")
-                                            .arg(format!("{}", pid))
-                                            .spawn()
-                                            .expect("Failed to run kill command.");
-                    let exit_code = stop_command.wait().expect("Failed to wait process exit.");
-                    info!("{}", exit_code);
-                    
+        DataserverCommand::Stop(cmd) => {
+            // Only forget the PID once the process is gone
+            match running_server_pid(instance) {
+                Ok(Some(pid)) => {
+                    if let Err(msg) = stop_server(pid, stop_timeout(instance, cmd.timeout)).and_then(|_| remove_pid_file(instance)).and_then(|_| remove_server_state(instance)) {
+                        error!("{}", msg);
+                    }
+                },
+                Ok(None) => error!("Server is not running."),
+                Err(msg) => error!("{}", msg)
+            }
+        },
+
+        // Stop the server and start it again
+        DataserverCommand::Restart(cmd) => {
+            let pid = match running_server_pid(instance) {
+                Ok(Some(pid)) => pid,
+                Ok(None) => {
+                    error!("Server is not running. Use `rd dataserver start` instead.");
+                    return;
                 },
                 Err(msg) => {
                     error!("{}", msg);
+                    return;
+                }
+            };
+
+            // Read the launch options before stopping removes them
+            let state = match ServerState::load(instance) {
+                Ok(state) => state,
+                Err(msg) => {
+                    error!("Cannot restart server: {}", msg);
+                    return;
                 }
+            };
+
+            if let Err(msg) = stop_server(pid, stop_timeout(instance, cmd.timeout)).and_then(|_| remove_pid_file(instance)).and_then(|_| remove_server_state(instance)) {
+                error!("{}", msg);
+                return;
             }
+
+            info!("Respawning server process at {}:{}...", state.host, state.port);
+            let server = ServerConfig {
+                host: state.host,
+                port: state.port,
+                workers: state.workers,
+                metrics_port: state.metrics_port,
+                tls_cert: state.tls_cert,
+                tls_key: state.tls_key
+            };
+            spawn_server(instance, &server, cmd.allow_pending_migrations);
+        },
+
+        // Reload the server's configuration
+        DataserverCommand::Reload(_) => {
+            match running_server_pid(instance) {
+                Ok(Some(pid)) => {
+                    if unsafe { libc::kill(pid as libc::pid_t, libc::SIGHUP) } == 0 {
+                        info!("Asked server process {} to reload its configuration.", pid);
+                    } else {
+                        error!("Failed to signal server process {}: {}", pid, std::io::Error::last_os_error());
+                    }
+                },
+                Ok(None) => error!("Server is not running."),
+                Err(msg) => error!("{}", msg)
+            }
+        },
+
+        // Check on the server
+        DataserverCommand::Status(_) => {
+            let pid = match running_server_pid(instance) {
+                Ok(Some(pid)) => pid,
+                Ok(None) => {
+                    info!("Server is not running.");
+                    return;
+                },
+                Err(msg) => {
+                    error!("{}", msg);
+                    return;
+                }
+            };
+            info!("Server is running with PID {}.", pid);
+
+            match ServerState::load(instance) {
+                Ok(state) => {
+                    info!("Listening on {}:{} with {} worker(s).", state.host, state.port, state.workers);
+                    if let Some(metrics_port) = state.metrics_port {
+                        info!("Serving metrics on {}:{}.", state.host, metrics_port);
+                    }
+                    if let Some(tls_cert) = &state.tls_cert {
+                        info!("Serving HTTPS with certificate {}.", tls_cert);
+                    }
+                    info!("Up for {} since {}.", format_uptime(state.started_at), state.started_at);
+
+                    // The probe only speaks plain HTTP
+                    if state.tls_cert.is_some() {
+                        info!("Readiness check skipped: server only answers HTTPS.");
+                    } else {
+                        match probe_health(&state.host, state.port) {
+                            Ok(()) => info!("Server is ready."),
+                            Err(msg) => warn!("Readiness check failed: {}", msg)
+                        }
+                    }
+                },
+                Err(msg) => warn!("Launch details unavailable: {}", msg)
+            }
+        },
+
+        // Show every instance
+        DataserverCommand::List(_) => {
+            let instances = match list_instances() {
+                Ok(instances) => instances,
+                Err(msg) => {
+                    error!("{}", msg);
+                    return;
+                }
+            };
+
+            instances.iter().for_each(|name| {
+                match running_server_pid(name) {
+                    Ok(Some(pid)) => match ServerState::load(name) {
+                        Ok(state) => info!("{}: running with PID {} on {}:{}", name, pid, state.host, state.port),
+                        Err(_) => info!("{}: running with PID {}", name, pid)
+                    },
+                    Ok(None) => info!("{}: stopped", name),
+                    Err(msg) => error!("{}: {}", name, msg)
+                }
+            });
+        },
+
+        // Create a development certificate
+        DataserverCommand::GenCert(cmd) => {
+            match generate_certificate(instance, cmd) {
+                Ok((cert_path, key_path)) => {
+                    info!("Created self-signed certificate {} with key {}.", cert_path.display(), key_path.display());
+                    info!(
+                        "Serve HTTPS with --tls-cert {} --tls-key {}, or set server.tls_cert and server.tls_key in rd.toml.",
+                        cert_path.display(),
+                        key_path.display()
+                    );
+                },
+                Err(msg) => error!("{}", msg)
+            }
+        },
+
+        // Apply pending migrations
+        DataserverCommand::Migrate(_) => {
+            match open_database().and_then(|mut conn| run_migrations(&mut conn)) {
+                Ok(applied) if applied.is_empty() => info!("Database is already up to date."),
+                Ok(applied) => info!("Applied {} migration(s).", applied.len()),
+                Err(msg) => error!("{}", msg)
+            }
+        },
+
+        // Show which migrations are applied
+        DataserverCommand::MigrateStatus(_) => {
+            let applied = match open_database().and_then(|conn| applied_migrations(&conn)) {
+                Ok(applied) => applied,
+                Err(msg) => {
+                    error!("{}", msg);
+                    return;
+                }
+            };
+
+            MIGRATIONS.iter().for_each(|migration| {
+                match applied.iter().find(|a| a.version == migration.version) {
+                    Some(a) => info!("{:04} {} applied at {}", migration.version, migration.name, a.applied_at),
+                    None => info!("{:04} {} pending", migration.version, migration.name)
+                }
+            });
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recent_openssl_supports_addext() {
+        assert!(supports_addext("OpenSSL 1.1.1k  25 Mar 2021"));
+        assert!(supports_addext("OpenSSL 3.0.2 15 Mar 2022 (Library: OpenSSL 3.0.2 15 Mar 2022)"));
+    }
+
+    #[test]
+    fn old_openssl_does_not_support_addext() {
+        assert!(!supports_addext("OpenSSL 1.1.0l  10 Sep 2019"));
+        assert!(!supports_addext("OpenSSL 1.0.2u  20 Dec 2019"));
+    }
+
+    #[test]
+    fn libressl_and_garbage_do_not_support_addext() {
+        assert!(!supports_addext("LibreSSL 2.8.3"));
+        assert!(!supports_addext(""));
+        assert!(!supports_addext("OpenSSL"));
+    }
+}
@@ -0,0 +1,607 @@
+/**
+* This file contains a parser for env files in the format
+* understood by dotenv: comments, `export` prefixes, quoted
+* values spanning several lines and `${VAR}` interpolation.
+*/
+use std::{
+    collections::HashMap,
+    fmt
+};
+
+// Local imports
+use crate::cli::secrets::is_secret;
+
+/// A variable defined by an env file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnvEntry {
+    pub key: String,
+    pub value: String,
+
+    /// Value as written in the file, quotes and all, so that it can
+    /// be copied to another file with its references intact.
+    pub written: String,
+
+    /// Lines the definition starts and ends on, counting from 1.
+    pub line: usize,
+    pub end_line: usize,
+
+    /// Whether the definition has an `export` prefix.
+    pub exported: bool,
+
+    /// Comment following the value on its last line.
+    pub comment: Option<String>,
+
+    /// Variables the value refers to that are not defined anywhere.
+    pub undefined: Vec<String>
+}
+
+/// A line of an env file that could not be read.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub line: usize,
+    pub message: String
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// Whether a character may appear in a variable name.
+fn is_name_char(c: char) -> bool {
+    c == '_' || c.is_ascii_alphanumeric()
+}
+
+/// Whether a value can be written without quotes and read back unchanged.
+fn is_plain_value(value: &str) -> bool {
+    value.chars().all(|c| c.is_ascii_alphanumeric() || "_-./:@,+=%".contains(c))
+}
+
+/// Quote a value so that it reads back unchanged, leaving
+/// it bare where that is already the case.
+///
+/// # Arguments
+/// * `value` - Value to quote.
+///
+/// # Examples
+/// ```
+/// let line = format!("{}={}\n", key, quote_value(&value));
+/// ```
+pub fn quote_value(value: &str) -> String {
+    if is_plain_value(value) {
+        value.to_string()
+    } else if !value.contains('\'') {
+        // Nothing inside single quotes is special
+        format!("'{}'", value)
+    } else {
+        let escaped = value.replace('\\', "\\\\")
+                           .replace('"', "\\\"")
+                           .replace('$', "\\$")
+                           .replace('\n', "\\n");
+        format!("\"{}\"", escaped)
+    }
+}
+
+/// Look up a variable a value refers to. Secrets are refused, since
+/// copying one into another variable would leave it unprotected.
+///
+/// # Arguments
+/// * `resolve` - Looks up the value of a variable, as stored.
+/// * `name` - Name of the variable.
+fn resolve_reference(resolve: &dyn Fn(&str) -> Option<String>, name: &str) -> Result<Option<String>, String> {
+    match resolve(name) {
+        Some(found) if is_secret(&found) => Err(format!("Cannot refer to secret {}, as that would store it unencrypted.", name)),
+        found => Ok(found)
+    }
+}
+
+/// Replace references to other variables in a value, and
+/// escapes if asked to.
+///
+/// # Arguments
+/// * `raw` - Value as written in the file, without quotes.
+/// * `escapes` - Whether backslash escapes such as `\n` apply.
+/// * `resolve` - Looks up the value of a variable.
+/// * `undefined` - Collects the variables that could not be looked up.
+fn expand(raw: &str, escapes: bool, resolve: &dyn Fn(&str) -> Option<String>, undefined: &mut Vec<String>) -> Result<String, String> {
+    let mut value = String::new();
+    let mut chars = raw.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.peek() {
+                Some('$') => {
+                    value.push('$');
+                    chars.next();
+                },
+                Some(&escaped) if escapes => {
+                    match escaped {
+                        'n' => value.push('\n'),
+                        'r' => value.push('\r'),
+                        't' => value.push('\t'),
+                        '"' | '\\' => value.push(escaped),
+                        _ => {
+                            // Unknown escapes are kept as written
+                            value.push('\\');
+                            value.push(escaped);
+                        }
+                    }
+                    chars.next();
+                },
+                _ => value.push('\\')
+            },
+            '$' if chars.peek() == Some(&'{') => {
+                chars.next();
+                let mut reference = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => reference.push(c),
+                        None => return Err(String::from("Missing '}' to close '${'."))
+                    }
+                }
+
+                // ${NAME:-default} applies when unset or empty, ${NAME-default} only when unset
+                let (name, default, or_empty) = match reference.find(|c| !is_name_char(c)) {
+                    None => (reference.as_str(), None, false),
+                    Some(i) if reference[i..].starts_with(":-") => (&reference[..i], Some(&reference[i + 2..]), true),
+                    Some(i) if reference[i..].starts_with('-') => (&reference[..i], Some(&reference[i + 1..]), false),
+                    Some(_) => return Err(format!("Invalid reference '${{{}}}'.", reference))
+                };
+                if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit()) {
+                    return Err(format!("Invalid reference '${{{}}}'.", reference));
+                }
+
+                match (resolve_reference(resolve, name)?, default) {
+                    (Some(found), Some(default)) if or_empty && found.is_empty() => value.push_str(default),
+                    (Some(found), _) => value.push_str(&found),
+                    (None, Some(default)) => value.push_str(default),
+                    (None, None) => undefined.push(name.to_string())
+                }
+            },
+            '$' if chars.peek().map_or(false, |&c| c == '_' || c.is_ascii_alphabetic()) => {
+                let mut name = String::new();
+                while let Some(&c) = chars.peek() {
+                    if !is_name_char(c) {
+                        break;
+                    }
+                    name.push(c);
+                    chars.next();
+                }
+                match resolve_reference(resolve, &name)? {
+                    Some(found) => value.push_str(&found),
+                    None => undefined.push(name)
+                }
+            },
+            _ => value.push(c)
+        }
+    }
+    Ok(value)
+}
+
+/// Find where a quoted value closes, returning the text inside
+/// the quotes and whatever follows the closing quote.
+fn closing_quote(text: &str, quote: char) -> Option<(&str, &str)> {
+    let mut escaped = false;
+    for (i, c) in text.char_indices() {
+        match c {
+            '\\' if quote == '"' && !escaped => escaped = true,
+            c if c == quote && !escaped => return Some((&text[..i], &text[i + 1..])),
+            _ => escaped = false
+        }
+    }
+    None
+}
+
+/// Parse the contents of an env file. Parsing carries on past
+/// bad lines so that every error can be reported at once; only
+/// the lines that parsed are returned as entries.
+///
+/// Values may refer to variables defined earlier in the file, and
+/// otherwise to whatever `lookup` finds. References to variables
+/// found in neither become empty and are noted on the entry, while
+/// references to secrets are errors.
+///
+/// # Arguments
+/// * `text` - Contents of the file.
+/// * `lookup` - Looks up variables not defined by the file itself.
+///
+/// # Examples
+/// ```
+/// let (entries, errors) = parse_env("export RD_PORT=8000 # Default port", &|key| env::var(key).ok());
+/// ```
+pub fn parse_env(text: &str, lookup: &dyn Fn(&str) -> Option<String>) -> (Vec<EnvEntry>, Vec<ParseError>) {
+    let lines = text.lines().collect::<Vec<&str>>();
+    let mut entries: Vec<EnvEntry> = Vec::new();
+    let mut errors = Vec::new();
+    let mut defined: HashMap<String, String> = HashMap::new();
+
+    let mut index = 0;
+    while index < lines.len() {
+        let line = index + 1;
+        let mut rest = lines[index].trim_start();
+        index += 1;
+
+        // Skip blank lines and comments
+        if rest.is_empty() || rest.starts_with('#') {
+            continue;
+        }
+        let exported = rest.starts_with("export") && rest["export".len()..].starts_with(char::is_whitespace);
+        if exported {
+            rest = rest["export".len()..].trim_start();
+        }
+
+        // Read the name of the variable
+        let name_end = rest.find(|c| !is_name_char(c)).unwrap_or_else(|| rest.len());
+        let key = &rest[..name_end];
+        rest = rest[name_end..].trim_start();
+        let error = match rest.chars().next() {
+            _ if key.is_empty() => Some(String::from("Expected a variable name.")),
+            _ if key.starts_with(|c: char| c.is_ascii_digit()) => Some(format!("Variable name {} starts with a digit.", key)),
+            Some('=') => None,
+            None => Some(format!("Missing '=' after {}.", key)),
+            Some(c) => Some(format!("Expected '=' after {}, found '{}'.", key, c))
+        };
+        if let Some(message) = error {
+            errors.push(ParseError { line, message });
+            continue;
+        }
+        rest = rest[1..].trim_start();
+
+        // Read the value, which may be quoted, and any comment after it
+        let resolve = |name: &str| defined.get(name).cloned().or_else(|| lookup(name));
+        let mut undefined = Vec::new();
+        let parsed = match rest.chars().next() {
+            Some(quote) if quote == '\'' || quote == '"' => {
+                // Quoted values run on until the quote closes
+                let mut quoted = String::from(&rest[1..]);
+                let mut closed = closing_quote(&quoted, quote).map(|(inside, after)| (inside.to_string(), after.trim().to_string()));
+                while closed.is_none() && index < lines.len() {
+                    quoted.push('\n');
+                    quoted.push_str(lines[index]);
+                    index += 1;
+                    closed = closing_quote(&quoted, quote).map(|(inside, after)| (inside.to_string(), after.trim().to_string()));
+                }
+
+                match closed {
+                    None => Err(format!("Missing closing {} for the value of {}.", quote, key)),
+                    Some((_, after)) if !after.is_empty() && !after.starts_with('#') => {
+                        Err(format!("Unexpected text after the closing {} of {}.", quote, key))
+                    },
+                    Some((inside, after)) => {
+                        let comment = if after.is_empty() { None } else { Some(after[1..].trim().to_string()) };
+                        let written = format!("{}{}{}", quote, inside, quote);
+                        if quote == '\'' {
+                            Ok((inside, written, comment))
+                        } else {
+                            expand(&inside, true, &resolve, &mut undefined).map(|value| (value, written, comment))
+                        }
+                    }
+                }
+            },
+            _ => {
+                // Comments need a space before them, so "a#b" is a value
+                let comment_start = if rest.starts_with('#') {
+                    Some(0)
+                } else {
+                    rest.find(" #").or_else(|| rest.find("\t#"))
+                };
+                let (unquoted, comment) = match comment_start {
+                    Some(start) => (&rest[..start], Some(rest[start..].trim_start()[1..].trim().to_string())),
+                    None => (rest, None)
+                };
+                let written = unquoted.trim_end();
+                expand(written, false, &resolve, &mut undefined).map(|value| (value, written.to_string(), comment))
+            }
+        };
+
+        match parsed {
+            Ok((value, written, comment)) => {
+                defined.insert(key.to_string(), value.clone());
+                entries.push(EnvEntry {
+                    key: key.to_string(),
+                    value,
+                    written,
+                    line,
+                    end_line: index,
+                    exported,
+                    comment,
+                    undefined
+                });
+            },
+            Err(message) => errors.push(ParseError { line, message })
+        }
+    }
+    (entries, errors)
+}
+
+/// Format the definition of a variable as a line of an env file.
+///
+/// # Arguments
+/// * `key` - Name of the variable.
+/// * `value` - Value of the variable.
+/// * `exported` - Whether to give the line an `export` prefix.
+/// * `comment` - Comment to follow the value.
+pub fn format_env_line(key: &str, value: &str, exported: bool, comment: Option<&str>) -> String {
+    format_definition(key, &quote_value(value), exported, comment)
+}
+
+/// Format the definition of a variable as a line of an env file,
+/// with its value already written the way the file is to have it.
+///
+/// # Arguments
+/// * `key` - Name of the variable.
+/// * `written` - Value as written, such as the `written` of an `EnvEntry`.
+/// * `exported` - Whether to give the line an `export` prefix.
+/// * `comment` - Comment to follow the value.
+pub fn format_definition(key: &str, written: &str, exported: bool, comment: Option<&str>) -> String {
+    let mut line = format!("{}{}={}", if exported { "export " } else { "" }, key, written);
+    if let Some(comment) = comment {
+        line.push_str(" # ");
+        line.push_str(comment);
+    }
+    line
+}
+
+/// Parse an env file that is about to be edited, refusing files
+/// with errors since they cannot be edited safely.
+fn parse_for_edit(text: &str) -> Result<Vec<EnvEntry>, String> {
+    let (entries, errors) = parse_env(text, &|_| None);
+    match errors.first() {
+        Some(err) => Err(format!("Cannot edit a file with errors ({}). Run `rd environment validate` for details.", err)),
+        None => Ok(entries)
+    }
+}
+
+/// Join lines back into the contents of a file.
+fn join_lines(lines: Vec<String>) -> String {
+    let mut text = lines.join("\n");
+    if !text.is_empty() {
+        text.push('\n');
+    }
+    text
+}
+
+/// Set a variable in the contents of an env file, leaving every
+/// other line as it was. A variable already defined keeps its
+/// place, `export` prefix and comment; a new one goes at the end.
+///
+/// # Arguments
+/// * `text` - Contents of the file.
+/// * `key` - Name of the variable.
+/// * `value` - Value to set.
+///
+/// # Examples
+/// ```
+/// let updated = set_in_env(&contents, "RD_PORT", "8080")?;
+/// ```
+pub fn set_in_env(text: &str, key: &str, value: &str) -> Result<String, String> {
+    set_definition_in_env(text, key, &quote_value(value))
+}
+
+/// Set a variable in the contents of an env file to a value already
+/// written the way the file is to have it, so that any references
+/// it makes are kept. Otherwise the same as `set_in_env`.
+///
+/// # Arguments
+/// * `text` - Contents of the file.
+/// * `key` - Name of the variable.
+/// * `written` - Value as written, such as the `written` of an `EnvEntry`.
+///
+/// # Examples
+/// ```
+/// let updated = set_definition_in_env(&contents, "RD_URL", "\"http://${RD_HOST}\"")?;
+/// ```
+pub fn set_definition_in_env(text: &str, key: &str, written: &str) -> Result<String, String> {
+    if key.is_empty() || key.starts_with(|c: char| c.is_ascii_digit()) || !key.chars().all(is_name_char) {
+        return Err(format!("Invalid variable name \"{}\". Use letters, digits and '_'.", key));
+    }
+
+    let entries = parse_for_edit(text)?;
+    let mut lines = text.lines().map(String::from).collect::<Vec<String>>();
+
+    // Only the last definition counts, so that is the one changed
+    match entries.iter().rev().find(|entry| entry.key == key) {
+        Some(entry) => {
+            let line = format_definition(key, written, entry.exported, entry.comment.as_ref().map(String::as_str));
+            lines.splice(entry.line - 1..entry.end_line, vec![line]);
+        },
+        None => lines.push(format_definition(key, written, false, None))
+    }
+    Ok(join_lines(lines))
+}
+
+/// Remove every definition of a variable from the contents of an
+/// env file, leaving every other line as it was. Returns the new
+/// contents, or nothing if the variable was not defined.
+///
+/// # Arguments
+/// * `text` - Contents of the file.
+/// * `key` - Name of the variable.
+///
+/// # Examples
+/// ```
+/// if let Some(updated) = remove_from_env(&contents, "RD_PORT")? {
+///     // Write it back
+/// }
+/// ```
+pub fn remove_from_env(text: &str, key: &str) -> Result<Option<String>, String> {
+    let entries = parse_for_edit(text)?;
+    let mut lines = text.lines().map(String::from).collect::<Vec<String>>();
+
+    let mut removed = false;
+    for entry in entries.iter().rev().filter(|entry| entry.key == key) {
+        lines.drain(entry.line - 1..entry.end_line);
+        removed = true;
+    }
+    Ok(if removed { Some(join_lines(lines)) } else { None })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parse a file with nothing set outside of it.
+    fn parse(text: &str) -> (Vec<EnvEntry>, Vec<ParseError>) {
+        parse_env(text, &|_| None)
+    }
+
+    /// Value of the only variable a file defines.
+    fn value_of(text: &str, lookup: &dyn Fn(&str) -> Option<String>) -> String {
+        let (entries, errors) = parse_env(text, lookup);
+        assert_eq!(errors, vec![]);
+        assert_eq!(entries.len(), 1);
+        entries[0].value.clone()
+    }
+
+    /// Message of the only error in a file, with its line.
+    fn error_of(text: &str) -> (usize, String) {
+        let (_, errors) = parse(text);
+        assert_eq!(errors.len(), 1);
+        (errors[0].line, errors[0].message.clone())
+    }
+
+    #[test]
+    fn plain_values_and_export_prefixes() {
+        let (entries, errors) = parse("RD_HOST=0.0.0.0\n\n# Port\nexport RD_PORT = 8000\nexported=1\n");
+        assert!(errors.is_empty());
+        assert_eq!(entries.len(), 3);
+        assert_eq!((entries[0].key.as_str(), entries[0].value.as_str(), entries[0].exported), ("RD_HOST", "0.0.0.0", false));
+        assert_eq!((entries[1].key.as_str(), entries[1].value.as_str(), entries[1].exported), ("RD_PORT", "8000", true));
+        assert_eq!(entries[1].line, 4);
+        assert_eq!((entries[2].key.as_str(), entries[2].exported), ("exported", false));
+    }
+
+    #[test]
+    fn comments_need_a_space_before_them() {
+        let (entries, _) = parse("A=value # Comment\nB=a#b\nC=\t# Empty\nD='quoted # not a comment' # Comment\n");
+        assert_eq!(entries[0].value, "value");
+        assert_eq!(entries[0].comment, Some(String::from("Comment")));
+        assert_eq!(entries[1].value, "a#b");
+        assert_eq!(entries[1].comment, None);
+        assert_eq!(entries[2].value, "");
+        assert_eq!(entries[2].comment, Some(String::from("Empty")));
+        assert_eq!(entries[3].value, "quoted # not a comment");
+        assert_eq!(entries[3].comment, Some(String::from("Comment")));
+    }
+
+    #[test]
+    fn quoted_values_span_lines() {
+        let (entries, errors) = parse("KEY=\"first\nsecond\n  third\"\nNEXT=1\n");
+        assert!(errors.is_empty());
+        assert_eq!(entries[0].value, "first\nsecond\n  third");
+        assert_eq!((entries[0].line, entries[0].end_line), (1, 3));
+        assert_eq!((entries[1].key.as_str(), entries[1].line), ("NEXT", 4));
+    }
+
+    #[test]
+    fn escapes_apply_inside_double_quotes_only() {
+        assert_eq!(value_of(r#"A="tab\there\nquote\" slash\\ dollar\$ other\q""#, &|_| None), "tab\there\nquote\" slash\\ dollar$ other\\q");
+        assert_eq!(value_of(r#"A='no\nescapes $HOME'"#, &|_| Some(String::from("/home"))), "no\\nescapes $HOME");
+        assert_eq!(value_of(r#"A=bare\n\$HOME"#, &|_| Some(String::from("/home"))), "bare\\n$HOME");
+    }
+
+    #[test]
+    fn references_are_expanded() {
+        let lookup = |name: &str| match name {
+            "HOME" => Some(String::from("/home/duck")),
+            "EMPTY" => Some(String::new()),
+            _ => None
+        };
+        assert_eq!(value_of("A=${HOME}/rd", &lookup), "/home/duck/rd");
+        assert_eq!(value_of("A=$HOME/rd", &lookup), "/home/duck/rd");
+        assert_eq!(value_of("A=\"$HOME\"", &lookup), "/home/duck");
+        assert_eq!(value_of("A=$1", &lookup), "$1");
+
+        // Variables defined earlier in the file take precedence
+        let (entries, _) = parse_env("HOME=/srv\nA=$HOME/rd\n", &lookup);
+        assert_eq!(entries[1].value, "/srv/rd");
+    }
+
+    #[test]
+    fn references_fall_back_to_defaults() {
+        let lookup = |name: &str| match name {
+            "EMPTY" => Some(String::new()),
+            "SET" => Some(String::from("set")),
+            _ => None
+        };
+        assert_eq!(value_of("A=${UNSET:-default}", &lookup), "default");
+        assert_eq!(value_of("A=${EMPTY:-default}", &lookup), "default");
+        assert_eq!(value_of("A=${SET:-default}", &lookup), "set");
+        assert_eq!(value_of("A=${UNSET-default}", &lookup), "default");
+        assert_eq!(value_of("A=${EMPTY-default}", &lookup), "");
+        assert_eq!(value_of("A=${SET-default}", &lookup), "set");
+    }
+
+    #[test]
+    fn undefined_references_are_noted() {
+        let (entries, errors) = parse("A=${MISSING}/$ALSO_MISSING/${DEFAULTED:-x}\n");
+        assert!(errors.is_empty());
+        assert_eq!(entries[0].value, "//x");
+        assert_eq!(entries[0].undefined, vec![String::from("MISSING"), String::from("ALSO_MISSING")]);
+    }
+
+    #[test]
+    fn secrets_cannot_be_referred_to() {
+        let (entries, errors) = parse("PASS=secret:v1:00\nURL=postgres://u:${PASS}@h\nALSO=$PASS\n");
+        assert_eq!(entries.iter().map(|entry| entry.key.as_str()).collect::<Vec<&str>>(), vec!["PASS"]);
+        assert_eq!(errors.iter().map(|err| err.line).collect::<Vec<usize>>(), vec![2, 3]);
+        assert_eq!(errors[0].message, "Cannot refer to secret PASS, as that would store it unencrypted.");
+    }
+
+    #[test]
+    fn errors_name_their_line() {
+        assert_eq!(error_of("A=1\nB\n"), (2, String::from("Missing '=' after B.")));
+        assert_eq!(error_of("A=1\n\nB: 2\n"), (3, String::from("Expected '=' after B, found ':'.")));
+        assert_eq!(error_of("=1\n"), (1, String::from("Expected a variable name.")));
+        assert_eq!(error_of("1A=1\n"), (1, String::from("Variable name 1A starts with a digit.")));
+        assert_eq!(error_of("A=1\nB=\"open\nC=2\n"), (2, String::from("Missing closing \" for the value of B.")));
+        assert_eq!(error_of("A='x' y\n"), (1, String::from("Unexpected text after the closing ' of A.")));
+        assert_eq!(error_of("A=${B\n"), (1, String::from("Missing '}' to close '${'.")));
+        assert_eq!(error_of("A=${B?}\n"), (1, String::from("Invalid reference '${B?}'.")));
+        assert_eq!(error_of("A=${}\n"), (1, String::from("Invalid reference '${}'.")));
+    }
+
+    #[test]
+    fn parsing_carries_on_past_errors() {
+        let (entries, errors) = parse("A=1\nB\nC=3\n4D=4\n");
+        assert_eq!(entries.iter().map(|entry| entry.key.as_str()).collect::<Vec<&str>>(), vec!["A", "C"]);
+        assert_eq!(errors.iter().map(|err| err.line).collect::<Vec<usize>>(), vec![2, 4]);
+    }
+
+    #[test]
+    fn quoted_values_read_back_unchanged() {
+        for value in &["plain", "", "with space", "it's", "say \"hi\"", "$HOME", "a\\b", "two\nlines", "'\"$\\\n"] {
+            assert_eq!(&value_of(&format_env_line("A", value, false, None), &|_| Some(String::from("x"))), value);
+        }
+    }
+
+    #[test]
+    fn setting_keeps_other_lines() {
+        let text = "# Settings\nexport A=1 # First\nB=2\n";
+        assert_eq!(set_in_env(text, "A", "one two").unwrap(), "# Settings\nexport A='one two' # First\nB=2\n");
+        assert_eq!(set_in_env(text, "C", "3").unwrap(), "# Settings\nexport A=1 # First\nB=2\nC=3\n");
+        assert!(set_in_env(text, "1A", "3").is_err());
+        assert!(set_in_env("A\n", "B", "3").is_err());
+    }
+
+    #[test]
+    fn definitions_are_kept_as_written() {
+        let (entries, errors) = parse("A=${HOME}/rd # Home\nB='$1'\nC=\"${A:-x}\nmore\"\n");
+        assert!(errors.is_empty());
+        assert_eq!(entries.iter().map(|entry| entry.written.as_str()).collect::<Vec<&str>>(), vec!["${HOME}/rd", "'$1'", "\"${A:-x}\nmore\""]);
+
+        let text = "# Settings\nexport A=1 # First\n";
+        assert_eq!(set_definition_in_env(text, "A", "\"${HOME}\"").unwrap(), "# Settings\nexport A=\"${HOME}\" # First\n");
+        assert_eq!(set_definition_in_env(text, "B", "$A").unwrap(), "# Settings\nexport A=1 # First\nB=$A\n");
+    }
+
+    #[test]
+    fn removing_drops_every_definition() {
+        let text = "A=1\nB=\"multi\nline\"\nA=2\n";
+        assert_eq!(remove_from_env(text, "A").unwrap(), Some(String::from("B=\"multi\nline\"\n")));
+        assert_eq!(remove_from_env(text, "B").unwrap(), Some(String::from("A=1\nA=2\n")));
+        assert_eq!(remove_from_env(text, "C").unwrap(), None);
+    }
+}
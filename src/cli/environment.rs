@@ -7,23 +7,77 @@ use std::{
     fs::{
         File,
         create_dir,
+        create_dir_all,
+        metadata,
+        read_dir,
         remove_file,
+        rename,
         read_to_string
     },
     io::{
-        BufReader,
-        BufRead,
+        self,
+        Read,
         Write
     },
-    path::PathBuf,
-    collections::HashMap,
-    time::{
-        UNIX_EPOCH,
-        SystemTime
+    os::unix::process::CommandExt,
+    path::{
+        Path,
+        PathBuf
+    },
+    process,
+    collections::{
+        HashMap,
+        HashSet
+    },
+    sync::Mutex
+};
+use fs2::FileExt;
+use structopt::{
+    StructOpt,
+    clap::{
+        AppSettings,
+        Shell
+    }
+};
+
+// Local imports
+use crate::cli::{
+    dotenv::{
+        EnvEntry,
+        ParseError,
+        parse_env,
+        quote_value,
+        remove_from_env,
+        set_definition_in_env,
+        set_in_env
+    },
+    exchange::{
+        EnvFormat,
+        MergeStrategy,
+        export_definitions,
+        export_variables,
+        import_definitions,
+        shell_exports
+    },
+    secrets::{
+        decrypt_secret,
+        encrypt_secret,
+        is_secret,
+        masked,
+        reveal_secrets
     }
 };
-use dirs::home_dir;
-use structopt::StructOpt;
+use crate::settings::warn_or_print;
+
+lazy_static! {
+    /// Variables of the environment `rd` was started in, before any
+    /// env file was loaded. These take precedence over every file.
+    static ref PROCESS_ENV: HashMap<String, String> = env::vars().collect();
+
+    /// Warnings already given about env files, since the same file
+    /// may be read several times by one command.
+    static ref ENV_WARNINGS: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+}
 
 /// Passthrough for subcommands of the `environment` command
 #[derive(Debug, StructOpt)]
@@ -64,6 +118,148 @@ pub struct RemoveEnvCLI {
     key: String
 }
 
+/// Command arg options for setting a secret
+#[derive(Debug, StructOpt)]
+pub struct SetSecretCLI {
+    #[structopt(
+        short,
+        long,
+        help = "The variable key for the secret."
+    )]
+    key: String,
+
+    #[structopt(
+        short,
+        long,
+        help = "The value of the secret. Read from standard input if not given, to keep it out of shell history."
+    )]
+    value: Option<String>
+}
+
+/// Command arg options for reading a secret
+#[derive(Debug, StructOpt)]
+pub struct GetSecretCLI {
+    #[structopt(
+        short,
+        long,
+        help = "The variable key of the secret."
+    )]
+    key: String
+}
+
+/// Command arg options for exporting the environment
+#[derive(Debug, StructOpt)]
+pub struct ExportEnvCLI {
+    #[structopt(
+        short,
+        long,
+        default_value = "dotenv",
+        help = "Format to export in: dotenv, json or shell. Only dotenv keeps references to other variables."
+    )]
+    format: EnvFormat,
+
+    #[structopt(
+        short,
+        long,
+        help = "File to write to. Written to standard output if not given.",
+        parse(from_os_str)
+    )]
+    output: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        help = "Leave secrets out of the export."
+    )]
+    exclude_secrets: bool
+}
+
+/// Command arg options for importing the environment
+#[derive(Debug, StructOpt)]
+pub struct ImportEnvCLI {
+    #[structopt(
+        help = "File to import, or - to read standard input.",
+        parse(from_os_str)
+    )]
+    file: PathBuf,
+
+    #[structopt(
+        short,
+        long,
+        help = "Format of the file: dotenv, json or shell. Guessed from the file extension if not given."
+    )]
+    format: Option<EnvFormat>,
+
+    #[structopt(
+        short,
+        long,
+        default_value = "fail-on-conflict",
+        help = "What to do with variables already set to something else: overwrite, keep-existing or fail-on-conflict."
+    )]
+    strategy: MergeStrategy,
+
+    #[structopt(
+        long,
+        help = "Leave secrets in the file out of the import."
+    )]
+    exclude_secrets: bool
+}
+
+/// Command arg options for switching profiles
+#[derive(Debug, StructOpt)]
+pub struct UseProfileCLI {
+    #[structopt(help = "Profile to switch to, or \"base\" to use base.env alone.")]
+    name: String
+}
+
+/// Command arg options for comparing profiles
+#[derive(Debug, StructOpt)]
+pub struct DiffProfilesCLI {
+    #[structopt(help = "Profile to compare from, or \"base\" for base.env alone.")]
+    from: String,
+
+    #[structopt(help = "Profile to compare to, or \"base\" for base.env alone.")]
+    to: String
+}
+
+/// Command arg options for running a command in the environment
+#[derive(Debug, StructOpt)]
+#[structopt(setting = AppSettings::TrailingVarArg)]
+pub struct ExecCLI {
+    #[structopt(
+        long,
+        help = "Instance whose instance.env to load too. Defaults to RD_INSTANCE, or else the default instance."
+    )]
+    instance: Option<String>,
+
+    #[structopt(
+        required = true,
+        help = "Command to run, followed by its arguments."
+    )]
+    command: Vec<String>
+}
+
+/// Command arg options for setting variables in a shell
+#[derive(Debug, StructOpt)]
+pub struct ShellInitCLI {
+    #[structopt(
+        possible_values = &["bash", "zsh", "fish"],
+        help = "Shell that evaluates the output, as in `eval \"$(rd environment shell-init bash)\"`."
+    )]
+    shell: Shell,
+
+    #[structopt(
+        long,
+        help = "Instance whose instance.env to load too. Defaults to RD_INSTANCE, or else the default instance."
+    )]
+    instance: Option<String>,
+
+    #[structopt(
+        long,
+        help = "Leave secrets out, rather than setting them decrypted."
+    )]
+    exclude_secrets: bool
+}
+
 /// Enum listing the various subcommands of the `environment` command.
 #[derive(Debug, StructOpt)]
 pub enum EnvironmentCommand {
@@ -75,7 +271,82 @@ pub enum EnvironmentCommand {
     SetEnv(SetEnvCLI),
 
     #[structopt(about = "Removes an item from the environment.")]
-    RemoveEnv(RemoveEnvCLI)
+    RemoveEnv(RemoveEnvCLI),
+
+    #[structopt(about = "Sets an environment variable, stored encrypted.")]
+    SetSecret(SetSecretCLI),
+
+    #[structopt(about = "Prints the decrypted value of a secret.")]
+    GetSecret(GetSecretCLI),
+
+    #[structopt(about = "Writes out every variable, for importing elsewhere.")]
+    Export(ExportEnvCLI),
+
+    #[structopt(about = "Sets variables from an exported file.")]
+    Import(ImportEnvCLI),
+
+    #[structopt(about = "Checks base.env, every profile and the instance.env of every instance for errors.")]
+    Validate,
+
+    #[structopt(about = "Switches the profile layered on top of base.env.")]
+    Use(UseProfileCLI),
+
+    #[structopt(about = "Lists every profile, marking the active one.")]
+    List,
+
+    #[structopt(about = "Shows how the variables of two profiles differ.")]
+    Diff(DiffProfilesCLI),
+
+    #[structopt(about = "Runs a command in the environment the dataserver sees, as in `rd environment exec -- <cmd>`.")]
+    Exec(ExecCLI),
+
+    #[structopt(about = "Prints statements that set the variables of base.env, the active profile and instance.env in a shell.")]
+    ShellInit(ShellInitCLI)
+}
+
+impl EnvironmentCommand {
+
+    /// Whether the command prints output for other programs to read,
+    /// which log records must be kept out of.
+    pub fn prints_output(&self) -> bool {
+        match self {
+            EnvironmentCommand::GetSecret(_) | EnvironmentCommand::ShellInit(_) => true,
+            EnvironmentCommand::Export(cmd) => cmd.output.is_none(),
+            _ => false
+        }
+    }
+
+    /// Whether the command writes to the env file of the profile
+    /// given with `--profile`, creating it if it does not exist yet.
+    pub fn creates_profile(&self) -> bool {
+        match self {
+            EnvironmentCommand::SetEnv(_) | EnvironmentCommand::SetSecret(_) | EnvironmentCommand::Import(_) => true,
+            _ => false
+        }
+    }
+}
+
+/// Get the path of RD_HOME, falling back to `.rd` in the
+/// user's home directory. The directory need not exist.
+#[cfg(not(test))]
+fn get_rd_home_path() -> Result<PathBuf, String> {
+    match env::var("RD_HOME") {
+        Ok(home) => Ok(PathBuf::from(home)),
+        Err(_) => match dirs::home_dir() {
+            Some(mut dir) => {
+                dir.push(".rd");
+                Ok(dir)
+            },
+            None => Err(String::from("Failed to get system home directory. Set RD_HOME to correct this error."))
+        }
+    }
+}
+
+/// Get the RD_HOME of the running test. Each test has one of its
+/// own, so none of them touch the real one or each other's.
+#[cfg(test)]
+fn get_rd_home_path() -> Result<PathBuf, String> {
+    crate::testing::test_rd_home().ok_or_else(|| String::from("Tests must set up a TestRdHome before using RD_HOME."))
 }
 
 /// Returns the RD_HOME environment variable, creating
@@ -87,16 +358,7 @@ pub enum EnvironmentCommand {
 /// ```
 #[inline]
 pub fn get_or_create_rd_home() -> Result<String, String> {
-    let home_directory = match env::var("RD_HOME") {
-        Ok(home) => PathBuf::from(home),
-        Err(_) => match home_dir() {
-            Some(mut dir) => {
-                dir.push(".rd");
-                dir
-            },
-            None => return Err(String::from("Failed to get system home directory. Set RD_HOME to correct this error."))
-        }
-    };
+    let home_directory = get_rd_home_path()?;
 
     // If the directory does not exist, create it.
     if !home_directory.exists() {
@@ -109,26 +371,213 @@ pub fn get_or_create_rd_home() -> Result<String, String> {
     }
 }
 
-/// Get or create the current time log file
+/// Name of the instance used when none is given. Its files
+/// live directly in RD_HOME.
+pub const DEFAULT_INSTANCE: &str = "default";
+
+/// Name of the per-instance environment file.
+pub const INSTANCE_ENV_FILE: &str = "instance.env";
+
+/// Name standing for base.env without any profile on top.
+pub const BASE_PROFILE: &str = "base";
+
+/// Name of the log file currently written to in each logs directory.
+pub const ACTIVE_LOG_FILE: &str = "output.log";
+
+/// Returns the dataserver instance selected by RD_INSTANCE,
+/// falling back to the default instance.
 /// 
 /// # Examples
 /// ```
-/// if get_or_create_log_file().is_ok() {
-///     // Do some stuff
+/// let instance = current_instance();
+/// ```
+#[inline]
+pub fn current_instance() -> String {
+    env::var("RD_INSTANCE").unwrap_or_else(|_| String::from(DEFAULT_INSTANCE))
+}
+
+/// Get a variable of the environment `rd` was started in, ignoring
+/// anything loaded from env files since.
+/// 
+/// # Arguments
+/// * `key` - Name of the variable.
+/// 
+/// # Examples
+/// ```
+/// if process_env_var("RD_PORT").is_some() {
+///     // Set outside of any env file
 /// }
 /// ```
+pub fn process_env_var(key: &str) -> Option<&'static String> {
+    PROCESS_ENV.get(key)
+}
+
+/// Get every variable of the environment `rd` was started in.
+pub fn process_environment() -> &'static HashMap<String, String> {
+    &PROCESS_ENV
+}
+
+/// Check that an instance name is safe to use as a directory name.
+/// 
+/// # Arguments
+/// * `instance` - Name of the instance.
+/// 
+/// # Examples
+/// ```
+/// validate_instance_name("staging")?;
+/// ```
+pub fn validate_instance_name(instance: &str) -> Result<(), String> {
+    if !instance.is_empty() && instance.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        Ok(())
+    } else {
+        Err(format!("Invalid instance name \"{}\". Use letters, digits, '-' and '_'.", instance))
+    }
+}
+
+/// Check that a profile name is safe to use as a file name.
+/// 
+/// # Arguments
+/// * `profile` - Name of the profile.
+/// 
+/// # Examples
+/// ```
+/// validate_profile_name("prod")?;
+/// ```
+pub fn validate_profile_name(profile: &str) -> Result<(), String> {
+    if profile != BASE_PROFILE && !profile.is_empty() && profile.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        Ok(())
+    } else {
+        Err(format!("Invalid profile name \"{}\". Use letters, digits, '-' and '_', other than \"{}\".", profile, BASE_PROFILE))
+    }
+}
+
+/// Returns the directory holding the PID file, logs and
+/// configuration of a dataserver instance, creating it if
+/// it does not already exist.
+/// 
+/// # Arguments
+/// * `instance` - Name of the instance.
+/// 
+/// # Examples
+/// ```
+/// let instance_home = get_or_create_instance_home("staging").unwrap();
+/// ```
+pub fn get_or_create_instance_home(instance: &str) -> Result<String, String> {
+    validate_instance_name(instance)?;
+    if instance == DEFAULT_INSTANCE {
+        return get_or_create_rd_home();
+    }
+
+    let mut instance_home = PathBuf::from(get_or_create_rd_home()?);
+    instance_home.push("instances");
+    instance_home.push(instance);
+
+    if !instance_home.exists() {
+        create_dir_all(&instance_home).map_err(|err| err.to_string())?;
+    }
+    Ok(String::from(instance_home.to_str().unwrap()))
+}
+
+/// List every known dataserver instance, the default one first.
+/// 
+/// # Examples
+/// ```
+/// let instances = list_instances().unwrap();
+/// ```
+pub fn list_instances() -> Result<Vec<String>, String> {
+    let mut instances_dir = PathBuf::from(get_or_create_rd_home()?);
+    instances_dir.push("instances");
+
+    let mut instances = Vec::new();
+    if instances_dir.exists() {
+        for entry in read_dir(&instances_dir).map_err(|err| err.to_string())? {
+            let entry = entry.map_err(|err| err.to_string())?;
+            if entry.path().is_dir() {
+                instances.push(entry.file_name().to_string_lossy().to_string());
+            }
+        }
+    }
+    instances.sort();
+    instances.insert(0, String::from(DEFAULT_INSTANCE));
+    Ok(instances)
+}
+
+/// Get or create the logs directory of an instance
+/// 
+/// # Arguments
+/// * `instance` - Name of the instance.
+/// 
+/// # Examples
+/// ```
+/// let logs_dir = get_or_create_logs_dir(DEFAULT_INSTANCE).unwrap();
+/// ```
 #[inline]
-pub fn get_or_create_log_file() -> Result<String, String> {
-    let mut file_path = PathBuf::from(get_or_create_rd_home()?);
-    let current_timestamp = SystemTime::now().duration_since(UNIX_EPOCH).expect("It seems time went backwards...");
-    file_path.push("logs");
+pub fn get_or_create_logs_dir(instance: &str) -> Result<PathBuf, String> {
+    let mut logs_dir = PathBuf::from(get_or_create_instance_home(instance)?);
+    logs_dir.push("logs");
 
     // Create the logs directory if it doesn't already exists
-    if !file_path.exists() && create_dir::<&PathBuf>(&file_path).is_err() {
+    if !logs_dir.exists() && create_dir::<&PathBuf>(&logs_dir).is_err() {
         return Err(String::from("Failed to create logs directory."));
     }
+    Ok(logs_dir)
+}
+
+/// Get or create the directory holding the TLS certificate of an instance
+/// 
+/// # Arguments
+/// * `instance` - Name of the instance.
+/// 
+/// # Examples
+/// ```
+/// let tls_dir = get_or_create_tls_dir(DEFAULT_INSTANCE).unwrap();
+/// ```
+pub fn get_or_create_tls_dir(instance: &str) -> Result<PathBuf, String> {
+    let mut tls_dir = PathBuf::from(get_or_create_instance_home(instance)?);
+    tls_dir.push("tls");
+
+    if !tls_dir.exists() {
+        create_dir(&tls_dir).map_err(|err| err.to_string())?;
+    }
+    Ok(tls_dir)
+}
+
+/// Get or create the active log file of an instance. Older
+/// records are rotated out of it into compressed files.
+/// 
+/// # Arguments
+/// * `instance` - Name of the instance.
+/// 
+/// # Examples
+/// ```
+/// if get_or_create_log_file(DEFAULT_INSTANCE).is_ok() {
+///     // Do some stuff
+/// }
+/// ```
+#[inline]
+pub fn get_or_create_log_file(instance: &str) -> Result<String, String> {
+    get_or_create_logs_file(instance, ACTIVE_LOG_FILE)
+}
+
+/// Get or create the file catching anything the server process of
+/// an instance prints outside the logger, such as panics.
+/// 
+/// # Arguments
+/// * `instance` - Name of the instance.
+/// 
+/// # Examples
+/// ```
+/// let output_file = get_or_create_server_output_file(DEFAULT_INSTANCE).unwrap();
+/// ```
+#[inline]
+pub fn get_or_create_server_output_file(instance: &str) -> Result<String, String> {
+    get_or_create_logs_file(instance, "server.out")
+}
 
-    file_path.push(format!("output-{}.log", current_timestamp.as_secs() / 86400).as_str());
+/// Get or create a file in the logs directory of an instance.
+fn get_or_create_logs_file(instance: &str, name: &str) -> Result<String, String> {
+    let mut file_path = get_or_create_logs_dir(instance)?;
+    file_path.push(name);
 
     // Create the file and return out the path
     if !file_path.exists() {
@@ -178,8 +627,8 @@ pub fn get_or_create_env_file() -> Result<String, String> {
 }
 
 /// Get's a HashMap representing all known environment variables
-/// in the configuration. Ownership of this structured is returned
-/// to the caller.
+/// in the configuration, with secrets still encrypted. Ownership
+/// of this structured is returned to the caller.
 /// 
 /// # Examples
 /// ```
@@ -187,219 +636,1251 @@ pub fn get_or_create_env_file() -> Result<String, String> {
 /// ```
 #[inline]
 pub fn get_env() -> Result<HashMap<String, String>, String> {
-    let mut var_map: HashMap<String, String> = HashMap::new();
-    let home_directory = match get_or_create_env_file() {
-        Ok(dir) => dir,
-        Err(msg) => return Err(msg)
-    };
+    read_env_file(&get_or_create_env_file()?, &HashMap::new())
+}
 
-    // Read each line of the file and dump the environment variables
-    let file = File::open::<String>(home_directory).unwrap();
-    for line in BufReader::new(file).lines() {
-        let env_line = match line {
-            Ok(l) => l,
-            Err(err) => return Err(err.to_string())
-        };
+/// Parse an env file, returning every variable it defines in
+/// order along with any lines that could not be read. Values
+/// may refer to variables of the files layered under it, and
+/// of the current process environment. Secrets are looked up
+/// as stored, so that referring to one is refused rather than
+/// copying it out decrypted.
+/// 
+/// # Arguments
+/// * `path` - Path of the env file.
+/// * `below` - Variables of the files layered under it, as stored.
+/// 
+/// # Examples
+/// ```
+/// let (entries, errors) = parse_env_file(&profile_file, &get_env()?).unwrap();
+/// ```
+pub fn parse_env_file(path: &str, below: &HashMap<String, String>) -> Result<(Vec<EnvEntry>, Vec<ParseError>), String> {
+    let contents = read_to_string(path).map_err(|err| err.to_string())?;
+
+    // The process environment holds secrets decrypted once loaded, so
+    // every secret defined here or below is found as stored first
+    let (own, _) = parse_env(&contents, &|_| None);
+    let secrets = own.into_iter()
+                     .map(|entry| (entry.key, entry.value))
+                     .chain(below.iter().map(|(key, value)| (key.clone(), value.clone())))
+                     .filter(|(_, value)| is_secret(value))
+                     .collect::<HashMap<String, String>>();
+    Ok(parse_env(&contents, &|key| {
+        secrets.get(key)
+               .or_else(|| process_env_var(key))
+               .or_else(|| below.get(key))
+               .cloned()
+               .or_else(|| env::var(key).ok())
+    }))
+}
 
-        // Split out the the environment information and put into the HashMap
-        let env_content = env_line.splitn(2, "=").map(|s| String::from(s)).collect::<Vec<String>>();
-        if env_content.len() < 2 {
-            // Skip bad lines
-            continue;
+/// Warn about every reference in an env file to a variable that is
+/// not set, which is left empty. Each is only warned about once.
+///
+/// # Arguments
+/// * `path` - Path of the env file.
+/// * `entries` - Variables the file defines.
+fn warn_undefined(path: &str, entries: &[EnvEntry]) {
+    for entry in entries.iter() {
+        for name in entry.undefined.iter() {
+            warn_once(format!("{} line {}: {} refers to {}, which is not set, so it is left empty.", path, entry.line, entry.key, name));
         }
+    }
+}
 
-        let variable = env_content[0].trim().to_string();
-        let value = env_content[1].trim().to_string();
-        var_map.insert(variable, value);
+/// Give a warning about an env file, unless it was already given.
+///
+/// # Arguments
+/// * `msg` - Warning to give.
+fn warn_once(msg: String) {
+    let mut warned = match ENV_WARNINGS.lock() {
+        Ok(warned) => warned,
+        Err(poisoned) => poisoned.into_inner()
+    };
+    if warned.insert(msg.clone()) {
+        warn_or_print(&msg);
     }
-    Ok(var_map)
 }
 
-/// Get the server PID file
+/// Read the variables of an env file into a HashMap. Lines that
+/// cannot be read are warned about and left out, so that one bad
+/// line does not keep every other variable from loading; `rd
+/// environment validate` fails on them instead. References to
+/// variables that are not set are warned about too.
 /// 
-/// # Example
+/// # Arguments
+/// * `path` - Path of the env file.
+/// * `below` - Variables of the files layered under it, as stored.
+/// 
+/// # Examples
 /// ```
-/// let file_path = get_server_pid_file().expect("Oh dear...");
+/// let variables = read_env_file(&env_file, &HashMap::new()).unwrap();
 /// ```
-#[inline]
-pub fn get_server_pid_file() -> Result<String, String> {
-    let mut pid_file = PathBuf::from(get_or_create_rd_home()?);
-    pid_file.push("server.pid");
-
-    if pid_file.exists() {
-        Ok(String::from(pid_file.to_str().unwrap()))
-    } else {
-        Err(String::from("Failed to retrieve server PID file."))
+fn read_env_file(path: &str, below: &HashMap<String, String>) -> Result<HashMap<String, String>, String> {
+    let (entries, errors) = parse_env_file(path, below)?;
+    for err in errors.iter() {
+        warn_once(format!("{} {} The line is left out; run `rd environment validate` for details.", path, err));
     }
+    warn_undefined(path, &entries);
+    Ok(entries.into_iter().map(|entry| (entry.key, entry.value)).collect())
 }
 
-/// Write the new process PID to the PID file.
+/// Check an env file for errors, logging each error along with
+/// duplicate definitions and references to undefined variables.
+/// Returns the number of errors found.
 /// 
 /// # Arguments
-/// * `pid` - New process ID for the file
+/// * `path` - Path of the env file.
+/// * `below` - Variables of the files layered under it, as stored.
 /// 
-/// # Example
+/// # Examples
 /// ```
-/// let file_path = write_server_pid_file(process_id).expect("Oh dear...");
+/// let error_count = validate_env_file(&env_file, &HashMap::new())?;
 /// ```
-#[inline]
-pub fn write_server_pid_file(pid: u32) -> Result<String, String> {
-    match get_server_pid_file() {
+fn validate_env_file(path: &str, below: &HashMap<String, String>) -> Result<usize, String> {
+    let (entries, errors) = parse_env_file(path, below)?;
+    errors.iter().for_each(|err| error!("{} {}", path, err));
 
-        // Overwrite the current file
-        Ok(path) => {
-            let mut pid_file = File::with_options().write(true).open(path.clone()).expect("Failed to open PID file to save new ID.");
-            pid_file.write_all(format!("{}", pid).as_bytes()).expect("Failed to write data to PID file.");
+    let mut first_lines: HashMap<&str, usize> = HashMap::new();
+    for entry in entries.iter() {
+        if let Some(first_line) = first_lines.get(entry.key.as_str()) {
+            warn!("{} line {}: {} is already set on line {}, which this overrides.", path, entry.line, entry.key, first_line);
+        } else {
+            first_lines.insert(&entry.key, entry.line);
+        }
+    }
+    warn_undefined(path, &entries);
 
-            match pid_file.sync_data() {
-                Ok(()) => Ok(path.clone()),
-                Err(err) => Err(err.to_string())
-            }
-        },
+    // Secrets are only any use if they can be decrypted
+    let mut secret_errors = 0;
+    for entry in entries.iter().filter(|entry| is_secret(&entry.value)) {
+        if let Err(msg) = decrypt_secret(&entry.key, &entry.value) {
+            error!("{} line {}: {}", path, entry.line, msg);
+            secret_errors += 1;
+        }
+    }
+
+    if errors.is_empty() && secret_errors == 0 {
+        info!("{}: {} variable(s), no errors.", path, entries.len());
+    }
+    Ok(errors.len() + secret_errors)
+}
+
+/// Get's a HashMap of the variables set only for a given instance,
+/// which take precedence over those in base.env and the active
+/// profile. Instances without an instance.env have no variables
+/// of their own.
+/// 
+/// # Arguments
+/// * `instance` - Name of the instance.
+/// 
+/// # Examples
+/// ```
+/// let variables = get_instance_env("staging").unwrap();
+/// ```
+pub fn get_instance_env(instance: &str) -> Result<HashMap<String, String>, String> {
+    let env_path = get_instance_env_path(instance)?;
+    if env_path.exists() {
+        read_env_file(env_path.to_str().unwrap(), &get_env_below_instance()?)
+    } else {
+        Ok(HashMap::new())
+    }
+}
+
+/// Get the variables of base.env with those of the active profile on
+/// top, as stored, which every instance.env is layered over. A profile
+/// that cannot be read is left out, having been reported on loading.
+fn get_env_below_instance() -> Result<HashMap<String, String>, String> {
+    let mut variables = get_env()?;
+    variables.extend(get_selected_profile_env(&variables).unwrap_or_default());
+    Ok(variables)
+}
+
+/// Get the path of an instance's instance.env. The file need not exist.
+/// 
+/// # Arguments
+/// * `instance` - Name of the instance.
+/// 
+/// # Examples
+/// ```
+/// let env_path = get_instance_env_path("staging").unwrap();
+/// ```
+pub fn get_instance_env_path(instance: &str) -> Result<PathBuf, String> {
+    let mut env_path = PathBuf::from(get_or_create_instance_home(instance)?);
+    env_path.push(INSTANCE_ENV_FILE);
+    Ok(env_path)
+}
 
-        // Create and write data to the file
-        Err(_) => {
-            let mut pid_file = PathBuf::from(get_or_create_rd_home()?);
-            pid_file.push("server.pid");
-            let mut new_pid_file = File::create(pid_file.clone()).expect("Failed to create PID file.");
+/// Get the path of a profile's env file, kept in the profiles
+/// directory of RD_HOME. The file need not exist.
+/// 
+/// # Arguments
+/// * `profile` - Name of the profile.
+/// 
+/// # Examples
+/// ```
+/// let env_path = get_profile_env_path("prod").unwrap();
+/// ```
+pub fn get_profile_env_path(profile: &str) -> Result<PathBuf, String> {
+    validate_profile_name(profile)?;
+    let mut env_path = PathBuf::from(get_or_create_rd_home()?);
+    env_path.push("profiles");
 
-            new_pid_file.write_all(format!("{}", pid).as_bytes()).expect("Failed to write data to PID file.");
-            match new_pid_file.sync_data() {
-                Ok(()) => Ok(String::from(pid_file.clone().to_str().unwrap())),
-                Err(err) => Err(err.to_string())
+    if !env_path.exists() {
+        create_dir(&env_path).map_err(|err| err.to_string())?;
+    }
+    env_path.push(format!("{}.env", profile));
+    Ok(env_path)
+}
+
+/// List the names of every profile, in order.
+/// 
+/// # Examples
+/// ```
+/// let profiles = list_profiles().unwrap();
+/// ```
+pub fn list_profiles() -> Result<Vec<String>, String> {
+    let mut profiles_dir = PathBuf::from(get_or_create_rd_home()?);
+    profiles_dir.push("profiles");
+
+    let mut profiles = Vec::new();
+    if profiles_dir.exists() {
+        for entry in read_dir(&profiles_dir).map_err(|err| err.to_string())? {
+            let path = entry.map_err(|err| err.to_string())?.path();
+            if path.extension().map_or(false, |extension| extension == "env") {
+                if let Some(profile) = path.file_stem() {
+                    profiles.push(profile.to_string_lossy().to_string());
+                }
             }
         }
     }
+    profiles.sort();
+    Ok(profiles)
+}
+
+/// Work out which profile is active: the one named by RD_PROFILE in
+/// the environment `rd` was started in (which `--profile` sets), or
+/// else the one chosen with `rd environment use` and kept in base.env.
+/// 
+/// # Arguments
+/// * `base_env` - Variables of base.env.
+/// 
+/// # Examples
+/// ```
+/// let profile = selected_profile(&get_env()?);
+/// ```
+pub fn selected_profile(base_env: &HashMap<String, String>) -> Option<String> {
+    process_env_var("RD_PROFILE").or_else(|| base_env.get("RD_PROFILE"))
+                                 .filter(|profile| !profile.is_empty() && profile.as_str() != BASE_PROFILE)
+                                 .cloned()
+}
+
+/// Get's a HashMap of the variables of a profile, which take
+/// precedence over those in base.env. The base profile has none.
+/// 
+/// # Arguments
+/// * `profile` - Name of the profile.
+/// 
+/// # Examples
+/// ```
+/// let variables = get_profile_env("prod").unwrap();
+/// ```
+pub fn get_profile_env(profile: &str) -> Result<HashMap<String, String>, String> {
+    if profile == BASE_PROFILE {
+        return Ok(HashMap::new());
+    }
+
+    let env_path = get_profile_env_path(profile)?;
+    if env_path.exists() {
+        read_env_file(env_path.to_str().unwrap(), &get_env()?)
+    } else {
+        Err(format!("Profile {} does not exist. Run `rd environment list` to see every profile.", profile))
+    }
+}
+
+/// Get's a HashMap of the variables of the active profile, if any.
+/// 
+/// # Arguments
+/// * `base_env` - Variables of base.env.
+/// 
+/// # Examples
+/// ```
+/// let variables = get_selected_profile_env(&get_env()?).unwrap();
+/// ```
+pub fn get_selected_profile_env(base_env: &HashMap<String, String>) -> Result<HashMap<String, String>, String> {
+    match selected_profile(base_env) {
+        Some(profile) => get_profile_env(&profile),
+        None => Ok(HashMap::new())
+    }
 }
 
-/// Delete the PID file and return the PID it contains
+/// Get the server PID file of an instance
+/// 
+/// # Arguments
+/// * `instance` - Name of the instance.
 /// 
 /// # Example
 /// ```
-/// let pid = remove_pid_file().expect("Oh dear...");
+/// let file_path = get_server_pid_file(DEFAULT_INSTANCE).expect("Oh dear...");
 /// ```
 #[inline]
-pub fn remove_pid_file() -> Result<u32, String> {
-    match get_server_pid_file() {
-        Ok(path) => {
-            // First read the file to get the PID then
-            // remove the file
-            let pid_string = read_to_string::<&String>(&path).expect("Failed ot read PID into memory.");
-            let pid = pid_string.trim().parse::<u32>().expect("Failed to parse PID into integer.");
-
-            match remove_file(path) {
-                Ok(()) => Ok(pid),
-                Err(err) => Err(err.to_string())
-            }
-        },
-        Err(err) => Err(err.to_string())
+pub fn get_server_pid_file(instance: &str) -> Result<String, String> {
+    let mut pid_file = PathBuf::from(get_or_create_instance_home(instance)?);
+    pid_file.push("server.pid");
+
+    if pid_file.exists() {
+        Ok(String::from(pid_file.to_str().unwrap()))
+    } else {
+        Err(String::from("Failed to retrieve server PID file."))
+    }
+}
+
+/// Write the new process PID to the PID file. The file is replaced
+/// whole, so no digits of an earlier PID can be left behind.
+/// 
+/// # Arguments
+/// * `instance` - Name of the instance.
+/// * `pid` - New process ID for the file
+/// 
+/// # Example
+/// ```
+/// let file_path = write_server_pid_file(DEFAULT_INSTANCE, process_id).expect("Oh dear...");
+/// ```
+#[inline]
+pub fn write_server_pid_file(instance: &str, pid: u32) -> Result<String, String> {
+    let mut pid_file = PathBuf::from(get_or_create_instance_home(instance)?);
+    pid_file.push("server.pid");
+
+    write_file_atomically(&pid_file, &pid.to_string())?;
+    Ok(String::from(pid_file.to_str().unwrap()))
+}
+
+/// Read the PID of the server from the PID file of an instance
+/// 
+/// # Arguments
+/// * `instance` - Name of the instance.
+/// 
+/// # Example
+/// ```
+/// let pid = read_server_pid(DEFAULT_INSTANCE).expect("Oh dear...");
+/// ```
+#[inline]
+pub fn read_server_pid(instance: &str) -> Result<u32, String> {
+    let path = get_server_pid_file(instance)?;
+    let pid_string = read_to_string::<&String>(&path).map_err(|err| err.to_string())?;
+    pid_string.trim().parse::<u32>().map_err(|_| format!("PID file {} does not contain a valid PID.", path))
+}
+
+/// Delete the PID file of an instance and return the PID it contains,
+/// if it contains a valid one. A corrupt file is deleted all the same,
+/// so it cannot keep the server from starting again.
+/// 
+/// # Arguments
+/// * `instance` - Name of the instance.
+/// 
+/// # Example
+/// ```
+/// let pid = remove_pid_file(DEFAULT_INSTANCE).expect("Oh dear...");
+/// ```
+#[inline]
+pub fn remove_pid_file(instance: &str) -> Result<Option<u32>, String> {
+    let path = get_server_pid_file(instance)?;
+
+    // First read the file to get the PID then
+    // remove the file
+    let pid = read_server_pid(instance).ok();
+    remove_file(&path).map_err(|err| format!("Failed to remove PID file {}: {}", path, err))?;
+    Ok(pid)
+}
+
+/// Get the path of the server state file, which records how the
+/// running server of an instance was launched. The file need not exist.
+/// 
+/// # Arguments
+/// * `instance` - Name of the instance.
+/// 
+/// # Example
+/// ```
+/// let state_path = get_server_state_path(DEFAULT_INSTANCE).expect("Oh dear...");
+/// ```
+#[inline]
+pub fn get_server_state_path(instance: &str) -> Result<PathBuf, String> {
+    let mut state_file = PathBuf::from(get_or_create_instance_home(instance)?);
+    state_file.push("server.state");
+    Ok(state_file)
+}
+
+/// Write the launch details of the server next to its PID file.
+/// 
+/// # Arguments
+/// * `instance` - Name of the instance.
+/// * `state` - Launch details keyed by name.
+/// 
+/// # Example
+/// ```
+/// write_server_state(DEFAULT_INSTANCE, &state).expect("Oh dear...");
+/// ```
+pub fn write_server_state(instance: &str, state: &HashMap<String, String>) -> Result<(), String> {
+    let contents = state.iter()
+                        .map(|(key, value)| format!("{}={}\n", key, quote_value(value)))
+                        .collect::<String>();
+    write_file_atomically(&get_server_state_path(instance)?, &contents)
+}
+
+/// Read the launch details of the server of an instance.
+/// 
+/// # Arguments
+/// * `instance` - Name of the instance.
+/// 
+/// # Example
+/// ```
+/// let state = read_server_state(DEFAULT_INSTANCE).expect("Oh dear...");
+/// ```
+pub fn read_server_state(instance: &str) -> Result<HashMap<String, String>, String> {
+    let state_file = get_server_state_path(instance)?;
+    if !state_file.exists() {
+        return Err(String::from("Failed to read server state file."));
     }
+    read_env_file(state_file.to_str().unwrap(), &HashMap::new())
 }
 
-/// Write a HashMap of assumed variables to the env file.
+/// Delete the server state file of an instance if there is one.
+/// 
+/// # Arguments
+/// * `instance` - Name of the instance.
+/// 
+/// # Example
+/// ```
+/// remove_server_state(DEFAULT_INSTANCE).expect("Oh dear...");
+/// ```
+pub fn remove_server_state(instance: &str) -> Result<(), String> {
+    let state_file = get_server_state_path(instance)?;
+    if state_file.exists() {
+        remove_file(state_file).map_err(|err| err.to_string())
+    } else {
+        Ok(())
+    }
+}
+
+/// Write a file by way of a temporary file renamed over it, so
+/// that readers see either the old contents or the new, never a
+/// mix of both, even if `rd` dies part way through.
+/// 
+/// # Arguments
+/// * `path` - Path of the file to write.
+/// * `contents` - New contents of the file.
 /// 
 /// # Examples
 /// ```
-/// if write_to_env(&variables).is_ok() {
-///     // Code here
-/// }
+/// write_file_atomically(Path::new(&env_file), "RD_PORT=8000\n")?;
 /// ```
-pub fn write_to_env(vars: &HashMap<String, String>) -> Result<(), String> {
-    let mut env_file = File::with_options().write(true).open(get_or_create_env_file()?).unwrap();
+pub fn write_file_atomically(path: &Path, contents: &str) -> Result<(), String> {
+    let file_name = match path.file_name() {
+        Some(name) => name.to_string_lossy().to_string(),
+        None => return Err(format!("Cannot write to {}.", path.display()))
+    };
+    let temp_path = path.with_file_name(format!(".{}.{}.tmp", file_name, process::id()));
+
+    let result = (|| -> io::Result<()> {
+        let mut temp_file = File::create(&temp_path)?;
+
+        // Keep the permissions of the file being replaced
+        if let Ok(existing) = metadata(path) {
+            temp_file.set_permissions(existing.permissions())?;
+        }
+        temp_file.write_all(contents.as_bytes())?;
+        temp_file.sync_all()?;
+        rename(&temp_path, path)
+    })();
+
+    if result.is_err() {
+        let _ = remove_file(&temp_path);
+    }
+    result.map_err(|err| format!("Failed to write {}: {}", path.display(), err))
+}
 
-    // Iteratively write all data
-    vars.iter().for_each(|(key, value)| {
-        env_file.write_all(format!("{}={}\n", key, value).as_bytes()).unwrap();
-    });
+/// Take an exclusive lock on an env file, held until the returned
+/// file is dropped. The lock is taken on a file beside the env file,
+/// since writing the env file replaces it.
+/// 
+/// # Arguments
+/// * `path` - Path of the env file.
+fn lock_env_file(path: &Path) -> Result<File, String> {
+    let mut lock_path = path.as_os_str().to_owned();
+    lock_path.push(".lock");
+
+    let lock_file = File::with_options().create(true).write(true).open(&lock_path).map_err(|err| err.to_string())?;
+    lock_file.lock_exclusive().map_err(|err| format!("Failed to lock {}: {}", path.display(), err))?;
+    Ok(lock_file)
+}
+
+/// Edit an env file while holding its lock, so concurrent edits
+/// are applied one after another rather than lost. The edit is
+/// given the current contents and returns the new contents, or
+/// nothing if the file should be left alone. Returns whether the
+/// file was written.
+/// 
+/// # Arguments
+/// * `path` - Path of the env file.
+/// * `edit` - Produces the new contents from the current ones.
+/// 
+/// # Examples
+/// ```
+/// update_env_file(&env_file, |contents| set_in_env(contents, "RD_PORT", "8080").map(Some))?;
+/// ```
+pub fn update_env_file<F>(path: &str, edit: F) -> Result<bool, String>
+    where F: FnOnce(&str) -> Result<Option<String>, String> {
+    let path = Path::new(path);
+    let _lock = lock_env_file(path)?;
 
-    // Make sure it all gets to disk
-    match env_file.sync_data() {
-        Ok(_) => Ok(()),
-        Err(err) => Err(err.to_string())
+    let contents = if path.exists() {
+        read_to_string(path).map_err(|err| err.to_string())?
+    } else {
+        String::new()
+    };
+    match edit(&contents)? {
+        Some(updated) => write_file_atomically(path, &updated).map(|_| true),
+        None => Ok(false)
     }
 }
 
+/// Set a variable in an env file, keeping the order and comments
+/// of everything else in it.
+/// 
+/// # Arguments
+/// * `path` - Path of the env file.
+/// * `key` - Name of the variable.
+/// * `value` - Value to set.
+/// 
+/// # Examples
+/// ```
+/// set_env_var(&get_or_create_env_file()?, "RD_PORT", "8080")?;
+/// ```
+pub fn set_env_var(path: &str, key: &str, value: &str) -> Result<(), String> {
+    update_env_file(path, |contents| set_in_env(contents, key, value).map(Some)).map(|_| ())
+}
+
+/// Remove a variable from an env file, keeping the order and comments
+/// of everything else in it. Returns whether the variable was there.
+/// 
+/// # Arguments
+/// * `path` - Path of the env file.
+/// * `key` - Name of the variable.
+/// 
+/// # Examples
+/// ```
+/// if !remove_env_var(&get_or_create_env_file()?, "RD_PORT")? {
+///     // Nothing to remove
+/// }
+/// ```
+pub fn remove_env_var(path: &str, key: &str) -> Result<bool, String> {
+    update_env_file(path, |contents| remove_from_env(contents, key))
+}
+
 /// Loads all environment variables in configuration into the
-/// the current process environment.
+/// the current process environment: those of base.env, then those
+/// of the active profile over them. Variables already set when
+/// `rd` was started are left alone.
+/// 
+/// # Arguments
+/// * `creating_profile` - Whether the command about to run creates the
+///   active profile, which then counts as empty until it does.
 /// 
 /// # Examples
 /// ```
-/// if set_environment().is_ok() {
+/// if set_environment(opts.cmd.creates_profile()).is_ok() {
 ///     // Do stuff here
 /// }
 /// ```
-pub fn set_environment() -> Result<(), String> {
-    match get_env() {
-        Ok(variables) => {
+pub fn set_environment(creating_profile: bool) -> Result<(), String> {
+    // Take note of the starting environment before changing it
+    lazy_static::initialize(&PROCESS_ENV);
 
-            // Iteratively set each variable
-            variables.iter().for_each(|(key, value)| {
-                env::set_var(key, value);
-            });
+    // Set the variables of base.env first, so the profile can refer to
+    // them. base.env stays loaded if the profile cannot be
+    let variables = get_env()?;
+    let profile = selected_profile(&variables);
+    let loaded = load_variables(variables);
+    let profile_loaded = match profile {
+        Some(profile) if creating_profile && get_profile_env_path(&profile).map_or(false, |path| !path.exists()) => Ok(()),
+        Some(profile) => get_profile_env(&profile).and_then(load_variables),
+        None => Ok(())
+    };
+    loaded.and(profile_loaded)
+}
+
+/// Set variables in the current process environment, other than
+/// those already set when `rd` was started. This is the only place
+/// secrets are decrypted; those that cannot be are left unset.
+fn load_variables(mut variables: HashMap<String, String>) -> Result<(), String> {
+    let revealed = reveal_secrets(&mut variables);
+    variables.iter()
+             .filter(|(key, _)| process_env_var(key).is_none())
+             .for_each(|(key, value)| {
+                 env::set_var(key, value);
+             });
+    revealed
+}
+
+/// Loads the variables of an instance's instance.env into the
+/// current process environment, over those of base.env and the
+/// active profile. Variables
+/// already set when `rd` was started are left alone.
+/// 
+/// # Arguments
+/// * `instance` - Name of the instance.
+/// 
+/// # Examples
+/// ```
+/// if set_instance_environment("staging").is_ok() {
+///     // Do stuff here
+/// }
+/// ```
+pub fn set_instance_environment(instance: &str) -> Result<(), String> {
+    load_variables(get_instance_env(instance)?)
+}
+
+/// Get the env file edited by `set-env` and `remove-env`: that of
+/// the profile given with `--profile`, or else base.env.
+fn get_target_env_file(profile: Option<&str>) -> Result<String, String> {
+    match profile {
+        Some(profile) => get_profile_env_path(profile).map(|path| String::from(path.to_str().unwrap())),
+        None => get_or_create_env_file()
+    }
+}
+
+/// Read the value of a secret from standard input, up to the end
+/// of the first line. When typed at a terminal, the value is not echoed.
+fn read_secret_value() -> Result<String, String> {
+    let interactive = unsafe { libc::isatty(libc::STDIN_FILENO) == 1 };
+    let mut saved: libc::termios = unsafe { std::mem::zeroed() };
+    let hidden = interactive && unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut saved) == 0 };
+    if interactive {
+        eprint!("Value: ");
+    }
+    if hidden {
+        let mut no_echo = saved;
+        no_echo.c_lflag &= !libc::ECHO;
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &no_echo) };
+    }
+
+    let mut value = String::new();
+    let read = io::stdin().read_line(&mut value);
+    if hidden {
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &saved) };
+        eprintln!();
+    }
+    read.map_err(|err| err.to_string())?;
+
+    let value = value.trim_end_matches(|c| c == '\n' || c == '\r').to_string();
+    if value.is_empty() {
+        Err(String::from("No value given for the secret."))
+    } else {
+        Ok(value)
+    }
+}
+
+/// Collapse repeated variables into one, keeping the place of the
+/// first and the value of the last, as loading them would.
+fn collapse_variables(variables: Vec<(String, String)>) -> Vec<(String, String)> {
+    let mut collapsed: Vec<(String, String)> = Vec::new();
+    for (key, value) in variables {
+        match collapsed.iter_mut().find(|(existing, _)| *existing == key) {
+            Some(existing) => existing.1 = value,
+            None => collapsed.push((key, value))
+        }
+    }
+    collapsed
+}
+
+/// Export the variables of base.env, or of a profile, in file order.
+/// Secrets stay encrypted, so they only load where the key file does.
+/// Dotenv exports keep references to other variables as written; the
+/// other formats have no way to refer, so they get the values.
+/// 
+/// # Arguments
+/// * `profile` - Profile to export instead of base.env, if any.
+/// * `cmd` - Options of the export.
+fn export_env(profile: Option<&str>, cmd: &ExportEnvCLI) -> Result<(), String> {
+    let path = get_target_env_file(profile)?;
+    let below = if profile.is_some() { get_env()? } else { HashMap::new() };
+    let (entries, errors) = parse_env_file(&path, &below)?;
+    if let Some(err) = errors.first() {
+        return Err(format!("{} {}. Run `rd environment validate` for details.", path, err));
+    }
+
+    let entries = entries.into_iter()
+                         .filter(|entry| !(cmd.exclude_secrets && is_secret(&entry.value)))
+                         .collect::<Vec<EnvEntry>>();
+    let variables = collapse_variables(entries.iter().map(|entry| (entry.key.clone(), entry.value.clone())).collect());
+    let exported = match cmd.format {
+        EnvFormat::Dotenv => export_definitions(&collapse_variables(
+            entries.into_iter().map(|entry| (entry.key, entry.written)).collect()
+        )),
+        format => export_variables(&variables, format)?
+    };
 
-            Ok(())
+    // Log records go to stderr when exporting to standard output,
+    // so only the variables can end up wherever it is redirected
+    match &cmd.output {
+        Some(output) => {
+            write_file_atomically(output, &exported)?;
+            info!("Exported {} variable(s) to {}.", variables.len(), output.display());
+            if variables.iter().any(|(_, value)| is_secret(value)) {
+                info!("Secrets are exported encrypted. Copy secret.key from RD_HOME along with them, or pass --exclude-secrets.");
+            }
         },
-        Err(msg) => Err(msg)
+        None => print!("{}", exported)
+    }
+    Ok(())
+}
+
+/// Import variables into base.env, or into a profile, in a single
+/// write. RD_HOME is never imported, since it differs by machine.
+/// Nothing is expanded on the way in: references are kept as
+/// written, to be looked up whenever the file is loaded.
+/// 
+/// # Arguments
+/// * `profile` - Profile to import into instead of base.env, if any.
+/// * `cmd` - Options of the import.
+fn import_env(profile: Option<&str>, cmd: &ImportEnvCLI) -> Result<(), String> {
+    let text = if cmd.file == Path::new("-") {
+        let mut text = String::new();
+        io::stdin().read_to_string(&mut text).map_err(|err| err.to_string())?;
+        text
+    } else {
+        read_to_string(&cmd.file).map_err(|err| format!("Failed to read {}: {}", cmd.file.display(), err))?
+    };
+    let format = cmd.format.unwrap_or_else(|| EnvFormat::from_path(&cmd.file));
+
+    let mut definitions = collapse_variables(
+        import_definitions(&text, format).map_err(|msg| format!("Failed to import {}: {}", cmd.file.display(), msg))?
+    );
+
+    // Read back as a file of their own for the values, which tell secrets apart
+    let (entries, _) = parse_env(&export_definitions(&definitions), &|_| None);
+    let values = entries.into_iter()
+                        .map(|entry| (entry.key, entry.value))
+                        .collect::<HashMap<String, String>>();
+    let imports_secret = |key: &str| values.get(key).map_or(false, |value| is_secret(value));
+    definitions.retain(|(key, _)| key != "RD_HOME" && !(cmd.exclude_secrets && imports_secret(key)));
+
+    // Definitions written differently match if they read the same and refer to nothing
+    let matches = |key: &str, current: &EnvEntry, written: &str| {
+        current.written == written || (!current.written.contains('$') && !written.contains('$') &&
+                                       values.get(key).map_or(false, |value| same_value(key, &current.value, value)))
+    };
+
+    let (mut added, mut overwritten, mut kept, mut unchanged) = (0, 0, 0, 0);
+    update_env_file(&get_target_env_file(profile)?, |contents| {
+        let (entries, _) = parse_env(contents, &|_| None);
+        let existing = entries.into_iter()
+                              .map(|entry| (entry.key.clone(), entry))
+                              .collect::<HashMap<String, EnvEntry>>();
+
+        let conflicts = definitions.iter()
+                                   .filter(|(key, written)| existing.get(key).map_or(false, |current| !matches(key, current, written)))
+                                   .map(|(key, _)| key.as_str())
+                                   .collect::<Vec<&str>>();
+        if cmd.strategy == MergeStrategy::FailOnConflict && !conflicts.is_empty() {
+            return Err(format!(
+                "Imported nothing, since these are already set to something else: {}. Pass --strategy overwrite or keep-existing to import anyway.",
+                conflicts.join(", ")
+            ));
+        }
+
+        let mut updated = contents.to_string();
+        for (key, written) in definitions.iter() {
+            match existing.get(key) {
+                Some(current) if matches(key, current, written) => unchanged += 1,
+                Some(_) if cmd.strategy == MergeStrategy::KeepExisting => kept += 1,
+                current => {
+                    updated = set_definition_in_env(&updated, key, written)?;
+                    if current.is_some() {
+                        overwritten += 1;
+                    } else {
+                        added += 1;
+                    }
+                }
+            }
+        }
+        Ok(if added + overwritten > 0 { Some(updated) } else { None })
+    })?;
+
+    info!("Imported {} new and {} changed variable(s); {} kept as they were and {} already matching.", added, overwritten, kept, unchanged);
+    for (key, value) in values.iter().filter(|(key, value)| is_secret(value) && definitions.iter().any(|(imported, _)| imported == *key)) {
+        if decrypt_secret(key, value).is_err() {
+            warn!("Secret {} was encrypted with another key file, and will not load until that key file is copied to RD_HOME.", key);
+        }
+    }
+    Ok(())
+}
+
+/// Get the variables of base.env with those of a profile on top,
+/// leaving out the choice of profile itself.
+fn get_layered_env(profile: &str) -> Result<HashMap<String, String>, String> {
+    let mut variables = get_env()?;
+    variables.extend(get_profile_env(profile)?);
+    variables.remove("RD_PROFILE");
+    Ok(variables)
+}
+
+/// Get the variables of base.env, with those of a profile on top if
+/// given, along with the name of the file each value is set in.
+/// Variables are sorted by name.
+///
+/// # Arguments
+/// * `profile` - Profile to layer on top of base.env, if any.
+///
+/// # Examples
+/// ```
+/// let variables = get_env_with_sources(Some("prod"))?;
+/// ```
+fn get_env_with_sources(profile: Option<&str>) -> Result<Vec<(String, String, String)>, String> {
+    let mut layers = vec![(String::from("base.env"), get_env()?)];
+    if let Some(profile) = profile.filter(|profile| *profile != BASE_PROFILE) {
+        layers.push((format!("{}.env", profile), get_profile_env(profile)?));
+    }
+
+    let mut variables: HashMap<String, (String, String)> = HashMap::new();
+    for (source, layer) in layers {
+        for (key, value) in layer {
+            variables.insert(key, (value, source.clone()));
+        }
+    }
+
+    let mut variables = variables.into_iter()
+                                 .map(|(key, (value, source))| (key, value, source))
+                                 .collect::<Vec<(String, String, String)>>();
+    variables.sort();
+    Ok(variables)
+}
+
+/// Whether two stored values of a variable are the same. Each
+/// encryption of a secret differs, so secrets are compared by
+/// what they decrypt to.
+fn same_value(key: &str, first: &str, second: &str) -> bool {
+    if first == second {
+        return true;
+    }
+    match (decrypt_secret(key, first), decrypt_secret(key, second)) {
+        (Ok(first), Ok(second)) => first == second,
+        _ => false
+    }
+}
+
+/// Log how the variables of one profile differ from another's,
+/// each taken with base.env underneath.
+/// 
+/// # Arguments
+/// * `from` - Profile to compare from.
+/// * `to` - Profile to compare to.
+fn diff_profiles(from: &str, to: &str) -> Result<(), String> {
+    let from_variables = get_layered_env(from)?;
+    let to_variables = get_layered_env(to)?;
+
+    let mut keys = from_variables.keys().chain(to_variables.keys()).collect::<Vec<&String>>();
+    keys.sort();
+    keys.dedup();
+
+    let mut differences = 0;
+    for key in keys {
+        match (from_variables.get(key), to_variables.get(key)) {
+            (Some(from_value), Some(to_value)) if !same_value(key, from_value, to_value) => {
+                info!("~ {}: {} -> {}", key, masked(from_value), masked(to_value))
+            },
+            (Some(from_value), None) => info!("- {}={}", key, masked(from_value)),
+            (None, Some(to_value)) => info!("+ {}={}", key, masked(to_value)),
+            _ => continue
+        }
+        differences += 1;
+    }
+
+    if differences == 0 {
+        info!("Profiles {} and {} set the same variables.", from, to);
+    }
+    Ok(())
+}
+
+/// Replace `rd` with a command, run in the environment the dataserver
+/// of an instance sees: base.env, the active profile and instance.env,
+/// with secrets decrypted. As the command takes over the process, its
+/// exit code and any signals sent to it need no passing on.
+/// 
+/// Only returns if the command could not be run, with the exit
+/// code a shell would give: 127 if it was not found, else 126.
+/// 
+/// # Arguments
+/// * `cmd` - Command to run and the instance to run it for.
+fn exec_command(cmd: &ExecCLI) -> i32 {
+    let instance = cmd.instance.clone().unwrap_or_else(current_instance);
+    if let Err(msg) = validate_instance_name(&instance).and_then(|_| set_instance_environment(&instance)) {
+        error!("Failed to load environment of instance {}: {}", instance, msg);
+        return 1;
+    }
+    env::set_var("RD_INSTANCE", &instance);
+
+    let err = process::Command::new(&cmd.command[0]).args(&cmd.command[1..]).exec();
+    error!("Failed to run {}: {}", cmd.command[0], err);
+    if err.kind() == io::ErrorKind::NotFound {
+        127
+    } else {
+        126
     }
 }
 
+/// Write the statements a shell evaluates to take on the environment
+/// `exec` runs commands in: base.env, the active profile and the
+/// instance.env of an instance, with secrets decrypted. The choice of
+/// profile is left out, so that `rd environment use` still takes
+/// effect in the shell afterwards.
+/// 
+/// # Arguments
+/// * `cmd` - Shell to write for and which variables to include.
+fn shell_init_script(cmd: &ShellInitCLI) -> Result<String, String> {
+    let instance = cmd.instance.clone().unwrap_or_else(current_instance);
+    validate_instance_name(&instance)?;
+    let profile = selected_profile(&get_env()?).unwrap_or_else(|| String::from(BASE_PROFILE));
+    let mut variables = get_layered_env(&profile)?;
+    variables.extend(get_instance_env(&instance)?);
+    variables.insert(String::from("RD_INSTANCE"), instance);
+    if cmd.exclude_secrets {
+        variables.retain(|_, value| !is_secret(value));
+    }
+    if let Err(msg) = reveal_secrets(&mut variables) {
+        warn!("{}", msg);
+    }
+
+    let mut variables = variables.into_iter().collect::<Vec<(String, String)>>();
+    variables.sort();
+    shell_exports(&variables, cmd.shell)
+}
+
 /// Run a given environment command from the CLI
 /// 
 /// # Arguments
 /// * `command` - The command to run
+/// * `profile` - Profile given with `--profile`, if any.
 /// 
 /// # Examples
 /// ```
-/// run_environment_command(&command_from_cli);
+/// run_environment_command(&command_from_cli, None);
 /// ```
 #[inline]
-pub fn run_environment_command(command: &EnvironmentCLI) {
+pub fn run_environment_command(command: &EnvironmentCLI, profile: Option<&str>) {
     match &command.cmd {
 
         // Get current environment
         EnvironmentCommand::GetEnv => {
-            let variables = match get_env() {
+            let variables = match get_env_with_sources(profile) {
                 Ok(v) => v,
                 Err(msg) => {
                     error!("{}", msg);
-                    HashMap::new()
+                    Vec::new()
                 }
             };
 
-            // Write them all out to standard out
-            variables.iter().for_each(|(k, v)| info!("{}: {}", k, v));
+            // Write them all out to standard out, without giving away secrets.
+            // A profile is shown on top of base.env, marking where each value is set
+            variables.iter().for_each(|(k, v, source)| match profile {
+                Some(_) => info!("{}: {} ({})", k, masked(v), source),
+                None => info!("{}: {}", k, masked(v))
+            });
         },
 
         // Set a new variable
         EnvironmentCommand::SetEnv(new_var) => {
-            if let Ok(mut variables) = get_env() {
-                // Add the new variable
-                variables.insert(new_var.key.clone(), new_var.value.clone());
+            match get_target_env_file(profile).and_then(|path| set_env_var(&path, &new_var.key, &new_var.value)) {
+                Ok(()) => info!("Successfully set environment variable"),
+                Err(msg) => error!("{}", msg)
+            }
+        },
+
+        // Remove an environment variable
+        EnvironmentCommand::RemoveEnv(remove_var) => {
+            match get_target_env_file(profile).and_then(|path| remove_env_var(&path, &remove_var.key)) {
+                Ok(true) => info!("Successfully removed environment variable"),
+                Ok(false) => warn!("{} is not set.", remove_var.key),
+                Err(msg) => error!("{}", msg)
+            }
+        },
+
+        // Set a new secret
+        EnvironmentCommand::SetSecret(new_secret) => {
+            let value = match &new_secret.value {
+                Some(value) => Ok(value.clone()),
+                None => read_secret_value()
+            };
+            let result = value.and_then(|value| encrypt_secret(&new_secret.key, &value))
+                              .and_then(|stored| set_env_var(&get_target_env_file(profile)?, &new_secret.key, &stored));
+            match result {
+                Ok(()) => info!("Successfully set secret"),
+                Err(msg) => error!("{}", msg)
+            }
+        },
 
-                match write_to_env(&variables) {
-                    Ok(()) => info!("Successfully set environment variable"),
+        // Reveal a secret
+        EnvironmentCommand::GetSecret(cmd) => {
+            let variables = profile.map_or_else(get_env, get_profile_env);
+            match variables.and_then(|variables| variables.get(&cmd.key).cloned().ok_or_else(|| format!("{} is not set.", cmd.key))) {
+                Ok(stored) if !is_secret(&stored) => error!("{} is not stored as a secret. Use get-env to see it.", cmd.key),
+                Ok(stored) => match decrypt_secret(&cmd.key, &stored) {
+                    // Printed bare so scripts can capture it
+                    Ok(value) => println!("{}", value),
                     Err(msg) => error!("{}", msg)
-                }
+                },
+                Err(msg) => error!("{}", msg)
+            }
+        },
 
+        // Export variables
+        EnvironmentCommand::Export(cmd) => {
+            if let Err(msg) = export_env(profile, cmd) {
+                error!("{}", msg);
+            }
+        },
+
+        // Import variables
+        EnvironmentCommand::Import(cmd) => {
+            if let Err(msg) = import_env(profile, cmd) {
+                error!("{}", msg);
+            }
+        },
+
+        // Switch profiles
+        EnvironmentCommand::Use(cmd) => {
+            let result = if cmd.name == BASE_PROFILE {
+                get_or_create_env_file().and_then(|path| remove_env_var(&path, "RD_PROFILE")).map(|_| ())
             } else {
-                error!("There was an error retrieving environment configuration.");
+                get_profile_env_path(&cmd.name).and_then(|path| {
+                    if !path.exists() {
+                        return Err(format!(
+                            "Profile {} does not exist. Create it with `rd --profile {} environment set-env`.",
+                            cmd.name,
+                            cmd.name
+                        ));
+                    }
+                    set_env_var(&get_or_create_env_file()?, "RD_PROFILE", &cmd.name)
+                })
+            };
+
+            match result {
+                Ok(()) => {
+                    info!("Now using profile {}.", cmd.name);
+                    if let Some(profile) = process_env_var("RD_PROFILE") {
+                        warn!("RD_PROFILE is set to {} in the environment, which takes precedence.", profile);
+                    }
+                },
+                Err(msg) => error!("{}", msg)
             }
         },
 
-        // Remove an environment variable
-        EnvironmentCommand::RemoveEnv(remove_var) => {
-            if let Ok(mut variables) = get_env() {
-                // Add the new variable
-                variables.remove(&remove_var.key);
+        // List profiles
+        EnvironmentCommand::List => {
+            let active = get_env().map(|variables| selected_profile(&variables)).unwrap_or(None);
+            match list_profiles() {
+                Ok(profiles) if profiles.is_empty() => info!("No profiles. Create one with `rd --profile <name> environment set-env`."),
+                Ok(profiles) => profiles.iter().for_each(|profile| {
+                    let marker = if active.as_ref() == Some(profile) { "*" } else { " " };
+                    info!("{} {}", marker, profile);
+                }),
+                Err(msg) => error!("{}", msg)
+            }
+        },
 
-                match write_to_env(&variables) {
-                    Ok(()) => info!("Successfully removed environment variable"),
-                    Err(msg) => error!("{}", msg)
+        // Compare profiles
+        EnvironmentCommand::Diff(cmd) => {
+            if let Err(msg) = diff_profiles(&cmd.from, &cmd.to) {
+                error!("{}", msg);
+            }
+        },
+
+        // Run a command in the environment
+        EnvironmentCommand::Exec(cmd) => process::exit(exec_command(cmd)),
+
+        // Set variables in a shell
+        EnvironmentCommand::ShellInit(cmd) => {
+            // Standard output is evaluated by the shell, so only
+            // statements may go there; log records go to stderr
+            match shell_init_script(cmd) {
+                Ok(script) => print!("{}", script),
+                Err(msg) => {
+                    error!("{}", msg);
+                    process::exit(1);
                 }
+            }
+        },
 
-            } else {
-                error!("There was an error retrieving environment configuration.");
+        // Check every env file
+        EnvironmentCommand::Validate => {
+            // Each file is checked along with the variables layered under it
+            let base_env = get_env().unwrap_or_default();
+            let mut paths = vec![get_or_create_env_file().map(|path| (path, HashMap::new()))];
+            match list_profiles() {
+                Ok(profiles) => {
+                    profiles.iter()
+                            .filter_map(|profile| get_profile_env_path(profile).ok())
+                            .for_each(|path| paths.push(Ok((String::from(path.to_str().unwrap()), base_env.clone()))));
+                },
+                Err(msg) => paths.push(Err(msg))
+            }
+            match list_instances() {
+                Ok(instances) => {
+                    let below = get_env_below_instance().unwrap_or_default();
+                    instances.iter()
+                             .filter_map(|instance| get_instance_env_path(instance).ok())
+                             .filter(|path| path.exists())
+                             .for_each(|path| paths.push(Ok((String::from(path.to_str().unwrap()), below.clone()))));
+                },
+                Err(msg) => paths.push(Err(msg))
+            }
+
+            let mut error_count = 0;
+            for path in paths {
+                match path.and_then(|(path, below)| validate_env_file(&path, &below)) {
+                    Ok(count) => error_count += count,
+                    Err(msg) => {
+                        error!("{}", msg);
+                        error_count += 1;
+                    }
+                }
+            }
+
+            // Let scripts tell whether the files are fine
+            if error_count > 0 {
+                error!("Found {} error(s).", error_count);
+                std::process::exit(1);
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::write;
+    use crate::cli::exchange::import_variables;
+    use crate::testing::TestRdHome;
+
+    /// Options of `shell-init` for a shell and instance.
+    fn shell_init_cli(shell: Shell, instance: &str) -> ShellInitCLI {
+        ShellInitCLI {
+            shell,
+            instance: Some(instance.to_string()),
+            exclude_secrets: false
+        }
+    }
+
+    #[test]
+    fn shell_init_sets_what_exec_would() {
+        let _home = TestRdHome::new();
+        write(get_or_create_env_file().unwrap(), "RD_PORT=8000\nRD_NAME=\"it's \\\"quoted\\\" $UNSET_IN_TEST\"\nRD_LAYER=base\n").unwrap();
+        write(get_instance_env_path("shell-init-test").unwrap(), "RD_LAYER=instance\n").unwrap();
+
+        // Only export statements, which read back as the layered variables
+        let script = shell_init_script(&shell_init_cli(Shell::Bash, "shell-init-test")).unwrap();
+        assert!(script.lines().all(|line| line.starts_with("export ")), "{}", script);
+        let variables = import_variables(&script, EnvFormat::Shell).unwrap().into_iter().collect::<HashMap<String, String>>();
+        assert_eq!(variables.get("RD_PORT").map(String::as_str), Some("8000"));
+        assert_eq!(variables.get("RD_NAME").map(String::as_str), Some("it's \"quoted\" "));
+        assert_eq!(variables.get("RD_LAYER").map(String::as_str), Some("instance"));
+        assert_eq!(variables.get("RD_INSTANCE").map(String::as_str), Some("shell-init-test"));
+
+        let script = shell_init_script(&shell_init_cli(Shell::Fish, "shell-init-test")).unwrap();
+        assert!(script.lines().all(|line| line.starts_with("set -gx ")), "{}", script);
+        assert!(script.contains("set -gx RD_NAME 'it\\'s \"quoted\" '\n"), "{}", script);
+
+        assert!(shell_init_script(&shell_init_cli(Shell::Bash, "../elsewhere")).is_err());
+    }
+
+    #[test]
+    fn bad_lines_leave_the_rest_of_the_file_loaded() {
+        let _home = TestRdHome::new();
+        let env_file = get_or_create_env_file().unwrap();
+        write(&env_file, "RD_PORT=8000\nthis is not a variable\nRD_WORKERS=4\n").unwrap();
+
+        let variables = get_env().unwrap();
+        assert_eq!(variables.get("RD_PORT").map(String::as_str), Some("8000"));
+        assert_eq!(variables.get("RD_WORKERS").map(String::as_str), Some("4"));
+        assert_eq!(variables.len(), 2);
+        assert_eq!(validate_env_file(&env_file, &HashMap::new()), Ok(1));
+    }
+
+    #[test]
+    fn tests_have_homes_of_their_own() {
+        let home = TestRdHome::new();
+        assert_eq!(PathBuf::from(get_or_create_rd_home().unwrap()), home.path());
+        assert!(std::thread::spawn(get_or_create_rd_home).join().unwrap().is_err());
+    }
+
+    #[test]
+    fn secrets_in_the_same_file_are_not_interpolated() {
+        let _home = TestRdHome::new();
+        let env_file = get_or_create_env_file().unwrap();
+        let secret = encrypt_secret("RD_TEST_DB_PASS", "hunter2").unwrap();
+        write(&env_file, format!("RD_TEST_DB_PASS={}\nRD_TEST_DB_URL=postgres://u:${{RD_TEST_DB_PASS}}@h\n", secret)).unwrap();
+
+        let variables = get_env().unwrap();
+        assert_eq!(variables.get("RD_TEST_DB_PASS"), Some(&secret));
+        assert_eq!(variables.get("RD_TEST_DB_URL"), None);
+        assert_eq!(validate_env_file(&env_file, &HashMap::new()), Ok(1));
+    }
+
+    #[test]
+    fn secrets_of_base_env_are_not_interpolated_into_profiles() {
+        let _home = TestRdHome::new();
+        write(get_or_create_env_file().unwrap(), "RD_TEST_PROFILE_PASS=secret:v1:00\n").unwrap();
+        write(get_profile_env_path("prod").unwrap(), "RD_TEST_PROFILE_URL=postgres://u:${RD_TEST_PROFILE_PASS}@h\n").unwrap();
+
+        // As once loaded, with the secret decrypted into the environment
+        env::set_var("RD_TEST_PROFILE_PASS", "hunter2");
+        let variables = get_profile_env("prod");
+        env::remove_var("RD_TEST_PROFILE_PASS");
+        assert_eq!(variables.unwrap().get("RD_TEST_PROFILE_URL"), None);
+    }
+
+    #[test]
+    fn imports_and_exports_keep_references() {
+        let home = TestRdHome::new();
+        let env_file = get_or_create_env_file().unwrap();
+        write(&env_file, "RD_PORT=8000\n").unwrap();
+        let import_file = home.path().join("import.env");
+        write(&import_file, "LEAK=${RD_TEST_IMPORT_KEY}\nURL=\"http://${RD_HOST}:$RD_PORT\"\n").unwrap();
+        let import = ImportEnvCLI {
+            file: import_file,
+            format: None,
+            strategy: MergeStrategy::FailOnConflict,
+            exclude_secrets: false
+        };
+
+        // As once loaded, with a secret decrypted into the environment
+        env::set_var("RD_TEST_IMPORT_KEY", "hunter2");
+        let imported = import_env(None, &import);
+        env::remove_var("RD_TEST_IMPORT_KEY");
+        imported.unwrap();
+        let contents = read_to_string(&env_file).unwrap();
+        assert_eq!(contents, "RD_PORT=8000\nLEAK=${RD_TEST_IMPORT_KEY}\nURL=\"http://${RD_HOST}:$RD_PORT\"\n");
+
+        let output = home.path().join("export.env");
+        export_env(None, &ExportEnvCLI { format: EnvFormat::Dotenv, output: Some(output.clone()), exclude_secrets: false }).unwrap();
+        assert_eq!(read_to_string(&output).unwrap(), contents);
+
+        // Importing the same again changes nothing, even if it would expand differently
+        import_env(None, &import).unwrap();
+        assert_eq!(read_to_string(&env_file).unwrap(), contents);
+    }
+
+    #[test]
+    fn profiles_are_shown_on_top_of_base_env() {
+        let _home = TestRdHome::new();
+        write(get_or_create_env_file().unwrap(), "RD_PORT=8000\nRD_LAYER=base\n").unwrap();
+        write(get_profile_env_path("prod").unwrap(), "RD_LAYER=prod\n").unwrap();
+
+        let variable = |key: &str, value: &str, source: &str| (key.to_string(), value.to_string(), source.to_string());
+        let layered = get_env_with_sources(Some("prod")).unwrap();
+        assert!(layered.contains(&variable("RD_PORT", "8000", "base.env")), "{:?}", layered);
+        assert!(layered.contains(&variable("RD_LAYER", "prod", "prod.env")), "{:?}", layered);
+        assert!(get_env_with_sources(None).unwrap().contains(&variable("RD_LAYER", "base", "base.env")));
+        assert!(get_env_with_sources(Some("staging")).is_err());
+    }
+}
@@ -0,0 +1,428 @@
+/**
+* This file contains the formats environment configuration
+* can be exported to and imported from, so it can be moved
+* between machines: dotenv, JSON and shell `export` lines.
+* It also writes the statements shells evaluate to take on
+* the environment.
+*/
+use std::{
+    path::Path,
+    str::FromStr
+};
+use serde_json::{
+    Map,
+    Value
+};
+use structopt::clap::Shell;
+
+// Local imports
+use crate::cli::dotenv::{
+    format_definition,
+    format_env_line,
+    parse_env,
+    quote_value
+};
+
+/// Format of exported environment configuration.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EnvFormat {
+    /// The format of base.env itself.
+    Dotenv,
+
+    /// A single JSON object of strings.
+    Json,
+
+    /// `export KEY='value'` lines, for sourcing into a shell.
+    Shell
+}
+
+impl FromStr for EnvFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<EnvFormat, String> {
+        match format.to_lowercase().as_str() {
+            "dotenv" | "env" => Ok(EnvFormat::Dotenv),
+            "json" => Ok(EnvFormat::Json),
+            "shell" | "sh" => Ok(EnvFormat::Shell),
+            other => Err(format!("Unknown format {}. Use dotenv, json or shell.", other))
+        }
+    }
+}
+
+impl EnvFormat {
+
+    /// Guess the format of a file from its extension, taking
+    /// anything unrecognised to be dotenv.
+    ///
+    /// # Arguments
+    /// * `path` - Path of the file.
+    pub fn from_path(path: &Path) -> EnvFormat {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("json") => EnvFormat::Json,
+            Some("sh") | Some("bash") | Some("zsh") => EnvFormat::Shell,
+            _ => EnvFormat::Dotenv
+        }
+    }
+}
+
+/// What an import does with variables that are already set to
+/// a different value.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MergeStrategy {
+    /// Replace them with the imported value.
+    Overwrite,
+
+    /// Leave them as they are.
+    KeepExisting,
+
+    /// Import nothing at all.
+    FailOnConflict
+}
+
+impl FromStr for MergeStrategy {
+    type Err = String;
+
+    fn from_str(strategy: &str) -> Result<MergeStrategy, String> {
+        match strategy.to_lowercase().as_str() {
+            "overwrite" => Ok(MergeStrategy::Overwrite),
+            "keep-existing" => Ok(MergeStrategy::KeepExisting),
+            "fail-on-conflict" => Ok(MergeStrategy::FailOnConflict),
+            other => Err(format!("Unknown strategy {}. Use overwrite, keep-existing or fail-on-conflict.", other))
+        }
+    }
+}
+
+/// Quote a value for a POSIX shell, which takes everything inside
+/// single quotes literally except the closing quote itself.
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// Quote a value for fish, which takes everything inside single
+/// quotes literally except escaped quotes and backslashes.
+fn fish_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+/// Write the statements a shell evaluates to set variables in
+/// its own environment, in the order given.
+///
+/// # Arguments
+/// * `variables` - Variables to set.
+/// * `shell` - Shell that evaluates the statements.
+///
+/// # Examples
+/// ```
+/// let script = shell_exports(&variables, Shell::Fish)?;
+/// ```
+pub fn shell_exports(variables: &[(String, String)], shell: Shell) -> Result<String, String> {
+    match shell {
+        Shell::Bash | Shell::Zsh => export_variables(variables, EnvFormat::Shell),
+        Shell::Fish => Ok(variables.iter()
+                                   .map(|(key, value)| format!("set -gx {} {}\n", key, fish_quote(value)))
+                                   .collect()),
+        other => Err(format!("Shell {} is not supported. Use bash, zsh or fish.", other))
+    }
+}
+
+/// Write variables out in the given format. JSON has its keys
+/// sorted; the other formats keep the order given.
+///
+/// # Arguments
+/// * `variables` - Variables to write, in order.
+/// * `format` - Format to write them in.
+///
+/// # Examples
+/// ```
+/// let exported = export_variables(&variables, EnvFormat::Json)?;
+/// ```
+pub fn export_variables(variables: &[(String, String)], format: EnvFormat) -> Result<String, String> {
+    match format {
+        EnvFormat::Dotenv => Ok(variables.iter()
+                                         .map(|(key, value)| format!("{}\n", format_env_line(key, value, false, None)))
+                                         .collect()),
+        EnvFormat::Shell => Ok(variables.iter()
+                                        .map(|(key, value)| format!("export {}={}\n", key, shell_quote(value)))
+                                        .collect()),
+        EnvFormat::Json => {
+            let object = variables.iter()
+                                  .map(|(key, value)| (key.clone(), Value::String(value.clone())))
+                                  .collect::<Map<String, Value>>();
+            serde_json::to_string_pretty(&object).map(|json| json + "\n").map_err(|err| err.to_string())
+        }
+    }
+}
+
+/// Write definitions out as a dotenv file, each value as it is
+/// written so that references to other variables are kept.
+///
+/// # Arguments
+/// * `definitions` - Names and written values of the variables, in order.
+///
+/// # Examples
+/// ```
+/// let exported = export_definitions(&definitions);
+/// ```
+pub fn export_definitions(definitions: &[(String, String)]) -> String {
+    definitions.iter()
+               .map(|(key, written)| format!("{}\n", format_definition(key, written, false, None)))
+               .collect()
+}
+
+/// Read variables from a JSON object. Numbers and booleans are
+/// taken as they are written; anything else nested is refused.
+fn import_json(text: &str) -> Result<Vec<(String, String)>, String> {
+    let object = match serde_json::from_str::<Value>(text).map_err(|err| err.to_string())? {
+        Value::Object(object) => object,
+        _ => return Err(String::from("Expected a JSON object of variables."))
+    };
+
+    object.into_iter().map(|(key, value)| match value {
+        Value::String(value) => Ok((key, value)),
+        Value::Number(_) | Value::Bool(_) => Ok((key, value.to_string())),
+        _ => Err(format!("Value of {} must be a string, number or boolean.", key))
+    }).collect()
+}
+
+/// Read one word of shell, as the value of an assignment, returning
+/// it along with the rest of the text. Quotes and backslashes are
+/// understood but nothing is expanded.
+fn shell_word(text: &str) -> Result<(String, &str), String> {
+    let mut word = String::new();
+    let mut chars = text.char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\'' => loop {
+                match chars.next() {
+                    Some((_, '\'')) => break,
+                    Some((_, c)) => word.push(c),
+                    None => return Err(String::from("Missing closing '."))
+                }
+            },
+            '"' => loop {
+                match chars.next() {
+                    Some((_, '"')) => break,
+                    Some((_, '\\')) => match chars.next() {
+                        Some((_, c)) if "$`\"\\\n".contains(c) => {
+                            if c != '\n' {
+                                word.push(c);
+                            }
+                        },
+                        Some((_, c)) => {
+                            word.push('\\');
+                            word.push(c);
+                        },
+                        None => return Err(String::from("Missing closing \"."))
+                    },
+                    Some((_, '$')) | Some((_, '`')) => return Err(String::from("Values must not run commands or expand variables.")),
+                    Some((_, c)) => word.push(c),
+                    None => return Err(String::from("Missing closing \"."))
+                }
+            },
+            '\\' => match chars.next() {
+                Some((_, '\n')) => {},
+                Some((_, c)) => word.push(c),
+                None => {}
+            },
+            '$' | '`' => return Err(String::from("Values must not run commands or expand variables.")),
+            c if c.is_whitespace() || c == ';' => return Ok((word, &text[i..])),
+            c => word.push(c)
+        }
+    }
+    Ok((word, ""))
+}
+
+/// Read variables from `export KEY=value` lines, as written by a
+/// shell export. Lines other than assignments are refused.
+fn import_shell(text: &str) -> Result<Vec<(String, String)>, String> {
+    let mut variables = Vec::new();
+    let mut rest = text;
+    let mut line = 1;
+
+    loop {
+        // Skip blank space, comments and separators between assignments
+        let trimmed = rest.trim_start_matches(|c: char| c.is_whitespace() || c == ';');
+        line += rest[..rest.len() - trimmed.len()].matches('\n').count();
+        rest = trimmed;
+        if rest.is_empty() {
+            break;
+        }
+        if rest.starts_with('#') {
+            rest = rest.find('\n').map_or("", |end| &rest[end..]);
+            continue;
+        }
+        if rest.starts_with("export") && rest["export".len()..].starts_with(char::is_whitespace) {
+            rest = rest["export".len()..].trim_start_matches(|c| c == ' ' || c == '\t');
+        }
+
+        let name_end = rest.find(|c: char| c != '_' && !c.is_ascii_alphanumeric()).unwrap_or_else(|| rest.len());
+        if name_end == 0 || !rest[name_end..].starts_with('=') {
+            return Err(format!("line {}: Expected an assignment such as export KEY='value'.", line));
+        }
+        let key = rest[..name_end].to_string();
+
+        let (value, after) = shell_word(&rest[name_end + 1..]).map_err(|msg| format!("line {}: {}", line, msg))?;
+        line += rest[..rest.len() - after.len()].matches('\n').count();
+        variables.push((key, value));
+        rest = after;
+    }
+    Ok(variables)
+}
+
+/// Read variables written in the given format. Values of dotenv
+/// files may only refer to variables of the file itself, since the
+/// process environment holds secrets decrypted.
+///
+/// # Arguments
+/// * `text` - Text to read.
+/// * `format` - Format the text is in.
+///
+/// # Examples
+/// ```
+/// let variables = import_variables(&contents, EnvFormat::from_path(&path))?;
+/// ```
+pub fn import_variables(text: &str, format: EnvFormat) -> Result<Vec<(String, String)>, String> {
+    match format {
+        EnvFormat::Json => import_json(text),
+        EnvFormat::Shell => import_shell(text),
+        EnvFormat::Dotenv => {
+            let (entries, errors) = parse_env(text, &|_| None);
+            match errors.first() {
+                Some(err) => Err(err.to_string()),
+                None => Ok(entries.into_iter().map(|entry| (entry.key, entry.value)).collect())
+            }
+        }
+    }
+}
+
+/// Read variables written in the given format as definitions for
+/// an env file, each with its value as it is to be written there.
+/// References in dotenv files are kept as written rather than
+/// expanded, so they are looked up wherever the file is loaded.
+///
+/// # Arguments
+/// * `text` - Text to read.
+/// * `format` - Format the text is in.
+///
+/// # Examples
+/// ```
+/// let definitions = import_definitions(&contents, EnvFormat::Dotenv)?;
+/// ```
+pub fn import_definitions(text: &str, format: EnvFormat) -> Result<Vec<(String, String)>, String> {
+    match format {
+        EnvFormat::Dotenv => {
+            let (entries, errors) = parse_env(text, &|_| None);
+            match errors.first() {
+                Some(err) => Err(err.to_string()),
+                None => Ok(entries.into_iter().map(|entry| (entry.key, entry.written)).collect())
+            }
+        },
+        format => Ok(import_variables(text, format)?.into_iter()
+                                                   .map(|(key, value)| (key, quote_value(&value)))
+                                                   .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    /// Variables with values that need quoting in every format.
+    fn awkward_variables() -> Vec<(String, String)> {
+        vec![
+            ("PLAIN", "8000"),
+            ("EMPTY", ""),
+            ("SPACES", "with  spaces "),
+            ("SINGLE", "it's"),
+            ("DOUBLE", "say \"hi\""),
+            ("DOLLAR", "$HOME and ${HOME} and $(ls) and `ls`"),
+            ("NEWLINES", "first\nsecond\n"),
+            ("BACKSLASHES", "C:\\path\\n \\\\ end\\"),
+            ("EVERYTHING", "'\"$\\\n#; &|")
+        ].into_iter()
+         .map(|(key, value)| (key.to_string(), value.to_string()))
+         .collect()
+    }
+
+    #[test]
+    fn dotenv_exports_read_back_unchanged() {
+        let variables = awkward_variables();
+        let exported = export_variables(&variables, EnvFormat::Dotenv).unwrap();
+        assert_eq!(import_variables(&exported, EnvFormat::Dotenv).unwrap(), variables);
+    }
+
+    #[test]
+    fn shell_exports_read_back_unchanged() {
+        let variables = awkward_variables();
+        let exported = export_variables(&variables, EnvFormat::Shell).unwrap();
+        assert_eq!(import_variables(&exported, EnvFormat::Shell).unwrap(), variables);
+    }
+
+    #[test]
+    fn json_exports_read_back_unchanged() {
+        let mut variables = awkward_variables();
+        let exported = export_variables(&variables, EnvFormat::Json).unwrap();
+        variables.sort();
+        assert_eq!(import_variables(&exported, EnvFormat::Json).unwrap(), variables);
+    }
+
+    #[test]
+    fn shell_exports_set_the_same_values_in_sh() {
+        for (key, value) in awkward_variables() {
+            let exported = export_variables(&[(key.clone(), value.clone())], EnvFormat::Shell).unwrap();
+            let output = Command::new("sh")
+                                 .arg("-c")
+                                 .arg(format!("{}printf '%s' \"${}\"", exported, key))
+                                 .output()
+                                 .unwrap();
+            assert_eq!(String::from_utf8_lossy(&output.stdout), value);
+        }
+    }
+
+    #[test]
+    fn shell_imports_refuse_expansions() {
+        assert!(import_variables("export A=$HOME\n", EnvFormat::Shell).is_err());
+        assert!(import_variables("export A=\"$(ls)\"\n", EnvFormat::Shell).is_err());
+        assert!(import_variables("echo hi\n", EnvFormat::Shell).is_err());
+        assert!(import_variables("export A='open\n", EnvFormat::Shell).is_err());
+    }
+
+    #[test]
+    fn dotenv_definitions_keep_references() {
+        let text = "URL=\"http://${RD_HOST}:${RD_PORT:-80}\"\nLEAK=$HOME\nLITERAL='${HOME}'\n";
+        let definitions = import_definitions(text, EnvFormat::Dotenv).unwrap();
+        assert_eq!(definitions, vec![
+            (String::from("URL"), String::from("\"http://${RD_HOST}:${RD_PORT:-80}\"")),
+            (String::from("LEAK"), String::from("$HOME")),
+            (String::from("LITERAL"), String::from("'${HOME}'"))
+        ]);
+        assert_eq!(export_definitions(&definitions), text);
+
+        // Nothing is looked up in the process environment
+        assert_eq!(import_variables("LEAK=${HOME}\n", EnvFormat::Dotenv).unwrap(), vec![(String::from("LEAK"), String::new())]);
+        assert_eq!(import_definitions(r#"{"A": "$HOME"}"#, EnvFormat::Json).unwrap(), vec![(String::from("A"), String::from("'$HOME'"))]);
+    }
+
+    #[test]
+    fn json_imports_take_scalars_only() {
+        let variables = import_variables(r#"{"PORT": 8000, "DEBUG": true, "NAME": "rd"}"#, EnvFormat::Json).unwrap();
+        assert_eq!(variables, vec![
+            (String::from("DEBUG"), String::from("true")),
+            (String::from("NAME"), String::from("rd")),
+            (String::from("PORT"), String::from("8000"))
+        ]);
+        assert!(import_variables(r#"{"LIST": [1]}"#, EnvFormat::Json).is_err());
+        assert!(import_variables("[]", EnvFormat::Json).is_err());
+    }
+
+    #[test]
+    fn fish_quotes_escape_only_quotes_and_backslashes() {
+        assert_eq!(fish_quote("plain"), "'plain'");
+        assert_eq!(fish_quote("it's"), "'it\\'s'");
+        assert_eq!(fish_quote("C:\\path"), "'C:\\\\path'");
+        assert_eq!(fish_quote("\\'"), "'\\\\\\''");
+        assert_eq!(fish_quote("$HOME \"x\"\nnext"), "'$HOME \"x\"\nnext'");
+    }
+}
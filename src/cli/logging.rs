@@ -0,0 +1,262 @@
+/**
+* This file configures logging for every `rd` command. The
+* level, format and outputs come from CLI flags, falling back
+* to keys in base.env and then to defaults.
+*/
+use std::{
+    env,
+    io::Write,
+    str::FromStr
+};
+use log::LevelFilter;
+use structopt::StructOpt;
+
+// Local imports
+use crate::cli::{
+    environment::get_or_create_log_file,
+    logs::{
+        RotatingFile,
+        RotationPolicy
+    }
+};
+use crate::settings::{
+    env_setting,
+    warn_or_print
+};
+
+/// Level logged at when none is configured.
+pub const DEFAULT_LOG_LEVEL: LevelFilter = LevelFilter::Debug;
+
+/// How each log record is written.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    /// `[date][time][target][level] message`, for people.
+    Human,
+
+    /// One JSON object per line, for log shippers.
+    Json
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<LogFormat, String> {
+        match format.to_lowercase().as_str() {
+            "human" => Ok(LogFormat::Human),
+            "json" => Ok(LogFormat::Json),
+            other => Err(format!("Unknown log format {}. Use human or json.", other))
+        }
+    }
+}
+
+/// Where log records are written.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogOutput {
+    Stdout,
+    File,
+    Both
+}
+
+impl FromStr for LogOutput {
+    type Err = String;
+
+    fn from_str(output: &str) -> Result<LogOutput, String> {
+        match output.to_lowercase().as_str() {
+            "stdout" => Ok(LogOutput::Stdout),
+            "file" => Ok(LogOutput::File),
+            "both" => Ok(LogOutput::Both),
+            other => Err(format!("Unknown log output {}. Use stdout, file or both.", other))
+        }
+    }
+}
+
+/// Logging flags accepted by every command of `rd`.
+#[derive(Debug, StructOpt)]
+pub struct LoggingCLI {
+    #[structopt(
+        help = "Level to log at: off, error, warn, info, debug or trace. Overrides RD_LOG_LEVEL.",
+        long,
+        global = true
+    )]
+    pub log_level: Option<LevelFilter>,
+
+    #[structopt(
+        help = "Format of log records: human or json. Overrides RD_LOG_FORMAT.",
+        long,
+        global = true
+    )]
+    pub log_format: Option<LogFormat>,
+
+    #[structopt(
+        help = "Where to log: stdout, file or both. Overrides RD_LOG_OUTPUT.",
+        long,
+        global = true
+    )]
+    pub log_output: Option<LogOutput>,
+
+    #[structopt(
+        help = "Level for a single module, as <module>=<level>. Adds to RD_LOG_MODULES.",
+        long = "log-module",
+        number_of_values = 1,
+        global = true
+    )]
+    pub log_modules: Vec<String>
+}
+
+impl LoggingCLI {
+
+    /// The logging settings given as flags, keyed by their name in rd.toml.
+    ///
+    /// # Examples
+    /// ```
+    /// let effective = EffectiveConfig::resolve(&instance, &opts.logging.flag_values())?;
+    /// ```
+    pub fn flag_values(&self) -> Vec<(&'static str, String)> {
+        let mut flags = Vec::new();
+        if let Some(level) = self.log_level {
+            flags.push(("logging.level", level.to_string().to_lowercase()));
+        }
+        if let Some(format) = self.log_format {
+            flags.push(("logging.format", format!("{:?}", format).to_lowercase()));
+        }
+        if let Some(output) = self.log_output {
+            flags.push(("logging.output", format!("{:?}", output).to_lowercase()));
+        }
+        if !self.log_modules.is_empty() {
+            flags.push(("logging.modules", self.log_modules.join(",")));
+        }
+        flags
+    }
+}
+
+/// Logging settings once flags and environment have been combined.
+#[derive(Debug)]
+pub struct LoggingConfig {
+    pub level: LevelFilter,
+    pub format: LogFormat,
+    pub output: LogOutput,
+    pub modules: Vec<(String, LevelFilter)>
+}
+
+/// Parse per-module levels written as `<module>=<level>`.
+///
+/// # Arguments
+/// * `overrides` - Overrides to parse.
+///
+/// # Examples
+/// ```
+/// let modules = parse_module_levels(&["rocket=warn"])?;
+/// ```
+pub fn parse_module_levels<S: AsRef<str>>(overrides: &[S]) -> Result<Vec<(String, LevelFilter)>, String> {
+    overrides.iter()
+             .map(|o| o.as_ref().trim())
+             .filter(|o| !o.is_empty())
+             .map(|o| {
+                 let parts = o.splitn(2, '=').collect::<Vec<&str>>();
+                 match parts.as_slice() {
+                     [module, level] => level.trim()
+                                             .parse::<LevelFilter>()
+                                             .map(|level| (module.trim().to_string(), level))
+                                             .map_err(|_| format!("Invalid level in module override {}.", o)),
+                     _ => Err(format!("Module override {} must look like <module>=<level>.", o))
+                 }
+             })
+             .collect()
+}
+
+impl LoggingConfig {
+
+    /// Combine logging flags with RD_LOG_LEVEL, RD_LOG_FORMAT,
+    /// RD_LOG_OUTPUT and RD_LOG_MODULES. Flags take precedence, and
+    /// module overrides from flags apply after those from the environment.
+    ///
+    /// # Arguments
+    /// * `cli` - Logging flags given on the command line.
+    ///
+    /// # Examples
+    /// ```
+    /// let config = LoggingConfig::resolve(&opts.logging);
+    /// ```
+    pub fn resolve(cli: &LoggingCLI) -> LoggingConfig {
+        let mut modules = match env::var("RD_LOG_MODULES") {
+            Ok(value) => parse_module_levels(&value.split(',').collect::<Vec<&str>>()).unwrap_or_else(|msg| {
+                warn_or_print(&format!("Ignoring RD_LOG_MODULES: {}", msg));
+                Vec::new()
+            }),
+            Err(_) => Vec::new()
+        };
+        match parse_module_levels(&cli.log_modules) {
+            Ok(overrides) => modules.extend(overrides),
+            Err(msg) => warn_or_print(&format!("Ignoring --log-module: {}", msg))
+        }
+
+        LoggingConfig {
+            level: cli.log_level.unwrap_or_else(|| env_setting("RD_LOG_LEVEL", DEFAULT_LOG_LEVEL)),
+            format: cli.log_format.unwrap_or_else(|| env_setting("RD_LOG_FORMAT", LogFormat::Human)),
+            output: cli.log_output.unwrap_or_else(|| env_setting("RD_LOG_OUTPUT", LogOutput::Stdout)),
+            modules
+        }
+    }
+}
+
+/// Format a record as a single line of JSON.
+fn json_record(record: &log::Record, message: &std::fmt::Arguments) -> String {
+    serde_json::json!({
+        "timestamp": chrono::Utc::now().to_rfc3339(),
+        "level": record.level().to_string(),
+        "target": record.target(),
+        "message": message.to_string()
+    }).to_string()
+}
+
+/// Setup the logger.
+///
+/// Records go to stdout, to the active log file of the
+/// instance in RD_HOME, or to both, as configured. The log
+/// file is rotated as set out by `RotationPolicy`.
+///
+/// # Arguments
+/// * `config` - Logging settings to apply.
+/// * `instance` - Dataserver instance whose log file is written to.
+/// * `keep_stdout` - Log to stderr in place of stdout, for commands
+///   whose output is read by other programs.
+///
+/// # Examples
+/// ```
+/// setup_logger(&LoggingConfig::resolve(&opts.logging), &instance, opts.cmd.prints_output())?;
+/// ```
+pub fn setup_logger(config: &LoggingConfig, instance: &str, keep_stdout: bool) -> Result<(), String> {
+    let format = config.format;
+    let mut dispatch = fern::Dispatch::new()
+        .format(move |out, message, record| {
+            match format {
+                LogFormat::Human => out.finish(format_args!(
+                    "{}[{}][{}] {}",
+                    chrono::Utc::now().format("[%Y-%m-%d][%H:%M:%S]"),
+                    record.target(),
+                    record.level(),
+                    message
+                )),
+                LogFormat::Json => out.finish(format_args!("{}", json_record(record, message)))
+            }
+        })
+        .level(config.level);
+
+    for (module, level) in config.modules.iter() {
+        dispatch = dispatch.level_for(module.clone(), *level);
+    }
+
+    if config.output != LogOutput::File {
+        dispatch = if keep_stdout {
+            dispatch.chain(std::io::stderr())
+        } else {
+            dispatch.chain(std::io::stdout())
+        };
+    }
+    if config.output != LogOutput::Stdout {
+        let log_file = RotatingFile::open(get_or_create_log_file(instance)?, RotationPolicy::from_env())?;
+        dispatch = dispatch.chain(Box::new(log_file) as Box<dyn Write + Send>);
+    }
+
+    dispatch.apply().map_err(|err| err.to_string())
+}
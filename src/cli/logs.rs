@@ -0,0 +1,581 @@
+/**
+* This file defines log rotation and retention, along with
+* the `logs` subcommand for reading and tidying the log files
+* of a dataserver instance.
+*/
+use std::{
+    fs::{
+        self,
+        File,
+        OpenOptions
+    },
+    io::{
+        self,
+        BufRead,
+        BufReader,
+        Read,
+        Seek,
+        SeekFrom,
+        Write
+    },
+    os::unix::fs::MetadataExt,
+    path::{
+        Path,
+        PathBuf
+    },
+    thread,
+    time::{
+        Duration,
+        SystemTime
+    }
+};
+use chrono::{
+    DateTime,
+    Utc
+};
+use flate2::{
+    Compression,
+    write::GzEncoder
+};
+use log::Level;
+use structopt::StructOpt;
+
+// Local imports
+use crate::cli::{
+    environment::{
+        ACTIVE_LOG_FILE,
+        DEFAULT_INSTANCE,
+        get_or_create_log_file,
+        get_or_create_logs_dir
+    }
+};
+use crate::settings::env_setting;
+
+/// How often `tail -f` checks the log file for new records.
+const FOLLOW_POLL_MILLIS: u64 = 500;
+
+/// Size at which the active log file is rotated, unless
+/// logging.max_size_mb says otherwise.
+pub const DEFAULT_LOG_MAX_SIZE_MB: u64 = 10;
+
+/// Age at which the active log file is rotated, unless
+/// logging.max_age_hours says otherwise.
+pub const DEFAULT_LOG_MAX_AGE_HOURS: u64 = 24;
+
+/// Whether rotated log files are gzipped, unless logging.compress
+/// says otherwise.
+pub const DEFAULT_LOG_COMPRESS: bool = true;
+
+/// How long rotated log files are kept, unless
+/// logging.retention_days says otherwise.
+pub const DEFAULT_LOG_RETENTION_DAYS: u64 = 14;
+
+/// How many rotated log files are kept, unless
+/// logging.retention_files says otherwise.
+pub const DEFAULT_LOG_RETENTION_FILES: usize = 20;
+
+/// When log files are rotated and how long rotated files are kept.
+/// Every setting can be changed through the environment file.
+#[derive(Debug, Clone)]
+pub struct RotationPolicy {
+    /// Rotate once the active file grows past this many bytes (RD_LOG_MAX_SIZE_MB).
+    pub max_size: u64,
+
+    /// Rotate once the active file is older than this (RD_LOG_MAX_AGE_HOURS).
+    pub max_age: Duration,
+
+    /// Gzip files as they are rotated out (RD_LOG_COMPRESS).
+    pub compress: bool,
+
+    /// Delete rotated files older than this many days (RD_LOG_RETENTION_DAYS).
+    pub retention_days: u64,
+
+    /// Keep at most this many rotated files (RD_LOG_RETENTION_FILES).
+    pub retention_files: usize
+}
+
+impl RotationPolicy {
+
+    /// Read the rotation policy from the environment, using defaults
+    /// for anything unset.
+    ///
+    /// # Examples
+    /// ```
+    /// let policy = RotationPolicy::from_env();
+    /// ```
+    pub fn from_env() -> RotationPolicy {
+        RotationPolicy {
+            max_size: env_setting("RD_LOG_MAX_SIZE_MB", DEFAULT_LOG_MAX_SIZE_MB) * 1024 * 1024,
+            max_age: Duration::from_secs(env_setting("RD_LOG_MAX_AGE_HOURS", DEFAULT_LOG_MAX_AGE_HOURS) * 3600),
+            compress: env_setting("RD_LOG_COMPRESS", DEFAULT_LOG_COMPRESS),
+            retention_days: env_setting("RD_LOG_RETENTION_DAYS", DEFAULT_LOG_RETENTION_DAYS),
+            retention_files: env_setting("RD_LOG_RETENTION_FILES", DEFAULT_LOG_RETENTION_FILES)
+        }
+    }
+}
+
+/// A log file which rotates itself out according to a policy.
+/// Other processes may rotate the same file, in which case it
+/// reopens the new one.
+pub struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    opened_at: SystemTime,
+    policy: RotationPolicy
+}
+
+/// Open a log file for appending.
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+impl RotatingFile {
+
+    /// Open the log file at the given path.
+    ///
+    /// # Arguments
+    /// * `path` - Path of the active log file.
+    /// * `policy` - When to rotate it.
+    ///
+    /// # Examples
+    /// ```
+    /// let file = RotatingFile::open(&log_file, RotationPolicy::from_env())?;
+    /// ```
+    pub fn open<P: AsRef<Path>>(path: P, policy: RotationPolicy) -> Result<RotatingFile, String> {
+        let path = path.as_ref().to_path_buf();
+        let file = open_append(&path).map_err(|err| err.to_string())?;
+        let opened_at = file.metadata()
+                            .and_then(|m| m.created().or_else(|_| m.modified()))
+                            .unwrap_or_else(|_| SystemTime::now());
+
+        Ok(RotatingFile {
+            path,
+            file,
+            opened_at,
+            policy
+        })
+    }
+
+    /// Whether the file at our path is no longer the one we hold open.
+    fn replaced(&self) -> bool {
+        match (fs::metadata(&self.path), self.file.metadata()) {
+            (Ok(on_disk), Ok(held)) => on_disk.ino() != held.ino() || on_disk.dev() != held.dev(),
+            _ => true
+        }
+    }
+
+    /// Whether the file we hold has outgrown the policy.
+    fn due(&self) -> bool {
+        let size = self.file.metadata().map(|m| m.len()).unwrap_or(0);
+        let age = self.opened_at.elapsed().unwrap_or_default();
+        size > 0 && (size >= self.policy.max_size || age >= self.policy.max_age)
+    }
+
+    /// Rotate the file if it is due, or reopen it if another process already has.
+    fn check(&mut self) -> io::Result<()> {
+        if !self.replaced() {
+            if !self.due() {
+                return Ok(());
+            }
+            let rotated = rotate_log_file(&self.path)?;
+
+            // Compressing and pruning can take a while, so keep them off the logging path
+            let policy = self.policy.clone();
+            thread::spawn(move || finish_rotation(&rotated, &policy));
+        }
+
+        self.file = open_append(&self.path)?;
+        self.opened_at = SystemTime::now();
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    // Records are flushed one at a time, so this is the one
+    // place rotation cannot split a record in two
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        self.check()
+    }
+}
+
+/// Move the active log file aside under a timestamped name.
+///
+/// # Arguments
+/// * `path` - Path of the active log file.
+fn rotate_log_file(path: &Path) -> io::Result<PathBuf> {
+    let stamp = Utc::now().format("%Y%m%d-%H%M%S").to_string();
+    let mut rotated = path.with_file_name(format!("output-{}.log", stamp));
+    let mut suffix = 1;
+    while rotated.exists() || rotated.with_extension("log.gz").exists() {
+        rotated = path.with_file_name(format!("output-{}-{}.log", stamp, suffix));
+        suffix += 1;
+    }
+
+    fs::rename(path, &rotated)?;
+    Ok(rotated)
+}
+
+/// Compress a freshly rotated file and apply retention to its directory.
+fn finish_rotation(rotated: &Path, policy: &RotationPolicy) {
+    if policy.compress {
+        if let Err(msg) = compress_log_file(rotated) {
+            error!("Failed to compress {}: {}", rotated.display(), msg);
+        }
+    }
+    if let Some(logs_dir) = rotated.parent() {
+        if let Err(msg) = prune_log_files(logs_dir, policy) {
+            error!("Failed to prune logs: {}", msg);
+        }
+    }
+}
+
+/// Gzip a log file, replacing it with `<name>.gz`. The archive is
+/// written under a temporary name first, so exiting part way through
+/// leaves the plain file in place rather than a truncated archive.
+///
+/// # Arguments
+/// * `path` - Path of the file to compress.
+///
+/// # Examples
+/// ```
+/// let compressed = compress_log_file(&rotated)?;
+/// ```
+pub fn compress_log_file(path: &Path) -> Result<PathBuf, String> {
+    let compressed = PathBuf::from(format!("{}.gz", path.display()));
+    let partial = PathBuf::from(format!("{}.gz.partial", path.display()));
+    let mut source = File::open(path).map_err(|err| err.to_string())?;
+    let mut encoder = GzEncoder::new(File::create(&partial).map_err(|err| err.to_string())?, Compression::default());
+
+    io::copy(&mut source, &mut encoder).map_err(|err| err.to_string())?;
+    encoder.finish().and_then(|file| file.sync_all()).map_err(|err| err.to_string())?;
+    fs::rename(&partial, &compressed).map_err(|err| err.to_string())?;
+    fs::remove_file(path).map_err(|err| err.to_string())?;
+    Ok(compressed)
+}
+
+/// A log file rotated out of use, along with when it was last written.
+#[derive(Debug)]
+pub struct RotatedLogFile {
+    pub path: PathBuf,
+    pub size: u64,
+    pub modified: DateTime<Utc>
+}
+
+/// List the rotated log files in a logs directory, newest first.
+///
+/// # Arguments
+/// * `logs_dir` - Directory holding the log files.
+///
+/// # Examples
+/// ```
+/// let rotated = rotated_log_files(&logs_dir)?;
+/// ```
+pub fn rotated_log_files(logs_dir: &Path) -> Result<Vec<RotatedLogFile>, String> {
+    let mut rotated = Vec::new();
+    for entry in fs::read_dir(logs_dir).map_err(|err| err.to_string())? {
+        let entry = entry.map_err(|err| err.to_string())?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if !name.starts_with("output-") || !(name.ends_with(".log") || name.ends_with(".log.gz")) {
+            continue;
+        }
+
+        let metadata = entry.metadata().map_err(|err| err.to_string())?;
+        rotated.push(RotatedLogFile {
+            path: entry.path(),
+            size: metadata.len(),
+            modified: metadata.modified().map(DateTime::<Utc>::from).unwrap_or_else(|_| Utc::now())
+        });
+    }
+
+    rotated.sort_by(|a, b| b.modified.cmp(&a.modified));
+    Ok(rotated)
+}
+
+/// Delete rotated log files the retention policy no longer keeps,
+/// returning the paths deleted.
+///
+/// # Arguments
+/// * `logs_dir` - Directory holding the log files.
+/// * `policy` - How many rotated files to keep, and for how long.
+///
+/// # Examples
+/// ```
+/// let removed = prune_log_files(&logs_dir, &RotationPolicy::from_env())?;
+/// ```
+pub fn prune_log_files(logs_dir: &Path, policy: &RotationPolicy) -> Result<Vec<PathBuf>, String> {
+    let cutoff = Utc::now() - chrono::Duration::days(policy.retention_days as i64);
+    let mut removed = Vec::new();
+
+    for (index, rotated) in rotated_log_files(logs_dir)?.into_iter().enumerate() {
+        if index >= policy.retention_files || rotated.modified < cutoff {
+            fs::remove_file(&rotated.path).map_err(|err| err.to_string())?;
+
+            // Along with whatever an interrupted compression left of it
+            let _ = fs::remove_file(format!("{}.gz.partial", rotated.path.display()));
+            removed.push(rotated.path);
+        }
+    }
+    Ok(removed)
+}
+
+/// Passthrough command for the logs subcommand of `rd`
+#[derive(Debug, StructOpt)]
+#[structopt(name = "logs")]
+pub struct LogsCLI {
+
+    #[structopt(
+        default_value = DEFAULT_INSTANCE,
+        help = "Name of the dataserver instance whose logs to read.",
+        long,
+        global = true
+    )]
+    pub instance: String,
+
+    #[structopt(subcommand)]
+    pub cmd: LogsCommand
+}
+
+/// Command arg options for tailing the active log file.
+#[derive(Debug, StructOpt)]
+pub struct TailCLI {
+    #[structopt(
+        default_value = "20",
+        help = "Number of records to show before following.",
+        short = "n",
+        long
+    )]
+    lines: usize,
+
+    #[structopt(
+        help = "Keep printing records as they are written.",
+        short,
+        long
+    )]
+    follow: bool,
+
+    #[structopt(
+        help = "Only show records at this level or more severe.",
+        short,
+        long
+    )]
+    level: Option<Level>,
+
+    #[structopt(
+        help = "Only show records whose target starts with this.",
+        short,
+        long
+    )]
+    target: Option<String>
+}
+
+/// Enum listing the various subcommands of the `logs` command.
+#[derive(Debug, StructOpt)]
+pub enum LogsCommand {
+
+    #[structopt(about = "Shows the latest records of the active log file.")]
+    Tail(TailCLI),
+
+    #[structopt(about = "Lists the active and rotated log files.")]
+    List,
+
+    #[structopt(about = "Deletes rotated log files past the retention policy.")]
+    Prune
+}
+
+/// Picks out the records a `tail` should show.
+struct RecordFilter {
+    level: Option<Level>,
+    target: Option<String>,
+
+    // Lines which are not records, such as the rest of a multi-line
+    // message, follow whatever the record before them did
+    last_shown: bool
+}
+
+/// Pull the level and target out of a record in either log format.
+fn parse_record(line: &str) -> Option<(Level, String)> {
+    if line.starts_with('{') {
+        let record = serde_json::from_str::<serde_json::Value>(line).ok()?;
+        let level = record.get("level")?.as_str()?.parse().ok()?;
+        let target = record.get("target")?.as_str()?.to_string();
+        return Some((level, target));
+    }
+
+    // [date][time][target][level] message
+    let fields = line.splitn(5, ']').collect::<Vec<&str>>();
+    if fields.len() < 5 || !fields[..4].iter().all(|f| f.starts_with('[')) {
+        return None;
+    }
+    Some((fields[3][1..].parse().ok()?, fields[2][1..].to_string()))
+}
+
+impl RecordFilter {
+
+    /// Whether a line of the log file should be shown.
+    fn matches(&mut self, line: &str) -> bool {
+        self.last_shown = match parse_record(line) {
+            Some((level, target)) => {
+                self.level.map_or(true, |min| level <= min)
+                    && self.target.as_ref().map_or(true, |t| target.starts_with(t.as_str()))
+            },
+            None => self.last_shown
+        };
+        self.last_shown
+    }
+}
+
+/// Print the latest records of a log file, then optionally keep
+/// printing new ones, picking up the new file after a rotation.
+///
+/// # Arguments
+/// * `path` - Path of the active log file.
+/// * `cmd` - How much to show and which records.
+fn tail_log_file(path: &Path, cmd: &TailCLI) -> Result<(), String> {
+    let mut filter = RecordFilter {
+        level: cmd.level,
+        target: cmd.target.clone(),
+        last_shown: true
+    };
+
+    let mut file = File::open(path).map_err(|err| err.to_string())?;
+    let mut content = String::new();
+    file.read_to_string(&mut content).map_err(|err| err.to_string())?;
+    let shown = content.lines().filter(|line| filter.matches(line)).collect::<Vec<&str>>();
+    shown[shown.len().saturating_sub(cmd.lines)..].iter().for_each(|line| println!("{}", line));
+
+    if !cmd.follow {
+        return Ok(());
+    }
+
+    let mut reader = BufReader::new(file);
+    let mut partial = String::new();
+    loop {
+        let mut line = String::new();
+        let read = reader.read_line(&mut line).map_err(|err| err.to_string())?;
+        if read > 0 {
+            // Hold on to half written lines until the rest arrives
+            partial.push_str(&line);
+            if partial.ends_with('\n') {
+                let record = partial.trim_end_matches('\n');
+                if filter.matches(record) {
+                    println!("{}", record);
+                }
+                partial.clear();
+            }
+            continue;
+        }
+
+        thread::sleep(Duration::from_millis(FOLLOW_POLL_MILLIS));
+
+        // Start over on the new file once the old one is rotated out
+        let held = reader.get_ref().metadata().map_err(|err| err.to_string())?;
+        let rotated = match fs::metadata(path) {
+            Ok(on_disk) => on_disk.ino() != held.ino(),
+            Err(_) => false
+        };
+        if rotated {
+            reader = BufReader::new(File::open(path).map_err(|err| err.to_string())?);
+            partial.clear();
+        } else if held.len() < reader.seek(SeekFrom::Current(0)).map_err(|err| err.to_string())? {
+            // Truncated in place
+            reader.seek(SeekFrom::Start(0)).map_err(|err| err.to_string())?;
+        }
+    }
+}
+
+/// Run a logs command.
+///
+/// # Arguments
+/// * `command` - Command to run.
+///
+/// # Examples
+/// ```
+/// run_logs_command(&command);
+/// ```
+pub fn run_logs_command(command: &LogsCLI) {
+    let logs_dir = match get_or_create_logs_dir(&command.instance) {
+        Ok(logs_dir) => logs_dir,
+        Err(msg) => {
+            error!("{}", msg);
+            return;
+        }
+    };
+
+    match &command.cmd {
+
+        // Show the latest records
+        LogsCommand::Tail(cmd) => {
+            if let Err(msg) = get_or_create_log_file(&command.instance).and_then(|path| tail_log_file(Path::new(&path), cmd)) {
+                error!("{}", msg);
+            }
+        },
+
+        // List every log file. Results are printed rather than logged,
+        // so they show whatever the log level and outputs
+        LogsCommand::List => {
+            let mut active = logs_dir.clone();
+            active.push(ACTIVE_LOG_FILE);
+            if let Ok(metadata) = fs::metadata(&active) {
+                println!("{} ({} bytes, active)", active.display(), metadata.len());
+            }
+
+            match rotated_log_files(&logs_dir) {
+                Ok(rotated) => rotated.iter().for_each(|r| println!("{} ({} bytes, last written {})", r.path.display(), r.size, r.modified)),
+                Err(msg) => error!("{}", msg)
+            }
+        },
+
+        // Delete old log files
+        LogsCommand::Prune => {
+            match prune_log_files(&logs_dir, &RotationPolicy::from_env()) {
+                Ok(removed) if removed.is_empty() => println!("No log files to prune."),
+                Ok(removed) => removed.iter().for_each(|path| println!("Removed {}.", path.display())),
+                Err(msg) => error!("{}", msg)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn compressing_replaces_the_log_file() {
+        let logs_dir = TempDir::new();
+        let rotated = logs_dir.path().join("output-20260101-000000.log");
+        fs::write(&rotated, "[2026-01-01][00:00:00][rd][INFO] Hello\n").unwrap();
+
+        let compressed = compress_log_file(&rotated).unwrap();
+        let mut contents = String::new();
+        GzDecoder::new(File::open(&compressed).unwrap()).read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "[2026-01-01][00:00:00][rd][INFO] Hello\n");
+
+        let names = fs::read_dir(logs_dir.path()).unwrap()
+                                                .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+                                                .collect::<Vec<String>>();
+        assert_eq!(names, vec!["output-20260101-000000.log.gz"]);
+    }
+
+    #[test]
+    fn pruning_removes_interrupted_compressions() {
+        let logs_dir = TempDir::new();
+        let rotated = logs_dir.path().join("output-20260101-000000.log");
+        fs::write(&rotated, "").unwrap();
+        fs::write(logs_dir.path().join("output-20260101-000000.log.gz.partial"), "").unwrap();
+
+        let policy = RotationPolicy {
+            retention_files: 0,
+            ..RotationPolicy::from_env()
+        };
+        assert_eq!(prune_log_files(logs_dir.path(), &policy).unwrap(), vec![rotated]);
+        assert_eq!(fs::read_dir(logs_dir.path()).unwrap().count(), 0);
+    }
+}
@@ -2,7 +2,10 @@
 * This file contains code for directing subcommands of the Rubber
 * Duck CLI.
 */
-use structopt::StructOpt;
+use structopt::{
+    StructOpt,
+    clap::Shell
+};
 use std::{
     path::PathBuf,
     process::Command as OsCommand,
@@ -12,8 +15,16 @@ use std::{
     }
 };
 
+pub mod apikey;
+pub mod config;
 pub mod dataserver;
+pub mod dotenv;
 pub mod environment;
+pub mod exchange;
+pub mod logging;
+pub mod logs;
+pub mod secrets;
+pub mod user;
 
 /// The primary command for the CLI 
 /// 
@@ -23,6 +34,16 @@ pub mod environment;
     name = "rd",
     about = "Welcome to Rubber Duck! This CLI assists you in completing all your necessary tasks related to the Rubber Duck environment.")]
 pub struct RD {
+    #[structopt(flatten)]
+    pub logging: logging::LoggingCLI,
+
+    #[structopt(
+        help = "Profile to layer on top of base.env. Overrides RD_PROFILE.",
+        long,
+        global = true
+    )]
+    pub profile: Option<String>,
+
     #[structopt(subcommand)]
     pub cmd: Command
 }
@@ -42,6 +63,19 @@ pub struct InstallCLI {
     source_dir: PathBuf
 }
 
+#[derive(Debug, StructOpt)]
+#[structopt(
+    about = "Print a completion script for rd."
+)]
+pub struct CompletionsCLI {
+
+    #[structopt(
+        possible_values = &["bash", "zsh", "fish"],
+        help = "Shell to complete in: bash, zsh or fish."
+    )]
+    shell: Shell
+}
+
 /// Enumeration the lists the different subcommands
 /// of the root CLI.
 #[derive(Debug, StructOpt)]
@@ -53,8 +87,46 @@ pub enum Command {
     /// To configure the environment
     Environment(environment::EnvironmentCLI),
 
+    /// To manage API keys for CLI and bot clients
+    #[structopt(name = "apikey")]
+    Apikey(apikey::ApiKeyCLI),
+
+    /// To administer dataserver users
+    User(user::UserCLI),
+
+    /// To read and tidy dataserver logs
+    Logs(logs::LogsCLI),
+
+    /// To inspect the layered configuration of rd
+    Config(config::ConfigCLI),
+
     /// Install the executable
-    Install(InstallCLI)
+    Install(InstallCLI),
+
+    /// Print shell completions
+    Completions(CompletionsCLI)
+}
+
+impl Command {
+
+    /// Whether the command prints output for other programs to read,
+    /// which log records must be kept out of.
+    pub fn prints_output(&self) -> bool {
+        match self {
+            Command::Completions(_) => true,
+            Command::Environment(cmd) => cmd.cmd.prints_output(),
+            _ => false
+        }
+    }
+
+    /// Whether the command creates the profile given with `--profile`
+    /// when it does not exist yet.
+    pub fn creates_profile(&self) -> bool {
+        match self {
+            Command::Environment(cmd) => cmd.cmd.creates_profile(),
+            _ => false
+        }
+    }
 }
 
 #[inline]
@@ -93,3 +165,18 @@ pub fn install_executable(command: &InstallCLI) {
         info!("Completed installation test.");
     }
 }
+
+/// Print a completion script for the given shell, generated from
+/// the definition of every command and flag.
+/// 
+/// # Arguments
+/// * `command` - Shell to print the script for.
+/// 
+/// # Examples
+/// ```
+/// generate_completions(&command);
+/// ```
+#[inline]
+pub fn generate_completions(command: &CompletionsCLI) {
+    RD::clap().gen_completions_to("rd", command.shell, &mut stdout());
+}
@@ -0,0 +1,263 @@
+/**
+* This file contains code for keeping secrets in env files
+* encrypted, using a key file kept in RD_HOME. Secrets stay
+* encrypted on disk and in listings, and are only decrypted
+* when loaded into the process environment.
+*/
+use std::{
+    collections::HashMap,
+    fs::{
+        File,
+        read
+    },
+    io::Write,
+    os::unix::fs::OpenOptionsExt,
+    path::PathBuf
+};
+use chacha20poly1305::{
+    Key,
+    XChaCha20Poly1305,
+    XNonce,
+    aead::{
+        Aead,
+        NewAead,
+        Payload
+    }
+};
+use rand::Rng;
+
+// Local imports
+use crate::cli::environment::get_or_create_rd_home;
+
+/// Marks a value of an env file as an encrypted secret.
+pub const SECRET_PREFIX: &str = "secret:v1:";
+
+/// Shown in place of secret values.
+pub const MASKED_SECRET: &str = "********";
+
+/// Name of the key file in RD_HOME.
+const KEY_FILE: &str = "secret.key";
+
+/// Length of the key, in bytes.
+const KEY_LENGTH: usize = 32;
+
+/// Length of each nonce, in bytes.
+const NONCE_LENGTH: usize = 24;
+
+/// Whether a value of an env file is an encrypted secret.
+///
+/// # Arguments
+/// * `value` - Value as stored in the file.
+pub fn is_secret(value: &str) -> bool {
+    value.starts_with(SECRET_PREFIX)
+}
+
+/// Get the path of the key file. The file need not exist.
+fn get_key_file_path() -> Result<PathBuf, String> {
+    let mut key_path = PathBuf::from(get_or_create_rd_home()?);
+    key_path.push(KEY_FILE);
+    Ok(key_path)
+}
+
+/// Read the key secrets are encrypted with, creating it the first
+/// time a secret is stored. Only the owner may read the key file.
+///
+/// # Arguments
+/// * `create` - Whether to create the key if there is none yet.
+fn read_key(create: bool) -> Result<Key, String> {
+    let key_path = get_key_file_path()?;
+    if !key_path.exists() {
+        if !create {
+            return Err(format!("No key file at {} to decrypt secrets with.", key_path.display()));
+        }
+
+        let key = rand::thread_rng().gen::<[u8; KEY_LENGTH]>();
+        let mut key_file = File::with_options().write(true)
+                                               .create_new(true)
+                                               .mode(0o600)
+                                               .open(&key_path)
+                                               .map_err(|err| format!("Failed to create key file {}: {}", key_path.display(), err))?;
+        key_file.write_all(&key).map_err(|err| err.to_string())?;
+        key_file.sync_all().map_err(|err| err.to_string())?;
+        info!("Created key file {}. Keep it safe: secrets cannot be read without it.", key_path.display());
+    }
+
+    let key = read(&key_path).map_err(|err| format!("Failed to read key file {}: {}", key_path.display(), err))?;
+    if key.len() != KEY_LENGTH {
+        return Err(format!("Key file {} is not a valid key.", key_path.display()));
+    }
+    Ok(*Key::from_slice(&key))
+}
+
+/// Encrypt the value of a variable for storing in an env file. The
+/// name of the variable is bound into the result, so it cannot be
+/// moved to another variable.
+///
+/// # Arguments
+/// * `key` - Name of the variable.
+/// * `value` - Value to encrypt.
+///
+/// # Examples
+/// ```
+/// let stored = encrypt_secret("RD_SECRET_KEY", "hunter2")?;
+/// ```
+pub fn encrypt_secret(key: &str, value: &str) -> Result<String, String> {
+    let cipher = XChaCha20Poly1305::new(&read_key(true)?);
+    let nonce = rand::thread_rng().gen::<[u8; NONCE_LENGTH]>();
+    let payload = Payload {
+        msg: value.as_bytes(),
+        aad: key.as_bytes()
+    };
+
+    let ciphertext = cipher.encrypt(XNonce::from_slice(&nonce), payload)
+                           .map_err(|_| format!("Failed to encrypt {}.", key))?;
+    Ok(format!("{}{}{}", SECRET_PREFIX, hex::encode(nonce), hex::encode(ciphertext)))
+}
+
+/// Decrypt a secret stored in an env file.
+///
+/// # Arguments
+/// * `key` - Name of the variable.
+/// * `stored` - Value as stored in the file.
+///
+/// # Examples
+/// ```
+/// let value = decrypt_secret("RD_SECRET_KEY", &stored)?;
+/// ```
+pub fn decrypt_secret(key: &str, stored: &str) -> Result<String, String> {
+    let invalid = || format!("{} is not a valid secret.", key);
+    let encoded = match stored.get(SECRET_PREFIX.len()..) {
+        Some(encoded) if is_secret(stored) => encoded,
+        _ => return Err(invalid())
+    };
+    let bytes = hex::decode(encoded).map_err(|_| invalid())?;
+    if bytes.len() < NONCE_LENGTH {
+        return Err(invalid());
+    }
+
+    let (nonce, ciphertext) = bytes.split_at(NONCE_LENGTH);
+    let payload = Payload {
+        msg: ciphertext,
+        aad: key.as_bytes()
+    };
+    let plaintext = XChaCha20Poly1305::new(&read_key(false)?).decrypt(XNonce::from_slice(nonce), payload)
+                                                             .map_err(|_| format!("Failed to decrypt {}: the key file is not the one it was encrypted with, or the value belongs to another variable.", key))?;
+    String::from_utf8(plaintext).map_err(|_| invalid())
+}
+
+/// Decrypt every secret among the variables of an env file in place.
+/// Secrets that cannot be decrypted are left out, so that the rest
+/// can still be used, and reported once all have been tried.
+///
+/// # Arguments
+/// * `variables` - Variables as read from an env file.
+///
+/// # Examples
+/// ```
+/// let mut variables = get_env()?;
+/// reveal_secrets(&mut variables)?;
+/// ```
+pub fn reveal_secrets(variables: &mut HashMap<String, String>) -> Result<(), String> {
+    let mut errors = Vec::new();
+    variables.retain(|key, value| {
+        if !is_secret(value) {
+            return true;
+        }
+        match decrypt_secret(key, value) {
+            Ok(revealed) => {
+                *value = revealed;
+                true
+            },
+            Err(msg) => {
+                errors.push(msg);
+                false
+            }
+        }
+    });
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors.join(" "))
+    }
+}
+
+/// Get a value as it may be shown in a listing, masking secrets.
+///
+/// # Arguments
+/// * `value` - Value as stored in the file.
+pub fn masked(value: &str) -> &str {
+    if is_secret(value) {
+        MASKED_SECRET
+    } else {
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestRdHome;
+
+    #[test]
+    fn secrets_decrypt_to_what_was_encrypted() {
+        let _home = TestRdHome::new();
+        for value in &["hunter2", "", "two\nlines", "quote \" and 'dollar' $HOME", "ünïcödé"] {
+            let stored = encrypt_secret("RD_SECRET_KEY", value).unwrap();
+            assert!(is_secret(&stored));
+            assert_eq!(&decrypt_secret("RD_SECRET_KEY", &stored).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn each_encryption_uses_a_new_nonce() {
+        let _home = TestRdHome::new();
+        assert_ne!(encrypt_secret("RD_SECRET_KEY", "same").unwrap(), encrypt_secret("RD_SECRET_KEY", "same").unwrap());
+    }
+
+    #[test]
+    fn secrets_cannot_move_to_another_variable() {
+        let _home = TestRdHome::new();
+        let stored = encrypt_secret("RD_SECRET_KEY", "hunter2").unwrap();
+        let err = decrypt_secret("RD_OTHER_KEY", &stored).unwrap_err();
+        assert!(err.starts_with("Failed to decrypt RD_OTHER_KEY"), "{}", err);
+    }
+
+    #[test]
+    fn tampered_secrets_are_rejected() {
+        let _home = TestRdHome::new();
+        let stored = encrypt_secret("RD_SECRET_KEY", "hunter2").unwrap();
+        let last = stored.chars().last().unwrap();
+        let tampered = format!("{}{}", &stored[..stored.len() - 1], if last == '0' { '1' } else { '0' });
+        assert!(decrypt_secret("RD_SECRET_KEY", &tampered).is_err());
+    }
+
+    #[test]
+    fn malformed_secrets_are_invalid() {
+        let _home = TestRdHome::new();
+        let invalid = Err(String::from("RD_SECRET_KEY is not a valid secret."));
+        assert_eq!(decrypt_secret("RD_SECRET_KEY", "hunter2"), invalid);
+        assert_eq!(decrypt_secret("RD_SECRET_KEY", &format!("{}not-hex", SECRET_PREFIX)), invalid);
+        assert_eq!(decrypt_secret("RD_SECRET_KEY", &format!("{}abcd", SECRET_PREFIX)), invalid);
+    }
+
+    #[test]
+    fn revealing_drops_secrets_that_cannot_be_decrypted() {
+        let _home = TestRdHome::new();
+        let mut variables = HashMap::new();
+        variables.insert(String::from("RD_PORT"), String::from("8000"));
+        variables.insert(String::from("RD_SECRET_KEY"), encrypt_secret("RD_SECRET_KEY", "hunter2").unwrap());
+        variables.insert(String::from("RD_MOVED"), encrypt_secret("RD_SECRET_KEY", "hunter2").unwrap());
+
+        assert!(reveal_secrets(&mut variables).unwrap_err().contains("RD_MOVED"));
+        assert_eq!(variables.get("RD_PORT").map(String::as_str), Some("8000"));
+        assert_eq!(variables.get("RD_SECRET_KEY").map(String::as_str), Some("hunter2"));
+        assert!(!variables.contains_key("RD_MOVED"));
+    }
+
+    #[test]
+    fn secrets_are_masked() {
+        assert_eq!(masked("8000"), "8000");
+        assert_eq!(masked(&format!("{}00", SECRET_PREFIX)), MASKED_SECRET);
+    }
+}
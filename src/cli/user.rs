@@ -0,0 +1,78 @@
+/**
+* This file defines subcommands for administering the users
+* of the dataserver, such as bootstrapping the first admin.
+* Users are managed directly in the dataserver database.
+*/
+use structopt::StructOpt;
+
+// Local imports
+use crate::cli::apikey::find_user;
+use crate::dataserver::{
+    models::Role,
+    storage::open_migrated_storage
+};
+
+/// Passthrough command for the user subcommand of `rd`
+#[derive(Debug, StructOpt)]
+#[structopt(name = "user")]
+pub struct UserCLI {
+
+    #[structopt(subcommand)]
+    pub cmd: UserCommand
+}
+
+/// Command arg options for changing the role of a user.
+#[derive(Debug, StructOpt)]
+pub struct SetRoleCLI {
+    #[structopt(
+        short,
+        long,
+        help = "Username of the user to change."
+    )]
+    user: String,
+
+    #[structopt(
+        short,
+        long,
+        help = "New role of the user: member, moderator or admin."
+    )]
+    role: Role
+}
+
+/// Enum listing the various subcommands of the `user` command.
+#[derive(Debug, StructOpt)]
+pub enum UserCommand {
+
+    #[structopt(about = "Sets the role of a user.")]
+    SetRole(SetRoleCLI)
+}
+
+/// Run a user command.
+///
+/// # Arguments
+/// * `command` - Command to run.
+///
+/// # Examples
+/// ```
+/// run_user_command(&command);
+/// ```
+pub fn run_user_command(command: &UserCLI) {
+    let storage = match open_migrated_storage() {
+        Ok(storage) => storage,
+        Err(msg) => {
+            error!("{}", msg);
+            return;
+        }
+    };
+
+    match &command.cmd {
+
+        // Change the role of a user
+        UserCommand::SetRole(cmd) => {
+            match find_user(storage.as_ref(), &cmd.user).and_then(|user| storage.set_user_role(user.id, cmd.role)) {
+                Ok(user) => info!("{} is now a {}.", user.username, user.role.as_str()),
+                Err(msg) => error!("{}", msg)
+            }
+        }
+    }
+}
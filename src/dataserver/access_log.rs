@@ -0,0 +1,117 @@
+/**
+ * Access logging for the dataserver. Every request is given
+ * an ID, echoed back in `X-Request-Id`, and logged once it
+ * has been answered so client reports can be matched up
+ * with server logs.
+ */
+use std::time::{
+    Duration,
+    Instant
+};
+use rand::Rng;
+use rocket::{
+    Data,
+    Outcome,
+    Request,
+    Response,
+    fairing::{
+        Fairing,
+        Info,
+        Kind
+    },
+    request::{
+        self,
+        FromRequest
+    }
+};
+
+/// Header carrying the request ID in both directions.
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+/// Longest request ID accepted from a client.
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+/// ID of the request being served.
+#[derive(Debug, Clone)]
+pub struct RequestId(pub String);
+
+/// When the request arrived.
+struct RequestStart(Option<Instant>);
+
+/// User the request was authenticated as, recorded by the
+/// `CurrentUser` guard for the access log.
+pub struct RequestUser(pub Option<String>);
+
+/// How long ago the request arrived, if the access log saw it arrive.
+///
+/// # Arguments
+/// * `request` - Request being answered.
+///
+/// # Examples
+/// ```
+/// let latency = request_latency(request).unwrap_or_default();
+/// ```
+pub fn request_latency(request: &Request) -> Option<Duration> {
+    request.local_cache(|| RequestStart(None)).0.map(|start| start.elapsed())
+}
+
+/// Use the client's request ID if it is reasonable, or make up a new one.
+fn request_id_for(request: &Request) -> RequestId {
+    let incoming = request.headers()
+                          .get_one(REQUEST_ID_HEADER)
+                          .map(|id| id.trim())
+                          .filter(|id| !id.is_empty() && id.len() <= MAX_REQUEST_ID_LENGTH)
+                          .filter(|id| id.chars().all(|c| c.is_ascii_graphic()));
+
+    match incoming {
+        Some(id) => RequestId(id.to_string()),
+        None => RequestId(hex::encode(rand::thread_rng().gen::<[u8; 16]>()))
+    }
+}
+
+/// Fairing assigning request IDs and writing the access log.
+/// Records are logged under the `access` target, so their level
+/// can be set on its own with `--log-module access=<level>`.
+pub struct AccessLog;
+
+impl Fairing for AccessLog {
+    fn info(&self) -> Info {
+        Info {
+            name: "Access Log",
+            kind: Kind::Request | Kind::Response
+        }
+    }
+
+    fn on_request(&self, request: &mut Request, _: &Data) {
+        request.local_cache(|| RequestStart(Some(Instant::now())));
+        let id = request_id_for(request);
+        request.local_cache(|| id);
+    }
+
+    fn on_response(&self, request: &Request, response: &mut Response) {
+        let id = request.local_cache(|| request_id_for(request));
+        response.set_raw_header(REQUEST_ID_HEADER, id.0.clone());
+
+        let latency = request_latency(request).map_or(0.0, |latency| latency.as_secs_f64() * 1000.0);
+        let user = request.local_cache(|| RequestUser(None));
+
+        info!(
+            target: "access",
+            "request_id={} method={} path={} status={} latency_ms={:.2} user={}",
+            id.0,
+            request.method(),
+            request.uri().path(),
+            response.status().code,
+            latency,
+            user.0.as_ref().map_or("-", |username| username.as_str())
+        );
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for RequestId {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        Outcome::Success(request.local_cache(|| request_id_for(request)).clone())
+    }
+}
@@ -0,0 +1,336 @@
+/**
+ * Authentication for the dataserver: password hashing,
+ * session tokens signed with a key derived from
+ * RD_SECRET_KEY, API keys for CLI and bot clients, and
+ * the request guards resolving the user behind a request.
+ */
+use std::sync::Arc;
+use argon2::{
+    Config,
+    Variant
+};
+use chrono::{
+    Duration,
+    Utc
+};
+use hmac::{
+    Hmac,
+    Mac,
+    NewMac
+};
+use rand::Rng;
+use rocket::{
+    Outcome,
+    State,
+    http::Status,
+    request::{
+        self,
+        FromRequest,
+        Request
+    }
+};
+use sha2::{
+    Digest,
+    Sha256
+};
+
+use crate::dataserver::{
+    access_log::RequestUser,
+    models::{
+        CreatedApiKey,
+        User
+    },
+    storage::Storage
+};
+
+/// How long a session token stays valid after login, unless
+/// auth.session_lifetime_hours says otherwise.
+pub const SESSION_LIFETIME_HOURS: i64 = 24 * 7;
+
+/// Shortest password accepted at registration.
+pub const MIN_PASSWORD_LENGTH: usize = 8;
+
+/// Marks a bearer credential as an API key rather than a session token.
+pub const API_KEY_MARKER: &str = "rdk_";
+
+/// Context string used to derive the session signing key from RD_SECRET_KEY.
+const SESSION_KEY_CONTEXT: &[u8] = b"rd-session-token";
+
+type HmacSha256 = Hmac<Sha256>;
+
+lazy_static! {
+    /// Hash checked when logging in as a user who does not exist or has
+    /// no password, so that how long a login takes does not reveal which
+    /// usernames exist.
+    pub static ref DUMMY_PASSWORD_HASH: String = hash_password("rd-dummy-password").expect("Failed to hash dummy password.");
+}
+
+/// Hash a password for storage using argon2id with a random salt.
+///
+/// # Arguments
+/// * `password` - Plaintext password to hash.
+///
+/// # Examples
+/// ```
+/// let hash = hash_password("hunter22").unwrap();
+/// ```
+pub fn hash_password(password: &str) -> Result<String, String> {
+    let salt: [u8; 16] = rand::thread_rng().gen();
+    let config = Config {
+        variant: Variant::Argon2id,
+        ..Config::default()
+    };
+    argon2::hash_encoded(password.as_bytes(), &salt, &config).map_err(|err| err.to_string())
+}
+
+/// Check a plaintext password against a stored hash.
+///
+/// # Arguments
+/// * `hash` - Hash produced by `hash_password`.
+/// * `password` - Plaintext password to check.
+///
+/// # Examples
+/// ```
+/// if verify_password(&hash, "hunter22") {
+///     // Log in
+/// }
+/// ```
+pub fn verify_password(hash: &str, password: &str) -> bool {
+    argon2::verify_encoded(hash, password.as_bytes()).unwrap_or(false)
+}
+
+/// Issues and verifies session tokens of the form
+/// `<user id>.<expiry>.<signature>`.
+#[derive(Clone)]
+pub struct SessionSigner {
+    key: Vec<u8>
+}
+
+impl SessionSigner {
+
+    /// Create a signer whose key is derived from the server secret.
+    ///
+    /// # Arguments
+    /// * `secret_key` - Value of RD_SECRET_KEY.
+    ///
+    /// # Examples
+    /// ```
+    /// let signer = SessionSigner::new(&secret_key);
+    /// ```
+    pub fn new(secret_key: &str) -> SessionSigner {
+        let mut mac = HmacSha256::new_varkey(secret_key.as_bytes()).expect("HMAC accepts keys of any length.");
+        mac.update(SESSION_KEY_CONTEXT);
+
+        SessionSigner {
+            key: mac.finalize().into_bytes().to_vec()
+        }
+    }
+
+    /// MAC over the signed part of a token.
+    fn mac(&self, payload: &str) -> HmacSha256 {
+        let mut mac = HmacSha256::new_varkey(&self.key).expect("HMAC accepts keys of any length.");
+        mac.update(payload.as_bytes());
+        mac
+    }
+
+    /// Issue a session token for a user, returning it with its expiry.
+    ///
+    /// # Arguments
+    /// * `user_id` - ID of the user the token authenticates.
+    /// * `lifetime_hours` - How long the token stays valid.
+    ///
+    /// # Examples
+    /// ```
+    /// let (token, expires_at) = signer.issue(user.id, SESSION_LIFETIME_HOURS);
+    /// ```
+    pub fn issue(&self, user_id: i32, lifetime_hours: i64) -> (String, chrono::DateTime<Utc>) {
+        let expires_at = Utc::now() + Duration::hours(lifetime_hours);
+        let payload = format!("{}.{}", user_id, expires_at.timestamp());
+        let signature = hex::encode(self.mac(&payload).finalize().into_bytes());
+
+        (format!("{}.{}", payload, signature), expires_at)
+    }
+
+    /// Verify a session token, returning the ID of the user it authenticates.
+    ///
+    /// # Arguments
+    /// * `token` - Token previously returned by `issue`.
+    ///
+    /// # Examples
+    /// ```
+    /// let user_id = signer.verify(&token)?;
+    /// ```
+    pub fn verify(&self, token: &str) -> Result<i32, String> {
+        let parts = token.splitn(3, '.').collect::<Vec<&str>>();
+        if parts.len() != 3 {
+            return Err(String::from("Malformed session token."));
+        }
+
+        // Check the signature before trusting anything in the payload
+        let payload = format!("{}.{}", parts[0], parts[1]);
+        let signature = hex::decode(parts[2]).map_err(|_| String::from("Malformed session token."))?;
+        if self.mac(&payload).verify(&signature).is_err() {
+            return Err(String::from("Invalid session token."));
+        }
+
+        let user_id = parts[0].parse::<i32>().map_err(|_| String::from("Malformed session token."))?;
+        let expires_at = parts[1].parse::<i64>().map_err(|_| String::from("Malformed session token."))?;
+        if expires_at < Utc::now().timestamp() {
+            return Err(String::from("Session token has expired."));
+        }
+        Ok(user_id)
+    }
+}
+
+/// Hash an API key for storage and lookup. Keys are long and
+/// random, so a fast hash is enough to keep them safe at rest.
+///
+/// # Arguments
+/// * `key` - The full API key.
+///
+/// # Examples
+/// ```
+/// let key_hash = hash_api_key(&key);
+/// ```
+pub fn hash_api_key(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+/// Generate and store a new API key for a user. The returned
+/// key is the only time its full value is available.
+///
+/// # Arguments
+/// * `storage` - Storage to record the key in.
+/// * `user_id` - ID of the user owning the key.
+/// * `name` - Label given to the key by its owner.
+///
+/// # Examples
+/// ```
+/// let created = create_api_key(storage.as_ref(), user.id, "ci-bot")?;
+/// ```
+pub fn create_api_key(storage: &dyn Storage, user_id: i32, name: &str) -> Result<CreatedApiKey, String> {
+    let mut rng = rand::thread_rng();
+    let prefix = format!("{}{}", API_KEY_MARKER, hex::encode(rng.gen::<[u8; 4]>()));
+    let key = format!("{}_{}", prefix, hex::encode(rng.gen::<[u8; 24]>()));
+
+    let api_key = storage.create_api_key(user_id, name, &prefix, &hash_api_key(&key))?;
+    Ok(CreatedApiKey {
+        key,
+        api_key
+    })
+}
+
+/// Extract the credential from an `Authorization: Bearer` header.
+fn bearer_token<'a>(request: &'a Request) -> Option<&'a str> {
+    request.headers()
+           .get_one("Authorization")
+           .and_then(|header| header.strip_prefix("Bearer "))
+           .map(|token| token.trim())
+}
+
+/// The user behind a request, if it carried credentials. Both
+/// session tokens and API keys are accepted as bearer credentials.
+///
+/// Requests without credentials resolve to `None`; requests
+/// with invalid credentials are refused outright. Failures to
+/// look credentials up are server errors, not bad credentials,
+/// so clients do not log in again over an outage.
+pub struct CurrentUser(pub Option<User>);
+
+impl<'a, 'r> FromRequest<'a, 'r> for CurrentUser {
+    type Error = String;
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        let token = match bearer_token(request) {
+            Some(token) => token,
+            None => return Outcome::Success(CurrentUser(None))
+        };
+
+        let (signer, storage) = match (request.guard::<State<SessionSigner>>().succeeded(),
+                                       request.guard::<State<Arc<dyn Storage>>>().succeeded()) {
+            (Some(signer), Some(storage)) => (signer, storage),
+            _ => return Outcome::Failure((Status::InternalServerError, String::from("Authentication is not configured.")))
+        };
+        let user = if token.starts_with(API_KEY_MARKER) {
+            storage.get_user_by_api_key(&hash_api_key(token))
+        } else {
+            match signer.verify(token) {
+                Ok(user_id) => storage.get_user(user_id),
+                Err(msg) => return Outcome::Failure((Status::Unauthorized, msg))
+            }
+        };
+
+        match user {
+            Ok(Some(user)) => {
+                request.local_cache(|| RequestUser(Some(user.username.clone())));
+                Outcome::Success(CurrentUser(Some(user)))
+            },
+            Ok(None) => Outcome::Failure((Status::Unauthorized, String::from("Unknown or revoked credentials."))),
+            Err(msg) => {
+                error!("Failed to look up credentials: {}", msg);
+                Outcome::Failure((Status::InternalServerError, String::from("Failed to look up credentials.")))
+            }
+        }
+    }
+}
+
+/// A user who must be logged in for the request to proceed.
+pub struct AuthenticatedUser(pub User);
+
+impl<'a, 'r> FromRequest<'a, 'r> for AuthenticatedUser {
+    type Error = String;
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        match request.guard::<CurrentUser>()? {
+            CurrentUser(Some(user)) => Outcome::Success(AuthenticatedUser(user)),
+            CurrentUser(None) => Outcome::Failure((Status::Unauthorized, String::from("Authentication required.")))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn issued_tokens_verify() {
+        let signer = SessionSigner::new("secret");
+        let (token, expires_at) = signer.issue(42, SESSION_LIFETIME_HOURS);
+
+        assert_eq!(signer.verify(&token), Ok(42));
+        assert!(expires_at > Utc::now());
+    }
+
+    #[test]
+    fn expired_tokens_are_refused() {
+        let signer = SessionSigner::new("secret");
+        let (token, _) = signer.issue(42, -1);
+
+        assert_eq!(signer.verify(&token), Err(String::from("Session token has expired.")));
+    }
+
+    #[test]
+    fn tampered_tokens_are_refused() {
+        let signer = SessionSigner::new("secret");
+        let (token, _) = signer.issue(42, SESSION_LIFETIME_HOURS);
+        let parts = token.split('.').collect::<Vec<&str>>();
+        let invalid = Err(String::from("Invalid session token."));
+
+        // Another user, a later expiry, a flipped signature or another key
+        assert_eq!(signer.verify(&format!("1.{}.{}", parts[1], parts[2])), invalid);
+        assert_eq!(signer.verify(&format!("{}.{}.{}", parts[0], i64::MAX, parts[2])), invalid);
+        let flipped = if parts[2].starts_with('0') { "1" } else { "0" };
+        assert_eq!(signer.verify(&format!("{}.{}.{}{}", parts[0], parts[1], flipped, &parts[2][1..])), invalid);
+        assert_eq!(SessionSigner::new("other secret").verify(&token), invalid);
+    }
+
+    #[test]
+    fn malformed_tokens_are_refused() {
+        let signer = SessionSigner::new("secret");
+        let malformed = Err(String::from("Malformed session token."));
+
+        assert_eq!(signer.verify(""), malformed);
+        assert_eq!(signer.verify("42.123"), malformed);
+        assert_eq!(signer.verify("42.123.not-hex"), malformed);
+    }
+}
@@ -0,0 +1,50 @@
+/**
+ * Structured errors returned by GraphQL resolvers. Each
+ * carries a machine readable `code` extension so clients
+ * can react to failures without parsing messages.
+ */
+use std::fmt::Display;
+use juniper::FieldError;
+
+/// Build an error with the given code extension.
+///
+/// # Arguments
+/// * `code` - Machine readable error code.
+/// * `message` - Human readable description of the error.
+///
+/// # Examples
+/// ```
+/// return Err(error("CONFLICT", "Username is already taken."));
+/// ```
+pub fn error<T: Display>(code: &str, message: T) -> FieldError {
+    FieldError::new(message, graphql_value!({ "code": code }))
+}
+
+/// The request needs a logged in user but carried no credentials.
+pub fn unauthenticated() -> FieldError {
+    error("UNAUTHENTICATED", "You must be logged in to do this.")
+}
+
+/// The logged in user is not allowed to do what they asked.
+///
+/// # Arguments
+/// * `message` - Why the request was refused.
+pub fn forbidden<T: Display>(message: T) -> FieldError {
+    error("FORBIDDEN", message)
+}
+
+/// Something the request refers to does not exist.
+///
+/// # Arguments
+/// * `message` - What could not be found.
+pub fn not_found<T: Display>(message: T) -> FieldError {
+    error("NOT_FOUND", message)
+}
+
+/// The request carried invalid arguments.
+///
+/// # Arguments
+/// * `message` - What was wrong with the arguments.
+pub fn invalid_input<T: Display>(message: T) -> FieldError {
+    error("INVALID_INPUT", message)
+}
@@ -0,0 +1,221 @@
+/**
+ * GraphQL requests as received over HTTP. These are read the
+ * same way as juniper_rocket reads its own, but keep the name
+ * of the operation they run so it can be counted by the metrics.
+ */
+use std::io::Read;
+use juniper::{
+    InputValue,
+    http
+};
+use juniper_rocket::GraphQLResponse;
+use rocket::{
+    Data,
+    Request,
+    Outcome::{
+        Failure,
+        Forward,
+        Success
+    },
+    data::{
+        self,
+        FromDataSimple
+    },
+    http::Status,
+    request::{
+        FormItems,
+        FromForm
+    }
+};
+use serde_json::Value;
+
+// Local imports
+use crate::dataserver::{
+    metrics::Metrics,
+    schema::{
+        Context,
+        Schema
+    }
+};
+
+/// A GraphQL request, along with the name of the operation
+/// it asks to run. juniper keeps that name to itself, so it
+/// is picked out while the request is read.
+#[derive(Debug)]
+pub struct NamedRequest {
+    request: http::GraphQLRequest,
+    operation_name: Option<String>
+}
+
+/// A single GraphQL request, or a batch of them.
+#[derive(Debug)]
+pub enum GraphQLRequest {
+    Single(NamedRequest),
+    Batch(Vec<NamedRequest>)
+}
+
+impl NamedRequest {
+
+    /// Read a request from its JSON body.
+    ///
+    /// # Arguments
+    /// * `value` - Body of the request.
+    fn from_value(value: Value) -> Result<NamedRequest, serde_json::Error> {
+        let operation_name = value["operationName"].as_str().map(String::from);
+        let request = serde_json::from_value(value)?;
+        Ok(NamedRequest { request, operation_name })
+    }
+}
+
+/// Get the code of every error in a response. Requests that could
+/// not be executed at all have their errors counted as `INVALID_QUERY`.
+///
+/// # Arguments
+/// * `response` - Response to look through.
+fn error_codes(response: &http::GraphQLResponse) -> Vec<String> {
+    let body = serde_json::to_value(response).unwrap_or(Value::Null);
+    let errors = match body["errors"].as_array() {
+        Some(errors) => errors,
+        None => return Vec::new()
+    };
+
+    if !response.is_ok() {
+        return vec![String::from("INVALID_QUERY"); errors.len()];
+    }
+    errors.iter()
+          .map(|err| err["extensions"]["code"].as_str().unwrap_or("UNKNOWN").to_string())
+          .collect()
+}
+
+impl GraphQLRequest {
+
+    /// The requests to execute, whether batched or not.
+    fn requests(&self) -> Vec<&NamedRequest> {
+        match self {
+            GraphQLRequest::Single(request) => vec![request],
+            GraphQLRequest::Batch(requests) => requests.iter().collect()
+        }
+    }
+
+    /// Execute the request, recording each operation in the metrics.
+    ///
+    /// # Arguments
+    /// * `schema` - Schema to execute against.
+    /// * `context` - Context of the request.
+    /// * `metrics` - Metrics to record operations in.
+    ///
+    /// # Examples
+    /// ```
+    /// request.execute(&schema, &context, &metrics)
+    /// ```
+    pub fn execute(&self, schema: &Schema, context: &Context, metrics: &Metrics) -> GraphQLResponse {
+        let mut ok = true;
+        let mut bodies = Vec::new();
+
+        for named in self.requests() {
+            let response = named.request.execute(schema, context);
+            metrics.observe_graphql_operation(named.operation_name.as_ref().map(String::as_str), &error_codes(&response));
+
+            ok = ok && response.is_ok();
+            bodies.push(serde_json::to_string(&response).unwrap_or_default());
+        }
+
+        let status = if ok { Status::Ok } else { Status::BadRequest };
+        match self {
+            GraphQLRequest::Single(_) => GraphQLResponse(status, bodies.concat()),
+            GraphQLRequest::Batch(_) => GraphQLResponse(status, format!("[{}]", bodies.join(",")))
+        }
+    }
+}
+
+impl<'f> FromForm<'f> for GraphQLRequest {
+    type Error = String;
+
+    fn from_form(form_items: &mut FormItems<'f>, strict: bool) -> Result<Self, String> {
+        let mut query = None;
+        let mut operation_name = None;
+        let mut variables = None;
+
+        for form_item in form_items {
+            let (key, value) = form_item.key_value();
+            let decoded = || value.url_decode().map_err(|err| err.to_string());
+            match key.as_str() {
+                "query" if query.is_some() => return Err(String::from("Query parameter must not occur more than once")),
+                "query" => query = Some(decoded()?),
+                "operation_name" if operation_name.is_some() => return Err(String::from("Operation name parameter must not occur more than once")),
+                "operation_name" => operation_name = Some(decoded()?),
+                "variables" if variables.is_some() => return Err(String::from("Variables parameter must not occur more than once")),
+                "variables" => variables = Some(serde_json::from_str::<InputValue>(&decoded()?).map_err(|err| err.to_string())?),
+                _ if strict => return Err(format!("Prohibited extra field '{}'", key)),
+                _ => {}
+            }
+        }
+
+        match query {
+            Some(query) => Ok(GraphQLRequest::Single(NamedRequest {
+                request: http::GraphQLRequest::new(query, operation_name.clone(), variables),
+                operation_name
+            })),
+            None => Err(String::from("Query parameter missing"))
+        }
+    }
+}
+
+impl FromDataSimple for GraphQLRequest {
+    type Error = String;
+
+    fn from_data(request: &Request, data: Data) -> data::Outcome<Self, String> {
+        if !request.content_type().map_or(false, |ct| ct.is_json()) {
+            return Forward(data);
+        }
+
+        let mut body = String::new();
+        if let Err(err) = data.open().read_to_string(&mut body) {
+            return Failure((Status::InternalServerError, err.to_string()));
+        }
+
+        let parsed = match serde_json::from_str::<Value>(&body) {
+            Ok(Value::Array(requests)) => requests.into_iter()
+                                                  .map(NamedRequest::from_value)
+                                                  .collect::<Result<Vec<NamedRequest>, _>>()
+                                                  .map(GraphQLRequest::Batch),
+            Ok(request) => NamedRequest::from_value(request).map(GraphQLRequest::Single),
+            Err(err) => Err(err)
+        };
+        match parsed {
+            Ok(request) => Success(request),
+            Err(err) => Failure((Status::BadRequest, err.to_string()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use juniper::{
+        FieldError,
+        graphql_value
+    };
+
+    #[test]
+    fn operation_name_is_kept() {
+        let body = serde_json::json!({ "query": "query Questions { questions { id } }", "operationName": "Questions" });
+        let named = NamedRequest::from_value(body).unwrap();
+        assert_eq!(named.operation_name, Some(String::from("Questions")));
+    }
+
+    #[test]
+    fn operation_name_may_be_missing() {
+        let body = serde_json::json!({ "query": "{ questions { id } }" });
+        assert_eq!(NamedRequest::from_value(body).unwrap().operation_name, None);
+    }
+
+    #[test]
+    fn error_codes_are_read_from_extensions() {
+        let response = http::GraphQLResponse::error(FieldError::new("Question not found.", graphql_value!({ "code": "NOT_FOUND" })));
+        assert_eq!(error_codes(&response), vec![String::from("NOT_FOUND")]);
+
+        let response = http::GraphQLResponse::error(FieldError::new("Something broke.", graphql_value!(None)));
+        assert_eq!(error_codes(&response), vec![String::from("UNKNOWN")]);
+    }
+}
@@ -0,0 +1,151 @@
+/**
+ * Liveness and readiness endpoints for the dataserver, so
+ * an orchestrator can tell whether to restart the server
+ * or hold traffic back from it.
+ */
+use std::{
+    ffi::CString,
+    io,
+    os::unix::ffi::OsStrExt,
+    path::Path,
+    sync::Arc
+};
+use chrono::{
+    DateTime,
+    Utc
+};
+use rocket::{
+    State,
+    http::Status,
+    response::{
+        content,
+        status
+    }
+};
+use serde_json::{
+    Value,
+    json
+};
+
+// Local imports
+use crate::cli::environment::{
+    current_instance,
+    get_or_create_instance_home
+};
+use crate::dataserver::{
+    signals::{
+        LiveConfig,
+        read_config
+    },
+    storage::Storage
+};
+
+/// Free space below which RD_HOME counts as full, unless
+/// limits.health_min_free_mb says otherwise.
+pub const DEFAULT_MIN_FREE_MB: u64 = 100;
+
+/// Version of `rd` the server was built as.
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// When the server started, for reporting its uptime.
+pub struct StartedAt(pub DateTime<Utc>);
+
+/// Outcome of a single component check, as reported in JSON.
+fn check(result: Result<Value, String>) -> (bool, Value) {
+    match result {
+        Ok(Value::Object(mut details)) => {
+            details.insert(String::from("status"), json!("ok"));
+            (true, Value::Object(details))
+        },
+        Ok(_) => (true, json!({ "status": "ok" })),
+        Err(msg) => (false, json!({ "status": "fail", "error": msg }))
+    }
+}
+
+/// Get the space available to unprivileged users on the
+/// filesystem holding the given path, in bytes.
+///
+/// # Arguments
+/// * `path` - Any path on the filesystem.
+fn available_disk_space(path: &Path) -> Result<u64, String> {
+    let path = CString::new(path.as_os_str().as_bytes()).map_err(|err| err.to_string())?;
+    let mut stats: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stats) } != 0 {
+        return Err(io::Error::last_os_error().to_string());
+    }
+    Ok(stats.f_bavail as u64 * stats.f_frsize as u64)
+}
+
+/// Check that the storage backend answers.
+fn check_storage(storage: &dyn Storage) -> Result<Value, String> {
+    storage.ping().map(|_| Value::Null)
+}
+
+/// Check that every migration has been applied.
+fn check_migrations(storage: &dyn Storage) -> Result<Value, String> {
+    match storage.pending_migration_count()? {
+        0 => Ok(Value::Null),
+        pending => Err(format!("{} migration(s) pending.", pending))
+    }
+}
+
+/// Check that the instance's home has room left for the
+/// database and logs.
+///
+/// # Arguments
+/// * `min_free_mb` - Free space below which the check fails.
+fn check_disk_space(min_free_mb: u64) -> Result<Value, String> {
+    let home = get_or_create_instance_home(&current_instance())?;
+    let free_mb = available_disk_space(Path::new(&home))? / (1024 * 1024);
+
+    if free_mb >= min_free_mb {
+        Ok(json!({ "free_mb": free_mb, "min_free_mb": min_free_mb }))
+    } else {
+        Err(format!("Only {}MB free in {}, below the minimum of {}MB.", free_mb, home, min_free_mb))
+    }
+}
+
+/// Build a health report from the results of component checks,
+/// answering 503 if any of them failed.
+///
+/// # Arguments
+/// * `started_at` - When the server started.
+/// * `checks` - Name and result of each check.
+fn report(started_at: &StartedAt, checks: Vec<(&str, Result<Value, String>)>) -> status::Custom<content::Json<String>> {
+    let mut healthy = true;
+    let mut components = serde_json::Map::new();
+    for (name, result) in checks {
+        let (ok, details) = check(result);
+        healthy = healthy && ok;
+        components.insert(name.to_string(), details);
+    }
+
+    let body = json!({
+        "status": if healthy { "ok" } else { "fail" },
+        "version": VERSION,
+        "started_at": started_at.0.to_rfc3339(),
+        "uptime_seconds": (Utc::now() - started_at.0).num_seconds().max(0),
+        "checks": components
+    });
+    let status = if healthy { Status::Ok } else { Status::ServiceUnavailable };
+    status::Custom(status, content::Json(body.to_string()))
+}
+
+/// Report whether the server is up. This only fails if the server
+/// cannot answer at all, so it is safe to restart the server on failure.
+#[rocket::get("/health/live")]
+pub fn live(started_at: State<StartedAt>) -> status::Custom<content::Json<String>> {
+    report(&started_at, Vec::new())
+}
+
+/// Report whether the server is ready to take traffic: storage
+/// answers, the schema is current and RD_HOME has room left.
+#[rocket::get("/health/ready")]
+pub fn ready(started_at: State<StartedAt>, storage: State<Arc<dyn Storage>>, config: State<LiveConfig>) -> status::Custom<content::Json<String>> {
+    let storage = storage.inner().as_ref();
+    report(&started_at, vec![
+        ("storage", check_storage(storage)),
+        ("migrations", check_migrations(storage)),
+        ("disk", check_disk_space(read_config(&config).limits.health_min_free_mb))
+    ])
+}
@@ -0,0 +1,236 @@
+/**
+ * Prometheus metrics for the dataserver. Request, GraphQL
+ * and connection pool metrics are gathered alongside those
+ * of the process itself, and served as text from `/metrics`.
+ */
+use std::{
+    collections::HashSet,
+    sync::{
+        Arc,
+        Mutex
+    },
+    time::Duration
+};
+use prometheus::{
+    Encoder,
+    HistogramOpts,
+    HistogramVec,
+    IntCounterVec,
+    IntGauge,
+    Opts,
+    Registry,
+    TextEncoder
+};
+use rocket::{
+    Request,
+    Response,
+    State,
+    fairing::{
+        Fairing,
+        Info,
+        Kind
+    },
+    http::{
+        ContentType,
+        Status
+    },
+    response::content
+};
+
+// Local imports
+use crate::dataserver::{
+    access_log::request_latency,
+    storage::Storage
+};
+
+/// Most operation names given a label of their own. Names are
+/// chosen by clients, so past this they are counted as `other`.
+const MAX_OPERATION_NAMES: usize = 100;
+
+/// Label of requests no route matched.
+const UNMATCHED_ROUTE: &str = "unmatched";
+
+/// Metrics of the running dataserver.
+pub struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_request_duration: HistogramVec,
+    graphql_operations: IntCounterVec,
+    graphql_errors: IntCounterVec,
+    db_pool_connections: IntGauge,
+    db_pool_idle_connections: IntGauge,
+    db_pool_max_size: IntGauge,
+    operation_names: Mutex<HashSet<String>>
+}
+
+impl Metrics {
+
+    /// Create and register every metric of the dataserver.
+    ///
+    /// # Examples
+    /// ```
+    /// let metrics = Arc::new(Metrics::new()?);
+    /// ```
+    pub fn new() -> Result<Metrics, String> {
+        let registry = Registry::new_custom(Some(String::from("rd")), None).map_err(|err| err.to_string())?;
+
+        let http_requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests answered, by route and status."),
+            &["method", "route", "status"]
+        ).map_err(|err| err.to_string())?;
+        let http_request_duration = HistogramVec::new(
+            HistogramOpts::new("http_request_duration_seconds", "Time taken to answer HTTP requests, by route."),
+            &["method", "route"]
+        ).map_err(|err| err.to_string())?;
+        let graphql_operations = IntCounterVec::new(
+            Opts::new("graphql_operations_total", "GraphQL operations executed, by operation name."),
+            &["name"]
+        ).map_err(|err| err.to_string())?;
+        let graphql_errors = IntCounterVec::new(
+            Opts::new("graphql_errors_total", "Errors returned by GraphQL operations, by operation name and error code."),
+            &["name", "code"]
+        ).map_err(|err| err.to_string())?;
+        let db_pool_connections = IntGauge::new("db_pool_connections", "Connections held by the database pool.")
+                                          .map_err(|err| err.to_string())?;
+        let db_pool_idle_connections = IntGauge::new("db_pool_idle_connections", "Idle connections held by the database pool.")
+                                               .map_err(|err| err.to_string())?;
+        let db_pool_max_size = IntGauge::new("db_pool_max_size", "Most connections the database pool will hold.")
+                                       .map_err(|err| err.to_string())?;
+
+        registry.register(Box::new(http_requests.clone())).map_err(|err| err.to_string())?;
+        registry.register(Box::new(http_request_duration.clone())).map_err(|err| err.to_string())?;
+        registry.register(Box::new(graphql_operations.clone())).map_err(|err| err.to_string())?;
+        registry.register(Box::new(graphql_errors.clone())).map_err(|err| err.to_string())?;
+        registry.register(Box::new(db_pool_connections.clone())).map_err(|err| err.to_string())?;
+        registry.register(Box::new(db_pool_idle_connections.clone())).map_err(|err| err.to_string())?;
+        registry.register(Box::new(db_pool_max_size.clone())).map_err(|err| err.to_string())?;
+
+        // CPU, memory and file descriptors of the server process
+        #[cfg(target_os = "linux")]
+        registry.register(Box::new(prometheus::process_collector::ProcessCollector::for_self()))
+                .map_err(|err| err.to_string())?;
+
+        Ok(Metrics {
+            registry,
+            http_requests,
+            http_request_duration,
+            graphql_operations,
+            graphql_errors,
+            db_pool_connections,
+            db_pool_idle_connections,
+            db_pool_max_size,
+            operation_names: Mutex::new(HashSet::new())
+        })
+    }
+
+    /// Record an answered HTTP request.
+    ///
+    /// # Arguments
+    /// * `method` - Method of the request.
+    /// * `route` - Path of the route that handled the request.
+    /// * `status` - Status code of the response.
+    /// * `latency` - Time taken to answer, if known.
+    pub fn observe_request(&self, method: &str, route: &str, status: u16, latency: Option<Duration>) {
+        self.http_requests.with_label_values(&[method, route, &status.to_string()]).inc();
+        if let Some(latency) = latency {
+            self.http_request_duration.with_label_values(&[method, route]).observe(latency.as_secs_f64());
+        }
+    }
+
+    /// Record an executed GraphQL operation and the errors it returned.
+    ///
+    /// # Arguments
+    /// * `name` - Operation name given by the request, if any.
+    /// * `error_codes` - Code of each error in the response.
+    ///
+    /// # Examples
+    /// ```
+    /// metrics.observe_graphql_operation(Some("AskQuestion"), &[]);
+    /// ```
+    pub fn observe_graphql_operation<S: AsRef<str>>(&self, name: Option<&str>, error_codes: &[S]) {
+        let name = self.operation_label(name);
+        self.graphql_operations.with_label_values(&[&name]).inc();
+        for code in error_codes {
+            self.graphql_errors.with_label_values(&[&name, code.as_ref()]).inc();
+        }
+    }
+
+    /// Label an operation name, keeping the number of labels in check.
+    /// Requests that do not name their operation are labelled `unknown`.
+    fn operation_label(&self, name: Option<&str>) -> String {
+        let name = match name {
+            Some(name) => name,
+            None => return String::from("unknown")
+        };
+
+        let mut names = match self.operation_names.lock() {
+            Ok(names) => names,
+            Err(poisoned) => poisoned.into_inner()
+        };
+        if names.contains(name) {
+            name.to_string()
+        } else if names.len() < MAX_OPERATION_NAMES {
+            names.insert(name.to_string());
+            name.to_string()
+        } else {
+            String::from("other")
+        }
+    }
+
+    /// Render every metric in the Prometheus text format.
+    ///
+    /// # Arguments
+    /// * `storage` - Storage whose connection pool is reported.
+    ///
+    /// # Examples
+    /// ```
+    /// let text = metrics.render(storage.as_ref())?;
+    /// ```
+    pub fn render(&self, storage: &dyn Storage) -> Result<String, String> {
+        // Pool usage is only meaningful at the moment it is scraped
+        if let Some(stats) = storage.pool_stats() {
+            self.db_pool_connections.set(stats.connections as i64);
+            self.db_pool_idle_connections.set(stats.idle_connections as i64);
+            self.db_pool_max_size.set(stats.max_size as i64);
+        }
+
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer).map_err(|err| err.to_string())?;
+        String::from_utf8(buffer).map_err(|err| err.to_string())
+    }
+}
+
+/// Fairing recording every answered request in the metrics.
+/// Latency is measured from when the access log saw the request arrive.
+pub struct MetricsFairing(pub Arc<Metrics>);
+
+impl Fairing for MetricsFairing {
+    fn info(&self) -> Info {
+        Info {
+            name: "Metrics",
+            kind: Kind::Response
+        }
+    }
+
+    fn on_response(&self, request: &Request, response: &mut Response) {
+        // Label by route rather than path, so IDs in paths do not
+        // make a new series each
+        let route = request.route().map_or_else(|| String::from(UNMATCHED_ROUTE), |route| route.uri.path().to_string());
+        self.0.observe_request(request.method().as_str(), &route, response.status().code, request_latency(request));
+    }
+}
+
+/// Serve the metrics in the Prometheus text format.
+#[rocket::get("/metrics")]
+pub fn metrics(
+    metrics: State<Arc<Metrics>>,
+    storage: State<Arc<dyn Storage>>
+) -> Result<content::Content<String>, Status> {
+    match metrics.render(storage.inner().as_ref()) {
+        Ok(text) => Ok(content::Content(ContentType::with_params("text", "plain", ("version", "0.0.4")), text)),
+        Err(msg) => {
+            error!("Failed to render metrics: {}", msg);
+            Err(Status::InternalServerError)
+        }
+    }
+}
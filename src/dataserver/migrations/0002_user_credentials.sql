@@ -0,0 +1,3 @@
+-- Password hashes for user accounts. Users created before accounts
+-- existed have no password and cannot log in until one is set.
+ALTER TABLE users ADD COLUMN password_hash TEXT;
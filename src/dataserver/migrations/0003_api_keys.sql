@@ -0,0 +1,14 @@
+-- Per-user API keys for CLI and bot clients. Only a hash of each key
+-- is kept; the prefix lets owners tell their keys apart.
+CREATE TABLE api_keys (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER NOT NULL REFERENCES users(id),
+    name TEXT NOT NULL,
+    prefix TEXT NOT NULL,
+    key_hash TEXT NOT NULL UNIQUE,
+    created_at TEXT NOT NULL,
+    last_used_at TEXT,
+    revoked_at TEXT
+);
+
+CREATE INDEX api_keys_user_id ON api_keys (user_id);
@@ -0,0 +1,7 @@
+-- Roles for users, and moderation state for questions and answers.
+ALTER TABLE users ADD COLUMN role TEXT NOT NULL DEFAULT 'member';
+
+ALTER TABLE questions ADD COLUMN closed INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE questions ADD COLUMN locked INTEGER NOT NULL DEFAULT 0;
+
+ALTER TABLE answers ADD COLUMN hidden INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,203 @@
+/**
+ * Versioned, forward-only schema migrations for the
+ * dataserver database. Every migration is embedded in
+ * the binary and recorded in the `schema_migrations`
+ * table once applied.
+ */
+use chrono::{
+    DateTime,
+    Utc
+};
+use rusqlite::{
+    Connection,
+    params
+};
+
+/// A single schema migration.
+pub struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    pub sql: &'static str
+}
+
+/// A migration recorded as applied in the database.
+#[derive(Debug)]
+pub struct AppliedMigration {
+    pub version: i64,
+    pub name: String,
+    pub applied_at: DateTime<Utc>
+}
+
+/// Every migration known to this binary, in the order they apply.
+/// New migrations are only ever appended to this list.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "initial_schema",
+        sql: include_str!("0001_initial_schema.sql")
+    },
+    Migration {
+        version: 2,
+        name: "user_credentials",
+        sql: include_str!("0002_user_credentials.sql")
+    },
+    Migration {
+        version: 3,
+        name: "api_keys",
+        sql: include_str!("0003_api_keys.sql")
+    },
+    Migration {
+        version: 4,
+        name: "roles_and_moderation",
+        sql: include_str!("0004_roles_and_moderation.sql")
+    }
+];
+
+/// Table tracking which migrations have been applied.
+const MIGRATIONS_TABLE: &str = "
+    CREATE TABLE IF NOT EXISTS schema_migrations (
+        version INTEGER PRIMARY KEY,
+        name TEXT NOT NULL,
+        applied_at TEXT NOT NULL
+    );
+";
+
+/// List the migrations already applied to the database, oldest first.
+///
+/// # Arguments
+/// * `conn` - Connection to the dataserver database.
+///
+/// # Examples
+/// ```
+/// let applied = applied_migrations(&conn).unwrap();
+/// ```
+pub fn applied_migrations(conn: &Connection) -> Result<Vec<AppliedMigration>, String> {
+    conn.execute_batch(MIGRATIONS_TABLE).map_err(|err| err.to_string())?;
+
+    let mut statement = conn.prepare("SELECT version, name, applied_at FROM schema_migrations ORDER BY version")
+                            .map_err(|err| err.to_string())?;
+    let applied = statement.query_map(params![], |row| Ok(AppliedMigration {
+                                version: row.get(0)?,
+                                name: row.get(1)?,
+                                applied_at: row.get(2)?
+                            }))
+                           .and_then(|rows| rows.collect::<rusqlite::Result<Vec<AppliedMigration>>>())
+                           .map_err(|err| err.to_string())?;
+
+    // Migrations are forward-only, so a database ahead of this
+    // binary cannot safely be used by it.
+    if let Some(unknown) = applied.iter().find(|a| !MIGRATIONS.iter().any(|m| m.version == a.version)) {
+        return Err(format!(
+            "Database has migration {} ({}) applied which this version of rd does not know about.",
+            unknown.version,
+            unknown.name
+        ));
+    }
+    Ok(applied)
+}
+
+/// List the migrations not yet applied to the database, in the order they apply.
+///
+/// # Arguments
+/// * `conn` - Connection to the dataserver database.
+///
+/// # Examples
+/// ```
+/// if !pending_migrations(&conn).unwrap().is_empty() {
+///     // Refuse to start
+/// }
+/// ```
+pub fn pending_migrations(conn: &Connection) -> Result<Vec<&'static Migration>, String> {
+    let applied = applied_migrations(conn)?;
+    Ok(MIGRATIONS.iter()
+                 .filter(|m| !applied.iter().any(|a| a.version == m.version))
+                 .collect())
+}
+
+/// Fail unless every migration has been applied to the database.
+///
+/// # Arguments
+/// * `conn` - Connection to the dataserver database.
+///
+/// # Examples
+/// ```
+/// ensure_migrated(&conn)?;
+/// ```
+pub fn ensure_migrated(conn: &Connection) -> Result<(), String> {
+    match pending_migrations(conn)?.len() {
+        0 => Ok(()),
+        pending => Err(format!("{} migration(s) pending. Run `rd dataserver migrate` first.", pending))
+    }
+}
+
+/// Apply every pending migration, each within its own transaction,
+/// and return the versions that were applied.
+///
+/// # Arguments
+/// * `conn` - Connection to the dataserver database.
+///
+/// # Examples
+/// ```
+/// let applied = run_migrations(&mut conn).unwrap();
+/// ```
+pub fn run_migrations(conn: &mut Connection) -> Result<Vec<i64>, String> {
+    let mut applied = Vec::new();
+
+    for migration in pending_migrations(conn)? {
+        info!("Applying migration {} ({})...", migration.version, migration.name);
+
+        let transaction = conn.transaction().map_err(|err| err.to_string())?;
+        transaction.execute_batch(migration.sql)
+                   .map_err(|err| format!("Migration {} ({}) failed: {}", migration.version, migration.name, err))?;
+        transaction.execute(
+                        "INSERT INTO schema_migrations (version, name, applied_at) VALUES (?1, ?2, ?3)",
+                        params![migration.version, migration.name, Utc::now()])
+                   .map_err(|err| err.to_string())?;
+        transaction.commit().map_err(|err| err.to_string())?;
+
+        applied.push(migration.version);
+    }
+    Ok(applied)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Names of the tables in the database, other than SQLite's own.
+    fn tables(conn: &Connection) -> Vec<String> {
+        let mut statement = conn.prepare("SELECT name FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%' ORDER BY name")
+                                .unwrap();
+        statement.query_map(params![], |row| row.get(0))
+                 .and_then(|rows| rows.collect())
+                 .unwrap()
+    }
+
+    #[test]
+    fn migrations_apply_in_order_once() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        assert_eq!(pending_migrations(&conn).unwrap().len(), MIGRATIONS.len());
+        assert!(ensure_migrated(&conn).is_err());
+
+        let versions = MIGRATIONS.iter().map(|m| m.version).collect::<Vec<i64>>();
+        assert_eq!(run_migrations(&mut conn).unwrap(), versions);
+        assert!(pending_migrations(&conn).unwrap().is_empty());
+        assert!(ensure_migrated(&conn).is_ok());
+        assert!(run_migrations(&mut conn).unwrap().is_empty());
+
+        let applied = applied_migrations(&conn).unwrap();
+        assert_eq!(applied.iter().map(|a| a.version).collect::<Vec<i64>>(), versions);
+        assert_eq!(applied[0].name, "initial_schema");
+        assert_eq!(tables(&conn), vec!["answers", "api_keys", "comments", "question_tags", "questions", "schema_migrations", "users"]);
+    }
+
+    #[test]
+    fn unknown_migrations_are_refused() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        run_migrations(&mut conn).unwrap();
+        conn.execute("INSERT INTO schema_migrations (version, name, applied_at) VALUES (999, 'from_the_future', ?1)", params![Utc::now()])
+            .unwrap();
+
+        assert!(pending_migrations(&conn).is_err());
+    }
+}
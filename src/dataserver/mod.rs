@@ -2,9 +2,67 @@
  * Primary entry point for the dataserver for
  * Rubber Ducks
  */
-use rocket::config::{
-    Config,
-    Environment
+use std::{
+    sync::{
+        Arc,
+        RwLock
+    },
+    thread
+};
+use chrono::Utc;
+use rocket::{
+    State,
+    config::{
+        Config,
+        Environment
+    },
+    request::Form,
+    response::content
+};
+
+pub mod access_log;
+pub mod auth;
+pub mod errors;
+pub mod graphql;
+pub mod health;
+pub mod metrics;
+pub mod migrations;
+pub mod models;
+pub mod schema;
+pub mod signals;
+pub mod storage;
+
+use crate::cli::config::{
+    RdConfig,
+    ServerConfig
+};
+use access_log::AccessLog;
+use graphql::GraphQLRequest;
+use health::StartedAt;
+use metrics::{
+    Metrics,
+    MetricsFairing
+};
+use auth::{
+    AuthenticatedUser,
+    CurrentUser,
+    SessionSigner
+};
+use schema::{
+    Context,
+    Schema,
+    create_schema
+};
+use signals::{
+    DrainFairing,
+    LiveConfig,
+    ShutdownState,
+    handle_signals,
+    read_config
+};
+use storage::{
+    Storage,
+    open_storage
 };
 
 #[rocket::get("/")]
@@ -12,17 +70,157 @@ pub fn health_check() -> &'static str {
     "Server is alive."
 }
 
-pub fn start_dataserver(host: &str, port: u16, workers: u16) {
+/// Report which user the request is authenticated as.
+#[rocket::get("/whoami")]
+pub fn whoami(user: AuthenticatedUser) -> String {
+    user.0.username
+}
+
+/// Serve the GraphiQL playground pointed at the GraphQL endpoint.
+#[rocket::get("/graphiql")]
+pub fn graphiql() -> content::Html<String> {
+    juniper_rocket::graphiql_source("/graphql")
+}
+
+/// Execute a GraphQL request passed through the query string.
+#[rocket::get("/graphql?<request..>")]
+pub fn get_graphql_handler(
+    request: Form<GraphQLRequest>,
+    schema: State<Schema>,
+    storage: State<Arc<dyn Storage>>,
+    signer: State<SessionSigner>,
+    config: State<LiveConfig>,
+    metrics: State<Arc<Metrics>>,
+    user: CurrentUser
+) -> juniper_rocket::GraphQLResponse {
+    request.execute(&schema, &Context::new(storage.inner().clone(), signer.inner().clone(), read_config(&config), user.0), &metrics)
+}
+
+/// Execute a GraphQL request passed in the request body.
+#[rocket::post("/graphql", data = "<request>")]
+pub fn post_graphql_handler(
+    request: GraphQLRequest,
+    schema: State<Schema>,
+    storage: State<Arc<dyn Storage>>,
+    signer: State<SessionSigner>,
+    config: State<LiveConfig>,
+    metrics: State<Arc<Metrics>>,
+    user: CurrentUser
+) -> juniper_rocket::GraphQLResponse {
+    request.execute(&schema, &Context::new(storage.inner().clone(), signer.inner().clone(), read_config(&config), user.0), &metrics)
+}
+
+/// Build the configuration of an app listening on the given port,
+/// serving HTTPS if the server is given a certificate and rd is
+/// built with the tls feature.
+///
+/// # Arguments
+/// * `server` - Where and how the server listens.
+/// * `port` - Port to listen on.
+/// * `workers` - Number of worker threads.
+/// * `secret_key` - Secret key of the app.
+fn app_config(server: &ServerConfig, port: u16, workers: u16, secret_key: &str) -> Result<Config, String> {
+    let builder = Config::build(Environment::Production)
+                         .address(server.host.as_str())
+                         .port(port)
+                         .workers(workers)
+                         .secret_key(secret_key);
+
+    #[cfg(feature = "tls")]
+    let builder = match (&server.tls_cert, &server.tls_key) {
+        (Some(cert), Some(key)) => builder.tls(cert.as_str(), key.as_str()),
+        _ => builder
+    };
+    builder.finalize().map_err(|err| err.to_string())
+}
+
+/// Serve the metrics on their own port, away from the API, so
+/// they can be scraped without exposing them to clients.
+///
+/// # Arguments
+/// * `server` - Where and how the server listens.
+/// * `port` - Port to serve the metrics on.
+/// * `secret_key` - Secret key of the app.
+/// * `metrics` - Metrics to serve.
+/// * `storage` - Storage whose connection pool is reported.
+fn launch_metrics_server(server: &ServerConfig, port: u16, secret_key: &str, metrics: Arc<Metrics>, storage: Arc<dyn Storage>) -> Result<(), String> {
+    let config = app_config(server, port, 1, secret_key)?;
+
+    thread::Builder::new()
+        .name(String::from("metrics"))
+        .spawn(move || {
+            let err = rocket::custom(config)
+                          .manage(metrics)
+                          .manage(storage)
+                          .mount("/", rocket::routes![metrics::metrics])
+                          .launch();
+            error!("Metrics server stopped: {}", err);
+        })
+        .map(|_| ())
+        .map_err(|err| err.to_string())
+}
+
+/// Run the dataserver until it is told to stop.
+///
+/// # Arguments
+/// * `instance` - Instance the server belongs to.
+/// * `rd_config` - Checked configuration of the instance.
+/// * `flags` - Settings given as flags, merged in again on reload.
+///
+/// # Examples
+/// ```
+/// start_dataserver(instance, &config, cmd.flag_values());
+/// ```
+pub fn start_dataserver(instance: &str, rd_config: &RdConfig, flags: Vec<(&'static str, String)>) {
+    let server = &rd_config.server;
     let secret_key = std::env::var("RD_SECRET_KEY").expect("No secret key was set. Set RD_SECRET_KEY to a secret string fix this.");
 
-    let config = Config::build(Environment::Production)
-                        .address(host)
-                        .port(port)
-                        .workers(workers)
-                        .secret_key(secret_key)
-                        .finalize()
-                        .expect("Failed to establish configuration for app.");
+    let signer = SessionSigner::new(&secret_key);
+
+    let config = app_config(server, server.port, server.workers, &secret_key).expect("Failed to establish configuration for app.");
+    if server.tls_enabled() {
+        info!("Serving HTTPS with certificate {}.", server.tls_cert.as_ref().map_or("", String::as_str));
+    }
+    let storage = open_storage().expect("Failed to open dataserver storage.");
+    let metrics = Arc::new(Metrics::new().expect("Failed to set up metrics."));
+
+    // Drain requests and flush storage when asked to stop,
+    // and reload the configuration when asked to
+    let shutdown = Arc::new(ShutdownState::default());
+    let live_config: LiveConfig = Arc::new(RwLock::new(rd_config.reloadable()));
+    handle_signals(shutdown.clone(), storage.clone(), live_config.clone(), instance.to_string(), flags)
+        .expect("Failed to install signal handlers.");
+
+    let mut routes = rocket::routes![
+        health_check,
+        health::live,
+        health::ready,
+        whoami,
+        graphiql,
+        get_graphql_handler,
+        post_graphql_handler
+    ];
+
+    // Metrics are served alongside the API unless given a port of their own
+    match server.metrics_port {
+        Some(metrics_port) => {
+            info!("Serving metrics on {}:{}...", server.host, metrics_port);
+            launch_metrics_server(server, metrics_port, &secret_key, metrics.clone(), storage.clone())
+                .expect("Failed to start metrics server.");
+        },
+        None => routes.extend(rocket::routes![metrics::metrics])
+    }
+
     let app = rocket::custom(config);
-    app.mount("/", rocket::routes![health_check])
+    app.manage(create_schema())
+        .manage(StartedAt(Utc::now()))
+        .manage(storage)
+        .manage(signer)
+        .manage(live_config)
+        .manage(metrics.clone())
+        .attach(DrainFairing(shutdown))
+        .attach(AccessLog)
+        .attach(MetricsFairing(metrics))
+        .mount("/", routes)
         .launch();
 }
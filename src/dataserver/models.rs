@@ -0,0 +1,476 @@
+/**
+ * Domain types for the Rubber Ducks dataserver: the
+ * users, questions, answers and comments developers
+ * use to help one another, along with how each is
+ * exposed through GraphQL.
+ */
+use chrono::{
+    DateTime,
+    Utc
+};
+use std::str::FromStr;
+use juniper::FieldResult;
+
+use crate::dataserver::schema::Context;
+
+/// Default number of questions returned by a single page.
+pub const DEFAULT_PAGE_SIZE: i32 = 20;
+
+/// Largest number of questions a single page may request, unless
+/// limits.max_page_size says otherwise.
+pub const MAX_PAGE_SIZE: i32 = 100;
+
+/// What a user is allowed to do. Each role can do
+/// everything the roles before it can.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, GraphQLEnum)]
+pub enum Role {
+    /// Asks, answers and comments.
+    Member,
+
+    /// Closes and locks questions and hides answers.
+    Moderator,
+
+    /// Manages the roles of other users.
+    Admin
+}
+
+impl Role {
+
+    /// Name of the role as stored and typed on the CLI.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Member => "member",
+            Role::Moderator => "moderator",
+            Role::Admin => "admin"
+        }
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(role: &str) -> Result<Role, String> {
+        match role.to_lowercase().as_str() {
+            "member" => Ok(Role::Member),
+            "moderator" => Ok(Role::Moderator),
+            "admin" => Ok(Role::Admin),
+            other => Err(format!("Unknown role {}. Use member, moderator or admin.", other))
+        }
+    }
+}
+
+/// A member of Rubber Ducks.
+#[derive(Debug, Clone)]
+pub struct User {
+    pub id: i32,
+    pub username: String,
+    pub role: Role,
+    pub created_at: DateTime<Utc>
+}
+
+/// A question asked by a user.
+#[derive(Debug, Clone)]
+pub struct Question {
+    pub id: i32,
+    pub author_id: i32,
+    pub title: String,
+    pub body: String,
+    pub tags: Vec<String>,
+    pub accepted_answer_id: Option<i32>,
+    pub closed: bool,
+    pub locked: bool,
+    pub created_at: DateTime<Utc>
+}
+
+/// An answer posted to a question.
+#[derive(Debug, Clone)]
+pub struct Answer {
+    pub id: i32,
+    pub question_id: i32,
+    pub author_id: i32,
+    pub body: String,
+    pub hidden: bool,
+    pub created_at: DateTime<Utc>
+}
+
+/// A comment left on either a question or an answer.
+#[derive(Debug, Clone)]
+pub struct Comment {
+    pub id: i32,
+    pub author_id: i32,
+    pub question_id: Option<i32>,
+    pub answer_id: Option<i32>,
+    pub body: String,
+    pub created_at: DateTime<Utc>
+}
+
+/// A key letting a client act as its owner without logging in.
+#[derive(Debug, Clone)]
+pub struct ApiKey {
+    pub id: i32,
+    pub user_id: i32,
+    pub name: String,
+    pub prefix: String,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>
+}
+
+/// Criteria narrowing down which questions are listed.
+#[derive(Debug, Default, GraphQLInputObject)]
+pub struct QuestionFilter {
+    /// Only include questions carrying this tag.
+    pub tag: Option<String>,
+
+    /// Only include questions asked by this user.
+    pub author_id: Option<i32>,
+
+    /// Only include questions whose title or body contains this text.
+    pub search: Option<String>,
+
+    /// Only include questions without an accepted answer.
+    pub unanswered: Option<bool>
+}
+
+/// Offset based pagination of a listing.
+#[derive(Debug, Default, GraphQLInputObject)]
+pub struct Page {
+    /// Number of items to skip.
+    pub offset: Option<i32>,
+
+    /// Maximum number of items to return.
+    pub limit: Option<i32>
+}
+
+impl Page {
+
+    /// Number of items to skip, never negative.
+    pub fn offset(&self) -> usize {
+        self.offset.unwrap_or(0).max(0) as usize
+    }
+
+    /// Number of items to return, never negative.
+    pub fn limit(&self) -> usize {
+        self.limit.unwrap_or(DEFAULT_PAGE_SIZE).max(0) as usize
+    }
+
+    /// The same page, returning no more than the given number of items.
+    ///
+    /// # Arguments
+    /// * `max_page_size` - Largest number of items a page may return.
+    pub fn clamped(self, max_page_size: i32) -> Page {
+        Page {
+            offset: self.offset,
+            limit: Some(self.limit.unwrap_or(DEFAULT_PAGE_SIZE).min(max_page_size))
+        }
+    }
+}
+
+impl Question {
+
+    /// Tags as every storage backend keeps them: sorted, without duplicates.
+    ///
+    /// # Arguments
+    /// * `tags` - Tags given when asking the question.
+    ///
+    /// # Examples
+    /// ```
+    /// let tags = Question::normalize_tags(&input.tags);
+    /// ```
+    pub fn normalize_tags(tags: &[String]) -> Vec<String> {
+        let mut tags = tags.to_vec();
+        tags.sort();
+        tags.dedup();
+        tags
+    }
+
+    /// Whether the question passes the given filter.
+    ///
+    /// # Arguments
+    /// * `filter` - Filter to check the question against.
+    pub fn matches(&self, filter: &QuestionFilter) -> bool {
+        if let Some(tag) = &filter.tag {
+            if !self.tags.iter().any(|t| t == tag) {
+                return false;
+            }
+        }
+
+        if let Some(author_id) = filter.author_id {
+            if self.author_id != author_id {
+                return false;
+            }
+        }
+
+        // Only ASCII letters match regardless of case, as with SQLite's LIKE
+        if let Some(search) = &filter.search {
+            let search = search.to_ascii_lowercase();
+            if !self.title.to_ascii_lowercase().contains(&search) && !self.body.to_ascii_lowercase().contains(&search) {
+                return false;
+            }
+        }
+
+        if filter.unanswered == Some(true) && self.accepted_answer_id.is_some() {
+            return false;
+        }
+
+        true
+    }
+}
+
+impl Answer {
+
+    /// Whether the user making a request may see the answer.
+    /// Hidden answers are only shown to moderators.
+    ///
+    /// # Arguments
+    /// * `context` - Context of the request.
+    pub fn visible_to(&self, context: &Context) -> bool {
+        !self.hidden || context.has_role(Role::Moderator)
+    }
+}
+
+graphql_object!(User: Context |&self| {
+    description: "A member of Rubber Ducks."
+
+    field id() -> i32 {
+        self.id
+    }
+
+    field username() -> &str {
+        &self.username
+    }
+
+    field role() -> Role {
+        self.role
+    }
+
+    field created_at() -> DateTime<Utc> {
+        self.created_at
+    }
+
+    /// Questions asked by this user.
+    field questions(&executor, page: Option<Page>) -> FieldResult<Vec<Question>> {
+        let filter = QuestionFilter {
+            author_id: Some(self.id),
+            ..QuestionFilter::default()
+        };
+        let context = executor.context();
+        let page = page.unwrap_or_default().clamped(context.config.limits.max_page_size);
+        Ok(context.storage.list_questions(&filter, &page)?)
+    }
+});
+
+/// A logged in session, returned on registration and login.
+pub struct Session {
+    pub token: String,
+    pub expires_at: DateTime<Utc>,
+    pub user: User
+}
+
+graphql_object!(Session: Context |&self| {
+    description: "A logged in session."
+
+    /// Token to send as `Authorization: Bearer <token>`.
+    field token() -> &str {
+        &self.token
+    }
+
+    field expires_at() -> DateTime<Utc> {
+        self.expires_at
+    }
+
+    field user() -> &User {
+        &self.user
+    }
+});
+
+graphql_object!(ApiKey: Context |&self| {
+    description: "An API key for CLI and bot clients."
+
+    field id() -> i32 {
+        self.id
+    }
+
+    /// Label given to the key by its owner.
+    field name() -> &str {
+        &self.name
+    }
+
+    /// Start of the key, to tell keys apart.
+    field prefix() -> &str {
+        &self.prefix
+    }
+
+    field created_at() -> DateTime<Utc> {
+        self.created_at
+    }
+
+    field last_used_at() -> Option<DateTime<Utc>> {
+        self.last_used_at
+    }
+
+    field revoked_at() -> Option<DateTime<Utc>> {
+        self.revoked_at
+    }
+});
+
+/// A newly created API key along with its secret value.
+pub struct CreatedApiKey {
+    pub key: String,
+    pub api_key: ApiKey
+}
+
+graphql_object!(CreatedApiKey: Context |&self| {
+    description: "A newly created API key."
+
+    /// The full key. It is only ever shown this once.
+    field key() -> &str {
+        &self.key
+    }
+
+    field api_key() -> &ApiKey {
+        &self.api_key
+    }
+});
+
+graphql_object!(Question: Context |&self| {
+    description: "A question asked by a member."
+
+    field id() -> i32 {
+        self.id
+    }
+
+    field title() -> &str {
+        &self.title
+    }
+
+    field body() -> &str {
+        &self.body
+    }
+
+    field tags() -> &Vec<String> {
+        &self.tags
+    }
+
+    /// Closed questions accept no new answers.
+    field closed() -> bool {
+        self.closed
+    }
+
+    /// Locked questions accept no new answers, comments or acceptance.
+    field locked() -> bool {
+        self.locked
+    }
+
+    field created_at() -> DateTime<Utc> {
+        self.created_at
+    }
+
+    field author(&executor) -> FieldResult<Option<User>> {
+        Ok(executor.context().storage.get_user(self.author_id)?)
+    }
+
+    /// Answers to the question. Hidden answers are only listed for moderators.
+    field answers(&executor) -> FieldResult<Vec<Answer>> {
+        let context = executor.context();
+        let answers = context.storage.answers_for_question(self.id)?;
+        Ok(answers.into_iter().filter(|a| a.visible_to(context)).collect())
+    }
+
+    /// The answer the author accepted, if any. Left out if hidden,
+    /// unless requested by a moderator.
+    field accepted_answer(&executor) -> FieldResult<Option<Answer>> {
+        let context = executor.context();
+        match self.accepted_answer_id {
+            Some(answer_id) => Ok(context.storage.get_answer(answer_id)?.filter(|a| a.visible_to(context))),
+            None => Ok(None)
+        }
+    }
+
+    field comments(&executor) -> FieldResult<Vec<Comment>> {
+        Ok(executor.context().storage.comments_for_question(self.id)?)
+    }
+});
+
+graphql_object!(Answer: Context |&self| {
+    description: "An answer posted to a question."
+
+    field id() -> i32 {
+        self.id
+    }
+
+    field body() -> &str {
+        &self.body
+    }
+
+    /// Hidden answers were removed from view by a moderator.
+    field hidden() -> bool {
+        self.hidden
+    }
+
+    field created_at() -> DateTime<Utc> {
+        self.created_at
+    }
+
+    field author(&executor) -> FieldResult<Option<User>> {
+        Ok(executor.context().storage.get_user(self.author_id)?)
+    }
+
+    field question(&executor) -> FieldResult<Option<Question>> {
+        Ok(executor.context().storage.get_question(self.question_id)?)
+    }
+
+    /// Whether the question's author accepted this answer.
+    field accepted(&executor) -> FieldResult<bool> {
+        let question = executor.context().storage.get_question(self.question_id)?;
+        Ok(question.map_or(false, |q| q.accepted_answer_id == Some(self.id)))
+    }
+
+    field comments(&executor) -> FieldResult<Vec<Comment>> {
+        Ok(executor.context().storage.comments_for_answer(self.id)?)
+    }
+});
+
+graphql_object!(Comment: Context |&self| {
+    description: "A comment on a question or an answer."
+
+    field id() -> i32 {
+        self.id
+    }
+
+    field body() -> &str {
+        &self.body
+    }
+
+    field created_at() -> DateTime<Utc> {
+        self.created_at
+    }
+
+    field author(&executor) -> FieldResult<Option<User>> {
+        Ok(executor.context().storage.get_user(self.author_id)?)
+    }
+});
+
+/// Input for asking a new question.
+#[derive(Debug, GraphQLInputObject)]
+pub struct NewQuestion {
+    pub title: String,
+    pub body: String,
+    pub tags: Option<Vec<String>>
+}
+
+/// Input for answering a question.
+#[derive(Debug, GraphQLInputObject)]
+pub struct NewAnswer {
+    pub question_id: i32,
+    pub body: String
+}
+
+/// Input for commenting on a question or an answer. Exactly
+/// one of `question_id` and `answer_id` must be given.
+#[derive(Debug, GraphQLInputObject)]
+pub struct NewComment {
+    pub question_id: Option<i32>,
+    pub answer_id: Option<i32>,
+    pub body: String
+}
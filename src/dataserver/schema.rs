@@ -0,0 +1,537 @@
+/**
+ * GraphQL schema for the Rubber Ducks dataserver. All
+ * queries and mutations exposed over `/graphql` are
+ * declared here.
+ */
+use std::sync::Arc;
+use juniper::{
+    FieldError,
+    FieldResult,
+    RootNode
+};
+
+use crate::cli::config::ReloadableConfig;
+use crate::dataserver::{
+    auth::{
+        DUMMY_PASSWORD_HASH,
+        MIN_PASSWORD_LENGTH,
+        SessionSigner,
+        create_api_key,
+        hash_password,
+        verify_password
+    },
+    errors::{
+        error,
+        forbidden,
+        invalid_input,
+        not_found,
+        unauthenticated
+    },
+    models::{
+        ApiKey,
+        CreatedApiKey,
+        User,
+        Question,
+        Answer,
+        Comment,
+        QuestionFilter,
+        Page,
+        NewQuestion,
+        NewAnswer,
+        NewComment,
+        Role,
+        Session
+    },
+    storage::Storage
+};
+
+/// Per-request context handed to every GraphQL resolver.
+pub struct Context {
+    pub storage: Arc<dyn Storage>,
+    pub signer: SessionSigner,
+    pub config: ReloadableConfig,
+    pub user: Option<User>
+}
+
+impl juniper::Context for Context {}
+
+impl Context {
+
+    /// Create a new resolver context.
+    ///
+    /// # Arguments
+    /// * `storage` - Storage the resolvers read from and write to.
+    /// * `signer` - Signer issuing session tokens on login.
+    /// * `config` - Settings as they were when the request arrived.
+    /// * `user` - The user making the request, if logged in.
+    ///
+    /// # Examples
+    /// ```
+    /// let context = Context::new(storage.clone(), signer.clone(), read_config(&config), current_user.0);
+    /// ```
+    pub fn new(storage: Arc<dyn Storage>, signer: SessionSigner, config: ReloadableConfig, user: Option<User>) -> Context {
+        Context {
+            storage,
+            signer,
+            config,
+            user
+        }
+    }
+
+    /// The user making the request, failing if nobody is logged in.
+    pub fn require_user(&self) -> Result<&User, FieldError> {
+        self.user.as_ref().ok_or_else(unauthenticated)
+    }
+
+    /// Whether the user making the request has at least the given role.
+    ///
+    /// # Arguments
+    /// * `role` - Least role required.
+    pub fn has_role(&self, role: Role) -> bool {
+        self.user.as_ref().map_or(false, |user| user.role >= role)
+    }
+
+    /// The user making the request, failing unless they have at least the given role.
+    ///
+    /// # Arguments
+    /// * `role` - Least role required.
+    pub fn require_role(&self, role: Role) -> Result<&User, FieldError> {
+        let user = self.require_user()?;
+        if user.role >= role {
+            Ok(user)
+        } else {
+            Err(forbidden(format!("This requires the {} role.", role.as_str())))
+        }
+    }
+
+    /// Get a question that must exist.
+    fn find_question(&self, id: i32) -> Result<Question, FieldError> {
+        self.storage.get_question(id)?.ok_or_else(|| not_found(format!("Question {} does not exist.", id)))
+    }
+
+    /// Get an answer that must exist.
+    fn find_answer(&self, id: i32) -> Result<Answer, FieldError> {
+        self.storage.get_answer(id)?.ok_or_else(|| not_found(format!("Answer {} does not exist.", id)))
+    }
+
+    /// Get an answer that must exist and not be hidden. Hidden
+    /// answers look as if they did not exist to anyone but moderators.
+    fn find_unhidden_answer(&self, id: i32) -> Result<Answer, FieldError> {
+        let answer = self.find_answer(id)?;
+        if !answer.hidden {
+            Ok(answer)
+        } else if answer.visible_to(self) {
+            Err(error("HIDDEN", format!("Answer {} is hidden.", id)))
+        } else {
+            Err(not_found(format!("Answer {} does not exist.", id)))
+        }
+    }
+
+    /// Start a new session for a user.
+    fn start_session(&self, user: User) -> Session {
+        let (token, expires_at) = self.signer.issue(user.id, self.config.session_lifetime_hours);
+        Session {
+            token,
+            expires_at,
+            user
+        }
+    }
+}
+
+/// Ensure a user supplied text field is not blank.
+fn require_text(field: &str, value: &str) -> Result<(), FieldError> {
+    if value.trim().is_empty() {
+        Err(invalid_input(format!("{} must not be empty.", field)))
+    } else {
+        Ok(())
+    }
+}
+
+/// Ensure a question still accepts changes from its participants.
+fn require_unlocked(question: &Question) -> Result<(), FieldError> {
+    if question.locked {
+        Err(error("LOCKED", format!("Question {} is locked.", question.id)))
+    } else {
+        Ok(())
+    }
+}
+
+/// Root of all GraphQL queries.
+pub struct Query;
+
+graphql_object!(Query: Context |&self| {
+    description: "Root of all Rubber Ducks queries."
+
+    /// Version of the GraphQL API served by this dataserver.
+    field api_version() -> &str {
+        "1.0"
+    }
+
+    /// The logged in user making the request.
+    field me(&executor) -> Option<&User> {
+        executor.context().user.as_ref()
+    }
+
+    /// API keys of the logged in user.
+    field api_keys(&executor) -> FieldResult<Vec<ApiKey>> {
+        let context = executor.context();
+        let user = context.require_user()?;
+        Ok(context.storage.list_api_keys(user.id)?)
+    }
+
+    field user(&executor, id: i32) -> FieldResult<Option<User>> {
+        Ok(executor.context().storage.get_user(id)?)
+    }
+
+    field question(&executor, id: i32) -> FieldResult<Option<Question>> {
+        Ok(executor.context().storage.get_question(id)?)
+    }
+
+    /// List questions, newest first.
+    field questions(&executor, filter: Option<QuestionFilter>, page: Option<Page>) -> FieldResult<Vec<Question>> {
+        let context = executor.context();
+        let page = page.unwrap_or_default().clamped(context.config.limits.max_page_size);
+        Ok(context.storage.list_questions(&filter.unwrap_or_default(), &page)?)
+    }
+});
+
+/// Root of all GraphQL mutations.
+pub struct Mutation;
+
+graphql_object!(Mutation: Context |&self| {
+    description: "Root of all Rubber Ducks mutations."
+
+    /// Create a new account and log into it.
+    field register(&executor, username: String, password: String) -> FieldResult<Session> {
+        require_text("Username", &username)?;
+        if password.chars().count() < MIN_PASSWORD_LENGTH {
+            return Err(invalid_input(format!("Password must be at least {} characters long.", MIN_PASSWORD_LENGTH)));
+        }
+
+        let context = executor.context();
+        if context.storage.get_user_by_username(username.trim())?.is_some() {
+            return Err(error("CONFLICT", format!("Username {} is already taken.", username.trim())));
+        }
+        let user = context.storage.create_user(username.trim(), &hash_password(&password)?)?;
+        Ok(context.start_session(user))
+    }
+
+    field login(&executor, username: String, password: String) -> FieldResult<Session> {
+        let context = executor.context();
+        let user = context.storage.get_user_by_username(username.trim())?;
+        let hash = match &user {
+            Some(user) => context.storage.get_password_hash(user.id)?,
+            None => None
+        };
+
+        // Always check a password, so unknown usernames take as long as wrong passwords
+        let verified = verify_password(hash.as_ref().unwrap_or(&DUMMY_PASSWORD_HASH), &password);
+        match user {
+            Some(user) if verified && hash.is_some() => Ok(context.start_session(user)),
+            _ => Err(error("INVALID_CREDENTIALS", "Invalid username or password."))
+        }
+    }
+
+    /// Create an API key acting as the logged in user.
+    field create_api_key(&executor, name: String) -> FieldResult<CreatedApiKey> {
+        require_text("Name", &name)?;
+
+        let context = executor.context();
+        let user = context.require_user()?;
+        Ok(create_api_key(context.storage.as_ref(), user.id, name.trim())?)
+    }
+
+    field revoke_api_key(&executor, id: i32) -> FieldResult<ApiKey> {
+        let context = executor.context();
+        let user = context.require_user()?;
+        Ok(context.storage.revoke_api_key(user.id, id)?)
+    }
+
+    /// Change the role of a user. Admins only.
+    field set_user_role(&executor, user_id: i32, role: Role) -> FieldResult<User> {
+        let context = executor.context();
+        let admin = context.require_role(Role::Admin)?;
+
+        // Demoting yourself could leave nobody able to manage roles
+        if admin.id == user_id && role < Role::Admin {
+            return Err(forbidden("Admins cannot demote themselves."));
+        }
+        if context.storage.get_user(user_id)?.is_none() {
+            return Err(not_found(format!("User {} does not exist.", user_id)));
+        }
+        Ok(context.storage.set_user_role(user_id, role)?)
+    }
+
+    field ask_question(&executor, input: NewQuestion) -> FieldResult<Question> {
+        require_text("Title", &input.title)?;
+        require_text("Body", &input.body)?;
+
+        let context = executor.context();
+        let author = context.require_user()?;
+        Ok(context.storage.create_question(author.id, &input.title, &input.body, &input.tags.unwrap_or_default())?)
+    }
+
+    field post_answer(&executor, input: NewAnswer) -> FieldResult<Answer> {
+        require_text("Body", &input.body)?;
+
+        let context = executor.context();
+        let author = context.require_user()?;
+        let question = context.find_question(input.question_id)?;
+        require_unlocked(&question)?;
+        if question.closed {
+            return Err(error("CLOSED", format!("Question {} is closed to new answers.", question.id)));
+        }
+        Ok(context.storage.create_answer(question.id, author.id, &input.body)?)
+    }
+
+    /// Mark an answer as the one that solved the question.
+    field accept_answer(&executor, question_id: i32, answer_id: i32) -> FieldResult<Question> {
+        let context = executor.context();
+        let user = context.require_user()?;
+        let question = context.find_question(question_id)?;
+
+        // Only whoever asked the question decides what solved it
+        if question.author_id != user.id {
+            return Err(forbidden("Only the author of a question can accept an answer."));
+        }
+        require_unlocked(&question)?;
+        context.find_unhidden_answer(answer_id)?;
+        Ok(context.storage.accept_answer(question_id, answer_id)?)
+    }
+
+    field post_comment(&executor, input: NewComment) -> FieldResult<Comment> {
+        require_text("Body", &input.body)?;
+
+        let context = executor.context();
+        let author = context.require_user()?;
+        let question = match (input.question_id, input.answer_id) {
+            (Some(question_id), None) => context.find_question(question_id)?,
+            (None, Some(answer_id)) => context.find_question(context.find_unhidden_answer(answer_id)?.question_id)?,
+            _ => return Err(invalid_input("Exactly one of questionId and answerId must be given."))
+        };
+        require_unlocked(&question)?;
+        Ok(context.storage.create_comment(author.id, input.question_id, input.answer_id, &input.body)?)
+    }
+
+    /// Close a question to new answers. Moderators only.
+    field close_question(&executor, id: i32) -> FieldResult<Question> {
+        let context = executor.context();
+        context.require_role(Role::Moderator)?;
+        context.find_question(id)?;
+        Ok(context.storage.set_question_closed(id, true)?)
+    }
+
+    field reopen_question(&executor, id: i32) -> FieldResult<Question> {
+        let context = executor.context();
+        context.require_role(Role::Moderator)?;
+        context.find_question(id)?;
+        Ok(context.storage.set_question_closed(id, false)?)
+    }
+
+    /// Lock a question against answers, comments and acceptance. Moderators only.
+    field lock_question(&executor, id: i32) -> FieldResult<Question> {
+        let context = executor.context();
+        context.require_role(Role::Moderator)?;
+        context.find_question(id)?;
+        Ok(context.storage.set_question_locked(id, true)?)
+    }
+
+    field unlock_question(&executor, id: i32) -> FieldResult<Question> {
+        let context = executor.context();
+        context.require_role(Role::Moderator)?;
+        context.find_question(id)?;
+        Ok(context.storage.set_question_locked(id, false)?)
+    }
+
+    /// Hide an answer from everyone but moderators. Moderators only.
+    field hide_answer(&executor, id: i32) -> FieldResult<Answer> {
+        let context = executor.context();
+        context.require_role(Role::Moderator)?;
+        context.find_answer(id)?;
+        Ok(context.storage.set_answer_hidden(id, true)?)
+    }
+
+    field unhide_answer(&executor, id: i32) -> FieldResult<Answer> {
+        let context = executor.context();
+        context.require_role(Role::Moderator)?;
+        context.find_answer(id)?;
+        Ok(context.storage.set_answer_hidden(id, false)?)
+    }
+});
+
+/// The complete GraphQL schema served by the dataserver.
+pub type Schema = RootNode<'static, Query, Mutation>;
+
+/// Build the schema served by the dataserver.
+///
+/// # Examples
+/// ```
+/// let schema = create_schema();
+/// ```
+pub fn create_schema() -> Schema {
+    Schema::new(Query, Mutation)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use juniper::Variables;
+    use serde_json::Value;
+    use crate::cli::config::LimitsConfig;
+    use crate::dataserver::{
+        models::MAX_PAGE_SIZE,
+        signals::DRAIN_TIMEOUT_SECS,
+        storage::MemoryStorage
+    };
+
+    /// Storage holding a member, a moderator and an admin, in that order.
+    fn storage_with_users() -> Arc<dyn Storage> {
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+        for (username, role) in &[("member", Role::Member), ("moderator", Role::Moderator), ("admin", Role::Admin)] {
+            let user = storage.create_user(username, "hash").unwrap();
+            storage.set_user_role(user.id, *role).unwrap();
+        }
+        storage
+    }
+
+    /// Context of a request made by the given user, or nobody.
+    fn context_for(storage: &Arc<dyn Storage>, username: Option<&str>) -> Context {
+        let config = ReloadableConfig {
+            session_lifetime_hours: 1,
+            limits: LimitsConfig {
+                max_page_size: MAX_PAGE_SIZE,
+                drain_timeout_secs: DRAIN_TIMEOUT_SECS,
+                health_min_free_mb: 0
+            }
+        };
+        let user = username.map(|username| storage.get_user_by_username(username).unwrap().unwrap());
+        Context::new(storage.clone(), SessionSigner::new("secret"), config, user)
+    }
+
+    /// Execute a query, returning its data and the code of each error.
+    fn execute(context: &Context, query: &str) -> (Value, Vec<String>) {
+        let (data, errors) = juniper::execute(query, None, &create_schema(), &Variables::new(), context).unwrap();
+        let codes = serde_json::to_value(&errors).unwrap()
+                                                 .as_array()
+                                                 .unwrap()
+                                                 .iter()
+                                                 .map(|err| err["extensions"]["code"].as_str().unwrap_or("UNKNOWN").to_string())
+                                                 .collect();
+        (serde_json::to_value(&data).unwrap(), codes)
+    }
+
+    #[test]
+    fn asking_requires_a_user() {
+        let storage = storage_with_users();
+        let ask = r#"mutation { askQuestion(input: { title: "Why?", body: "Because.", tags: ["rust", "rust"] }) { id tags } }"#;
+
+        let (_, codes) = execute(&context_for(&storage, None), ask);
+        assert_eq!(codes, vec!["UNAUTHENTICATED"]);
+
+        let (data, codes) = execute(&context_for(&storage, Some("member")), ask);
+        assert!(codes.is_empty());
+        assert_eq!(data["askQuestion"]["tags"], serde_json::json!(["rust"]));
+        assert_eq!(execute(&context_for(&storage, None), "{ questions { id } }").0["questions"].as_array().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn login_refuses_unknown_users_and_wrong_passwords() {
+        let storage = storage_with_users();
+        let context = context_for(&storage, None);
+        let (data, codes) = execute(&context, r#"mutation { register(username: "carol", password: "hunter2222") { token } }"#);
+        assert!(codes.is_empty());
+        assert!(context.signer.verify(data["register"]["token"].as_str().unwrap()).is_ok());
+
+        let login = |username: &str, password: &str| {
+            execute(&context, &format!(r#"mutation {{ login(username: "{}", password: "{}") {{ user {{ username }} }} }}"#, username, password))
+        };
+        assert_eq!(login("carol", "hunter2222").0["login"]["user"]["username"], "carol");
+        assert_eq!(login("carol", "hunter3333").1, vec!["INVALID_CREDENTIALS"]);
+        assert_eq!(login("dave", "hunter2222").1, vec!["INVALID_CREDENTIALS"]);
+        assert_eq!(login("dave", "rd-dummy-password").1, vec!["INVALID_CREDENTIALS"]);
+    }
+
+    #[test]
+    fn moderation_requires_a_moderator() {
+        let storage = storage_with_users();
+        let member = storage.get_user_by_username("member").unwrap().unwrap();
+        let question = storage.create_question(member.id, "Why?", "Because.", &[]).unwrap();
+        let close = format!("mutation {{ closeQuestion(id: {}) {{ closed }} }}", question.id);
+
+        assert_eq!(execute(&context_for(&storage, Some("member")), &close).1, vec!["FORBIDDEN"]);
+        let (data, codes) = execute(&context_for(&storage, Some("moderator")), &close);
+        assert!(codes.is_empty());
+        assert_eq!(data["closeQuestion"]["closed"], Value::Bool(true));
+        assert_eq!(execute(&context_for(&storage, Some("admin")), &close).1, Vec::<String>::new());
+    }
+
+    #[test]
+    fn roles_are_managed_by_admins() {
+        let storage = storage_with_users();
+        let promote = "mutation { setUserRole(userId: 1, role: MODERATOR) { role } }";
+
+        assert_eq!(execute(&context_for(&storage, Some("moderator")), promote).1, vec!["FORBIDDEN"]);
+        assert_eq!(execute(&context_for(&storage, Some("admin")), promote).0["setUserRole"]["role"], "MODERATOR");
+        assert_eq!(execute(&context_for(&storage, Some("admin")), "mutation { setUserRole(userId: 3, role: MEMBER) { role } }").1,
+                   vec!["FORBIDDEN"]);
+        assert_eq!(execute(&context_for(&storage, Some("admin")), "mutation { setUserRole(userId: 9, role: MEMBER) { role } }").1,
+                   vec!["NOT_FOUND"]);
+    }
+
+    #[test]
+    fn only_the_author_accepts_answers() {
+        let storage = storage_with_users();
+        let member = storage.get_user_by_username("member").unwrap().unwrap();
+        let question = storage.create_question(member.id, "Why?", "Because.", &[]).unwrap();
+        let answer = storage.create_answer(question.id, 2, "Just because.").unwrap();
+        let accept = format!("mutation {{ acceptAnswer(questionId: {}, answerId: {}) {{ acceptedAnswer {{ id }} }} }}", question.id, answer.id);
+
+        assert_eq!(execute(&context_for(&storage, Some("admin")), &accept).1, vec!["FORBIDDEN"]);
+        let (data, codes) = execute(&context_for(&storage, Some("member")), &accept);
+        assert!(codes.is_empty());
+        assert_eq!(data["acceptAnswer"]["acceptedAnswer"]["id"], answer.id);
+    }
+
+    #[test]
+    fn hidden_answers_are_shown_to_moderators_only() {
+        let storage = storage_with_users();
+        let question = storage.create_question(1, "Why?", "Because.", &[]).unwrap();
+        let answer = storage.create_answer(question.id, 1, "Buy my course.").unwrap();
+        let answers = format!("{{ question(id: {}) {{ answers {{ id }} }} }}", question.id);
+        let hide = format!("mutation {{ hideAnswer(id: {}) {{ hidden }} }}", answer.id);
+
+        assert_eq!(execute(&context_for(&storage, Some("member")), &hide).1, vec!["FORBIDDEN"]);
+        assert!(execute(&context_for(&storage, Some("moderator")), &hide).1.is_empty());
+
+        let visible = |username| execute(&context_for(&storage, username), &answers).0["question"]["answers"].as_array().unwrap().len();
+        assert_eq!(visible(None), 0);
+        assert_eq!(visible(Some("member")), 0);
+        assert_eq!(visible(Some("moderator")), 1);
+    }
+
+    #[test]
+    fn hidden_answers_cannot_be_accepted_or_commented_on() {
+        let storage = storage_with_users();
+        let question = storage.create_question(1, "Why?", "Because.", &[]).unwrap();
+        let answer = storage.create_answer(question.id, 1, "Buy my course.").unwrap();
+        storage.set_answer_hidden(answer.id, true).unwrap();
+        let accept = format!("mutation {{ acceptAnswer(questionId: {}, answerId: {}) {{ id }} }}", question.id, answer.id);
+        let comment = format!(r#"mutation {{ postComment(input: {{ answerId: {}, body: "Thanks!" }}) {{ id }} }}"#, answer.id);
+
+        assert_eq!(execute(&context_for(&storage, Some("member")), &accept).1, vec!["NOT_FOUND"]);
+        assert_eq!(execute(&context_for(&storage, Some("member")), &comment).1, vec!["NOT_FOUND"]);
+        assert!(storage.get_question(question.id).unwrap().unwrap().accepted_answer_id.is_none());
+        assert!(storage.comments_for_answer(answer.id).unwrap().is_empty());
+        assert_eq!(execute(&context_for(&storage, Some("moderator")), &comment).1, vec!["HIDDEN"]);
+    }
+
+    #[test]
+    fn locked_questions_refuse_answers() {
+        let storage = storage_with_users();
+        let question = storage.create_question(1, "Why?", "Because.", &[]).unwrap();
+        storage.set_question_locked(question.id, true).unwrap();
+        let answer = format!(r#"mutation {{ postAnswer(input: {{ questionId: {}, body: "Why not?" }}) {{ id }} }}"#, question.id);
+
+        assert_eq!(execute(&context_for(&storage, Some("member")), &answer).1, vec!["LOCKED"]);
+    }
+}
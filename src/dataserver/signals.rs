@@ -0,0 +1,288 @@
+/**
+ * Signal handling for the dataserver. On SIGTERM the
+ * server stops taking new requests, waits for the ones
+ * in flight to finish, flushes storage and logs, and
+ * only then exits. On SIGHUP it reloads the settings
+ * it reads while serving.
+ */
+use std::{
+    io::Cursor,
+    sync::{
+        Arc,
+        RwLock,
+        atomic::{
+            AtomicBool,
+            AtomicUsize,
+            Ordering
+        }
+    },
+    thread,
+    time::{
+        Duration,
+        Instant
+    }
+};
+use rocket::{
+    Data,
+    Request,
+    Response,
+    fairing::{
+        Fairing,
+        Info,
+        Kind
+    },
+    http::{
+        ContentType,
+        Method,
+        Status,
+        uri::Origin
+    }
+};
+use signal_hook::{
+    SIGHUP,
+    SIGINT,
+    SIGTERM,
+    iterator::Signals
+};
+
+use crate::cli::config::{
+    RdConfig,
+    ReloadableConfig
+};
+use crate::dataserver::storage::Storage;
+
+/// Longest the server waits for in-flight requests before exiting anyway,
+/// unless limits.drain_timeout_secs says otherwise. `rd dataserver stop`
+/// waits a little longer than this before killing the server.
+pub const DRAIN_TIMEOUT_SECS: u64 = 25;
+
+/// Path requests are redirected to once the server is draining. No
+/// route serves it, so they never reach a handler before `DrainFairing`
+/// answers them.
+const DRAINING_PATH: &str = "/__draining";
+
+/// How often the drain loop checks for finished requests.
+const DRAIN_POLL_MILLIS: u64 = 50;
+
+/// Settings the server reads while serving, shared with the signal
+/// thread so SIGHUP can replace them. The process environment is never
+/// changed once the server is running, as other threads may be reading it.
+pub type LiveConfig = Arc<RwLock<ReloadableConfig>>;
+
+/// Get a copy of the settings as they are now.
+///
+/// # Arguments
+/// * `config` - Settings shared with the signal thread.
+///
+/// # Examples
+/// ```
+/// let max_page_size = read_config(&config).limits.max_page_size;
+/// ```
+pub fn read_config(config: &LiveConfig) -> ReloadableConfig {
+    // Writers only ever swap in a whole value, so a poisoned lock still holds a sound one
+    match config.read() {
+        Ok(current) => current.clone(),
+        Err(poisoned) => poisoned.into_inner().clone()
+    }
+}
+
+/// Whether the server is shutting down and how many requests it is serving.
+#[derive(Default)]
+pub struct ShutdownState {
+    draining: AtomicBool,
+    in_flight: AtomicUsize
+}
+
+impl ShutdownState {
+
+    /// Whether the server has stopped taking new requests.
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
+    /// Number of requests currently being served.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::SeqCst)
+    }
+}
+
+/// Marks a request that arrived while the server was draining.
+struct ArrivedDraining(bool);
+
+/// Fairing counting requests in flight and turning new
+/// requests away while the server is draining.
+pub struct DrainFairing(pub Arc<ShutdownState>);
+
+impl Fairing for DrainFairing {
+    fn info(&self) -> Info {
+        Info {
+            name: "Graceful Shutdown",
+            kind: Kind::Request | Kind::Response
+        }
+    }
+
+    fn on_request(&self, request: &mut Request, _: &Data) {
+        self.0.in_flight.fetch_add(1, Ordering::SeqCst);
+
+        // Requests arriving after shutdown began never reach their handler
+        if self.0.is_draining() {
+            request.local_cache(|| ArrivedDraining(true));
+            request.set_method(Method::Get);
+            request.set_uri(Origin::parse(DRAINING_PATH).expect("Draining path is a valid URI."));
+        }
+    }
+
+    fn on_response(&self, request: &Request, response: &mut Response) {
+        if request.local_cache(|| ArrivedDraining(false)).0 {
+            response.set_status(Status::ServiceUnavailable);
+            response.set_header(ContentType::Plain);
+            response.set_sized_body(Cursor::new("Server is shutting down."));
+        }
+
+        // Keep-alive connections are closed rather than reused
+        if self.0.is_draining() {
+            response.set_raw_header("Connection", "close");
+        }
+        self.0.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Wait for in-flight requests to finish, giving up after the drain timeout.
+///
+/// # Arguments
+/// * `state` - Shutdown state shared with the `DrainFairing`.
+/// * `timeout_secs` - Longest to wait.
+fn drain(state: &ShutdownState, timeout_secs: u64) {
+    let deadline = Instant::now() + Duration::from_secs(timeout_secs);
+    while state.in_flight() > 0 && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(DRAIN_POLL_MILLIS));
+    }
+
+    match state.in_flight() {
+        0 => info!("All in-flight requests finished."),
+        remaining => warn!("Giving up on {} in-flight request(s).", remaining)
+    }
+}
+
+/// Merge the layers of configuration again, as they were merged when
+/// the server started, and swap in the settings that can be reloaded.
+/// Settings which only take effect at startup, such as the port, are
+/// left as they are. Invalid configuration is refused as a whole.
+///
+/// # Arguments
+/// * `config` - Settings shared with request handlers.
+/// * `instance` - Instance the server belongs to.
+/// * `flags` - Settings the server was started with as flags.
+fn reload_config(config: &LiveConfig, instance: &str, flags: &[(&'static str, String)]) {
+    let reloaded = match RdConfig::load(instance, flags) {
+        Ok(loaded) => loaded.reloadable(),
+        Err(msg) => {
+            error!("Failed to reload configuration, keeping the current settings: {}", msg);
+            return;
+        }
+    };
+
+    let mut current = match config.write() {
+        Ok(current) => current,
+        Err(poisoned) => poisoned.into_inner()
+    };
+    if *current == reloaded {
+        info!("Reloaded configuration, no settings changed.");
+    } else {
+        info!("Reloaded configuration, settings are now {:?}.", reloaded);
+        *current = reloaded;
+    }
+}
+
+/// Drain requests, flush storage and logs, then exit the process.
+fn shut_down(state: &ShutdownState, storage: &dyn Storage, config: &LiveConfig) {
+    state.draining.store(true, Ordering::SeqCst);
+    drain(state, read_config(config).limits.drain_timeout_secs);
+
+    if let Err(msg) = storage.flush() {
+        error!("Failed to flush storage: {}", msg);
+    }
+    info!("Dataserver stopped.");
+    log::logger().flush();
+    std::process::exit(0);
+}
+
+/// Spawn a thread which reloads the configuration on SIGHUP
+/// and shuts the server down gracefully on SIGTERM or SIGINT.
+///
+/// # Arguments
+/// * `state` - Shutdown state shared with the `DrainFairing`.
+/// * `storage` - Storage to flush before exiting.
+/// * `config` - Settings shared with request handlers.
+/// * `instance` - Instance the server belongs to.
+/// * `flags` - Settings the server was started with as flags.
+///
+/// # Examples
+/// ```
+/// handle_signals(state.clone(), storage.clone(), config.clone(), instance, cmd.flag_values())?;
+/// ```
+pub fn handle_signals(
+    state: Arc<ShutdownState>,
+    storage: Arc<dyn Storage>,
+    config: LiveConfig,
+    instance: String,
+    flags: Vec<(&'static str, String)>
+) -> Result<(), String> {
+    let signals = Signals::new(&[SIGHUP, SIGTERM, SIGINT]).map_err(|err| err.to_string())?;
+
+    thread::spawn(move || {
+        for signal in signals.forever() {
+            match signal {
+                SIGHUP => {
+                    info!("Received SIGHUP, reloading configuration...");
+                    reload_config(&config, &instance, &flags);
+                },
+                _ => {
+                    info!("Received signal {}, draining requests...", signal);
+                    shut_down(&state, storage.as_ref(), &config);
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::local::Client;
+
+    #[rocket::get("/")]
+    fn hello() -> &'static str {
+        "Hello!"
+    }
+
+    /// Client of a server with the fairing attached.
+    fn client(state: Arc<ShutdownState>) -> Client {
+        let app = rocket::ignite().attach(DrainFairing(state)).mount("/", rocket::routes![hello]);
+        Client::new(app).expect("Failed to build test server.")
+    }
+
+    #[test]
+    fn draining_path_is_not_served() {
+        let state = Arc::new(ShutdownState::default());
+        let client = client(state.clone());
+
+        assert_eq!(client.get("/").dispatch().body_string(), Some(String::from("Hello!")));
+        assert_eq!(client.get(DRAINING_PATH).dispatch().status(), Status::NotFound);
+        assert_eq!(state.in_flight(), 0);
+    }
+
+    #[test]
+    fn requests_are_refused_while_draining() {
+        let state = Arc::new(ShutdownState::default());
+        let client = client(state.clone());
+        state.draining.store(true, Ordering::SeqCst);
+
+        let mut response = client.get("/").dispatch();
+        assert_eq!(response.status(), Status::ServiceUnavailable);
+        assert_eq!(response.headers().get_one("Connection"), Some("close"));
+        assert_eq!(response.body_string(), Some(String::from("Server is shutting down.")));
+        assert_eq!(state.in_flight(), 0);
+    }
+}
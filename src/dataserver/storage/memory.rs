@@ -0,0 +1,278 @@
+/**
+ * Storage implementation keeping the whole Rubber Ducks
+ * domain in process memory. Nothing survives a restart,
+ * which makes it a good fit for tests.
+ */
+use std::{
+    collections::HashMap,
+    sync::RwLock
+};
+use chrono::Utc;
+
+use crate::dataserver::{
+    models::{
+        ApiKey,
+        User,
+        Question,
+        Answer,
+        Comment,
+        QuestionFilter,
+        Page,
+        Role
+    },
+    storage::Storage
+};
+
+/// Everything held by the memory storage, guarded by a single lock.
+#[derive(Default)]
+struct MemoryData {
+    users: Vec<User>,
+    password_hashes: HashMap<i32, String>,
+    api_keys: Vec<(ApiKey, String)>,
+    questions: Vec<Question>,
+    answers: Vec<Answer>,
+    comments: Vec<Comment>
+}
+
+/// Storage keeping the whole domain in process memory.
+#[derive(Default)]
+pub struct MemoryStorage {
+    data: RwLock<MemoryData>
+}
+
+impl MemoryStorage {
+
+    /// Create an empty memory storage.
+    ///
+    /// # Examples
+    /// ```
+    /// let storage = MemoryStorage::new();
+    /// ```
+    pub fn new() -> MemoryStorage {
+        MemoryStorage::default()
+    }
+}
+
+impl Storage for MemoryStorage {
+
+    fn create_user(&self, username: &str, password_hash: &str) -> Result<User, String> {
+        let mut data = self.data.write().map_err(|err| err.to_string())?;
+        if data.users.iter().any(|u| u.username == username) {
+            return Err(format!("Username {} is already taken.", username));
+        }
+
+        let user = User {
+            id: data.users.len() as i32 + 1,
+            username: String::from(username),
+            role: Role::Member,
+            created_at: Utc::now()
+        };
+        data.users.push(user.clone());
+        data.password_hashes.insert(user.id, String::from(password_hash));
+        Ok(user)
+    }
+
+    fn get_user(&self, id: i32) -> Result<Option<User>, String> {
+        let data = self.data.read().map_err(|err| err.to_string())?;
+        Ok(data.users.iter().find(|u| u.id == id).cloned())
+    }
+
+    fn get_user_by_username(&self, username: &str) -> Result<Option<User>, String> {
+        let data = self.data.read().map_err(|err| err.to_string())?;
+        Ok(data.users.iter().find(|u| u.username == username).cloned())
+    }
+
+    fn get_password_hash(&self, user_id: i32) -> Result<Option<String>, String> {
+        let data = self.data.read().map_err(|err| err.to_string())?;
+        Ok(data.password_hashes.get(&user_id).cloned())
+    }
+
+    fn set_user_role(&self, id: i32, role: Role) -> Result<User, String> {
+        let mut data = self.data.write().map_err(|err| err.to_string())?;
+        match data.users.iter_mut().find(|u| u.id == id) {
+            Some(user) => {
+                user.role = role;
+                Ok(user.clone())
+            },
+            None => Err(format!("User {} does not exist.", id))
+        }
+    }
+
+    fn create_api_key(&self, user_id: i32, name: &str, prefix: &str, key_hash: &str) -> Result<ApiKey, String> {
+        let mut data = self.data.write().map_err(|err| err.to_string())?;
+        let api_key = ApiKey {
+            id: data.api_keys.len() as i32 + 1,
+            user_id,
+            name: String::from(name),
+            prefix: String::from(prefix),
+            created_at: Utc::now(),
+            last_used_at: None,
+            revoked_at: None
+        };
+        data.api_keys.push((api_key.clone(), String::from(key_hash)));
+        Ok(api_key)
+    }
+
+    fn list_api_keys(&self, user_id: i32) -> Result<Vec<ApiKey>, String> {
+        let data = self.data.read().map_err(|err| err.to_string())?;
+        Ok(data.api_keys.iter().map(|(k, _)| k).filter(|k| k.user_id == user_id).cloned().collect())
+    }
+
+    fn revoke_api_key(&self, user_id: i32, id: i32) -> Result<ApiKey, String> {
+        let mut data = self.data.write().map_err(|err| err.to_string())?;
+        match data.api_keys.iter_mut().map(|(k, _)| k).find(|k| k.id == id && k.user_id == user_id) {
+            Some(api_key) => {
+                if api_key.revoked_at.is_none() {
+                    api_key.revoked_at = Some(Utc::now());
+                }
+                Ok(api_key.clone())
+            },
+            None => Err(format!("API key {} does not exist.", id))
+        }
+    }
+
+    fn get_user_by_api_key(&self, key_hash: &str) -> Result<Option<User>, String> {
+        let mut data = self.data.write().map_err(|err| err.to_string())?;
+        let user_id = match data.api_keys.iter_mut().find(|(k, h)| h == key_hash && k.revoked_at.is_none()) {
+            Some((api_key, _)) => {
+                api_key.last_used_at = Some(Utc::now());
+                api_key.user_id
+            },
+            None => return Ok(None)
+        };
+        Ok(data.users.iter().find(|u| u.id == user_id).cloned())
+    }
+
+    fn create_question(&self, author_id: i32, title: &str, body: &str, tags: &[String]) -> Result<Question, String> {
+        let mut data = self.data.write().map_err(|err| err.to_string())?;
+        let question = Question {
+            id: data.questions.len() as i32 + 1,
+            author_id,
+            title: String::from(title),
+            body: String::from(body),
+            tags: Question::normalize_tags(tags),
+            accepted_answer_id: None,
+            closed: false,
+            locked: false,
+            created_at: Utc::now()
+        };
+        data.questions.push(question.clone());
+        Ok(question)
+    }
+
+    fn get_question(&self, id: i32) -> Result<Option<Question>, String> {
+        let data = self.data.read().map_err(|err| err.to_string())?;
+        Ok(data.questions.iter().find(|q| q.id == id).cloned())
+    }
+
+    fn list_questions(&self, filter: &QuestionFilter, page: &Page) -> Result<Vec<Question>, String> {
+        let data = self.data.read().map_err(|err| err.to_string())?;
+        Ok(data.questions.iter()
+                         .rev()
+                         .filter(|q| q.matches(filter))
+                         .skip(page.offset())
+                         .take(page.limit())
+                         .cloned()
+                         .collect())
+    }
+
+    fn set_question_closed(&self, id: i32, closed: bool) -> Result<Question, String> {
+        let mut data = self.data.write().map_err(|err| err.to_string())?;
+        match data.questions.iter_mut().find(|q| q.id == id) {
+            Some(question) => {
+                question.closed = closed;
+                Ok(question.clone())
+            },
+            None => Err(format!("Question {} does not exist.", id))
+        }
+    }
+
+    fn set_question_locked(&self, id: i32, locked: bool) -> Result<Question, String> {
+        let mut data = self.data.write().map_err(|err| err.to_string())?;
+        match data.questions.iter_mut().find(|q| q.id == id) {
+            Some(question) => {
+                question.locked = locked;
+                Ok(question.clone())
+            },
+            None => Err(format!("Question {} does not exist.", id))
+        }
+    }
+
+    fn accept_answer(&self, question_id: i32, answer_id: i32) -> Result<Question, String> {
+        let mut data = self.data.write().map_err(|err| err.to_string())?;
+        if !data.answers.iter().any(|a| a.id == answer_id && a.question_id == question_id) {
+            return Err(format!("Answer {} does not belong to question {}.", answer_id, question_id));
+        }
+
+        match data.questions.iter_mut().find(|q| q.id == question_id) {
+            Some(question) => {
+                question.accepted_answer_id = Some(answer_id);
+                Ok(question.clone())
+            },
+            None => Err(format!("Question {} does not exist.", question_id))
+        }
+    }
+
+    fn create_answer(&self, question_id: i32, author_id: i32, body: &str) -> Result<Answer, String> {
+        let mut data = self.data.write().map_err(|err| err.to_string())?;
+        if !data.questions.iter().any(|q| q.id == question_id) {
+            return Err(format!("Question {} does not exist.", question_id));
+        }
+
+        let answer = Answer {
+            id: data.answers.len() as i32 + 1,
+            question_id,
+            author_id,
+            body: String::from(body),
+            hidden: false,
+            created_at: Utc::now()
+        };
+        data.answers.push(answer.clone());
+        Ok(answer)
+    }
+
+    fn get_answer(&self, id: i32) -> Result<Option<Answer>, String> {
+        let data = self.data.read().map_err(|err| err.to_string())?;
+        Ok(data.answers.iter().find(|a| a.id == id).cloned())
+    }
+
+    fn set_answer_hidden(&self, id: i32, hidden: bool) -> Result<Answer, String> {
+        let mut data = self.data.write().map_err(|err| err.to_string())?;
+        match data.answers.iter_mut().find(|a| a.id == id) {
+            Some(answer) => {
+                answer.hidden = hidden;
+                Ok(answer.clone())
+            },
+            None => Err(format!("Answer {} does not exist.", id))
+        }
+    }
+
+    fn answers_for_question(&self, question_id: i32) -> Result<Vec<Answer>, String> {
+        let data = self.data.read().map_err(|err| err.to_string())?;
+        Ok(data.answers.iter().filter(|a| a.question_id == question_id).cloned().collect())
+    }
+
+    fn create_comment(&self, author_id: i32, question_id: Option<i32>, answer_id: Option<i32>, body: &str) -> Result<Comment, String> {
+        let mut data = self.data.write().map_err(|err| err.to_string())?;
+        let comment = Comment {
+            id: data.comments.len() as i32 + 1,
+            author_id,
+            question_id,
+            answer_id,
+            body: String::from(body),
+            created_at: Utc::now()
+        };
+        data.comments.push(comment.clone());
+        Ok(comment)
+    }
+
+    fn comments_for_question(&self, question_id: i32) -> Result<Vec<Comment>, String> {
+        let data = self.data.read().map_err(|err| err.to_string())?;
+        Ok(data.comments.iter().filter(|c| c.question_id == Some(question_id)).cloned().collect())
+    }
+
+    fn comments_for_answer(&self, answer_id: i32) -> Result<Vec<Comment>, String> {
+        let data = self.data.read().map_err(|err| err.to_string())?;
+        Ok(data.comments.iter().filter(|c| c.answer_id == Some(answer_id)).cloned().collect())
+    }
+}
@@ -0,0 +1,379 @@
+/**
+ * Pluggable storage of the Rubber Ducks domain for the
+ * dataserver. Resolvers only ever talk to the `Storage`
+ * trait, so backends can be swapped without touching
+ * the GraphQL schema.
+ */
+use std::{
+    env,
+    path::PathBuf,
+    sync::Arc
+};
+
+use rusqlite::Connection;
+
+use crate::cli::environment::{
+    current_instance,
+    get_or_create_instance_home
+};
+use crate::dataserver::migrations::ensure_migrated;
+use crate::dataserver::models::{
+    ApiKey,
+    User,
+    Question,
+    Answer,
+    Comment,
+    QuestionFilter,
+    Page,
+    Role
+};
+
+pub mod memory;
+pub mod sqlite;
+
+pub use memory::MemoryStorage;
+pub use sqlite::SqliteStorage;
+
+/// Name of the SQLite database file kept in each instance's home.
+pub const DATABASE_FILE: &str = "rd.db";
+
+/// Usage of a backend's connection pool.
+#[derive(Debug, Clone, Copy)]
+pub struct PoolStats {
+    pub connections: u32,
+    pub idle_connections: u32,
+    pub max_size: u32
+}
+
+/// Operations every storage backend of the dataserver provides.
+pub trait Storage: Send + Sync {
+
+    /// Create a new user.
+    ///
+    /// # Arguments
+    /// * `username` - Unique name of the new user.
+    /// * `password_hash` - Hash of the user's password.
+    fn create_user(&self, username: &str, password_hash: &str) -> Result<User, String>;
+
+    /// Get a user by ID.
+    ///
+    /// # Arguments
+    /// * `id` - ID of the user.
+    fn get_user(&self, id: i32) -> Result<Option<User>, String>;
+
+    /// Get a user by username.
+    ///
+    /// # Arguments
+    /// * `username` - Name of the user.
+    fn get_user_by_username(&self, username: &str) -> Result<Option<User>, String>;
+
+    /// Get the password hash of a user, if they have a password.
+    ///
+    /// # Arguments
+    /// * `user_id` - ID of the user.
+    fn get_password_hash(&self, user_id: i32) -> Result<Option<String>, String>;
+
+    /// Change the role of a user.
+    ///
+    /// # Arguments
+    /// * `id` - ID of the user.
+    /// * `role` - New role of the user.
+    fn set_user_role(&self, id: i32, role: Role) -> Result<User, String>;
+
+    /// Create a new API key.
+    ///
+    /// # Arguments
+    /// * `user_id` - ID of the user owning the key.
+    /// * `name` - Label given to the key by its owner.
+    /// * `prefix` - Start of the key, to tell keys apart.
+    /// * `key_hash` - Hash of the full key.
+    fn create_api_key(&self, user_id: i32, name: &str, prefix: &str, key_hash: &str) -> Result<ApiKey, String>;
+
+    /// List the API keys of a user, including revoked ones.
+    ///
+    /// # Arguments
+    /// * `user_id` - ID of the user owning the keys.
+    fn list_api_keys(&self, user_id: i32) -> Result<Vec<ApiKey>, String>;
+
+    /// Revoke one of a user's API keys.
+    ///
+    /// # Arguments
+    /// * `user_id` - ID of the user owning the key.
+    /// * `id` - ID of the key to revoke.
+    fn revoke_api_key(&self, user_id: i32, id: i32) -> Result<ApiKey, String>;
+
+    /// Resolve the owner of an unrevoked API key, recording its use.
+    ///
+    /// # Arguments
+    /// * `key_hash` - Hash of the full key.
+    fn get_user_by_api_key(&self, key_hash: &str) -> Result<Option<User>, String>;
+
+    /// Create a new question.
+    ///
+    /// # Arguments
+    /// * `author_id` - ID of the user asking.
+    /// * `title` - Short summary of the question.
+    /// * `body` - Full text of the question.
+    /// * `tags` - Tags categorising the question.
+    fn create_question(&self, author_id: i32, title: &str, body: &str, tags: &[String]) -> Result<Question, String>;
+
+    /// Get a question by ID.
+    ///
+    /// # Arguments
+    /// * `id` - ID of the question.
+    fn get_question(&self, id: i32) -> Result<Option<Question>, String>;
+
+    /// List questions matching a filter, newest first.
+    ///
+    /// # Arguments
+    /// * `filter` - Criteria questions must match.
+    /// * `page` - Which slice of the matches to return.
+    fn list_questions(&self, filter: &QuestionFilter, page: &Page) -> Result<Vec<Question>, String>;
+
+    /// Close or reopen a question.
+    ///
+    /// # Arguments
+    /// * `id` - ID of the question.
+    /// * `closed` - Whether the question is closed.
+    fn set_question_closed(&self, id: i32, closed: bool) -> Result<Question, String>;
+
+    /// Lock or unlock a question.
+    ///
+    /// # Arguments
+    /// * `id` - ID of the question.
+    /// * `locked` - Whether the question is locked.
+    fn set_question_locked(&self, id: i32, locked: bool) -> Result<Question, String>;
+
+    /// Mark an answer as the accepted answer of its question.
+    ///
+    /// # Arguments
+    /// * `question_id` - ID of the question.
+    /// * `answer_id` - ID of the answer to accept.
+    fn accept_answer(&self, question_id: i32, answer_id: i32) -> Result<Question, String>;
+
+    /// Create a new answer.
+    ///
+    /// # Arguments
+    /// * `question_id` - ID of the question being answered.
+    /// * `author_id` - ID of the user answering.
+    /// * `body` - Full text of the answer.
+    fn create_answer(&self, question_id: i32, author_id: i32, body: &str) -> Result<Answer, String>;
+
+    /// Get an answer by ID.
+    ///
+    /// # Arguments
+    /// * `id` - ID of the answer.
+    fn get_answer(&self, id: i32) -> Result<Option<Answer>, String>;
+
+    /// Hide or unhide an answer.
+    ///
+    /// # Arguments
+    /// * `id` - ID of the answer.
+    /// * `hidden` - Whether the answer is hidden.
+    fn set_answer_hidden(&self, id: i32, hidden: bool) -> Result<Answer, String>;
+
+    /// List the answers to a question, oldest first.
+    ///
+    /// # Arguments
+    /// * `question_id` - ID of the question.
+    fn answers_for_question(&self, question_id: i32) -> Result<Vec<Answer>, String>;
+
+    /// Create a new comment on a question or an answer.
+    ///
+    /// # Arguments
+    /// * `author_id` - ID of the user commenting.
+    /// * `question_id` - ID of the question commented on, if any.
+    /// * `answer_id` - ID of the answer commented on, if any.
+    /// * `body` - Text of the comment.
+    fn create_comment(&self, author_id: i32, question_id: Option<i32>, answer_id: Option<i32>, body: &str) -> Result<Comment, String>;
+
+    /// List the comments on a question, oldest first.
+    ///
+    /// # Arguments
+    /// * `question_id` - ID of the question.
+    fn comments_for_question(&self, question_id: i32) -> Result<Vec<Comment>, String>;
+
+    /// List the comments on an answer, oldest first.
+    ///
+    /// # Arguments
+    /// * `answer_id` - ID of the answer.
+    fn comments_for_answer(&self, answer_id: i32) -> Result<Vec<Comment>, String>;
+
+    /// Write anything buffered by the backend through to durable
+    /// storage. Called once the dataserver has drained on shutdown.
+    fn flush(&self) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Report how the backend's connection pool is used, for
+    /// backends keeping one.
+    fn pool_stats(&self) -> Option<PoolStats> {
+        None
+    }
+
+    /// Check that the backend can currently be reached.
+    fn ping(&self) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Count the schema migrations not yet applied, for backends
+    /// with a schema to migrate.
+    fn pending_migration_count(&self) -> Result<usize, String> {
+        Ok(0)
+    }
+}
+
+/// Get the path of the SQLite database of the dataserver
+/// instance selected by RD_INSTANCE.
+///
+/// # Examples
+/// ```
+/// let database = get_database_path().unwrap();
+/// ```
+pub fn get_database_path() -> Result<PathBuf, String> {
+    let mut database_path = PathBuf::from(get_or_create_instance_home(&current_instance())?);
+    database_path.push(DATABASE_FILE);
+    Ok(database_path)
+}
+
+/// Open a single connection to the SQLite database of the current instance,
+/// for work such as migrations that happens outside the pool.
+///
+/// # Examples
+/// ```
+/// let mut conn = open_database().unwrap();
+/// ```
+pub fn open_database() -> Result<Connection, String> {
+    Connection::open(get_database_path()?).map_err(|err| err.to_string())
+}
+
+/// Name of the storage backend selected by RD_STORAGE, which
+/// is either `sqlite` (the default) or `memory`.
+///
+/// # Examples
+/// ```
+/// if storage_backend() == "sqlite" {
+///     // Check migrations
+/// }
+/// ```
+pub fn storage_backend() -> String {
+    env::var("RD_STORAGE").unwrap_or_else(|_| String::from("sqlite"))
+}
+
+/// Open the storage backend selected by RD_STORAGE.
+///
+/// # Examples
+/// ```
+/// let storage = open_storage().expect("Failed to open storage.");
+/// ```
+pub fn open_storage() -> Result<Arc<dyn Storage>, String> {
+    match storage_backend().as_str() {
+        "sqlite" => {
+            let database_path = get_database_path()?;
+            info!("Opening SQLite storage at {}...", database_path.display());
+            Ok(Arc::new(SqliteStorage::open(&database_path)?))
+        },
+        "memory" => {
+            warn!("Using memory storage: nothing will survive a restart.");
+            Ok(Arc::new(MemoryStorage::new()))
+        },
+        other => Err(format!("Unknown storage backend {}. Set RD_STORAGE to sqlite or memory.", other))
+    }
+}
+
+/// Open the storage backend selected by RD_STORAGE for use
+/// outside the server, refusing to touch a database whose
+/// schema is not up to date.
+///
+/// # Examples
+/// ```
+/// let storage = open_migrated_storage()?;
+/// ```
+pub fn open_migrated_storage() -> Result<Arc<dyn Storage>, String> {
+    if storage_backend() == "sqlite" {
+        ensure_migrated(&open_database()?)?;
+    }
+    open_storage()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dataserver::migrations::run_migrations;
+    use crate::testing::TempDir;
+
+    /// Exercise a backend the way the resolvers use it. Every
+    /// backend must pass, so tests against memory storage
+    /// describe what SQLite does in production.
+    fn check_backend(storage: &dyn Storage) {
+        let alice = storage.create_user("alice", "hash").unwrap();
+        let bob = storage.create_user("bob", "hash").unwrap();
+        assert!(storage.create_user("alice", "hash").is_err());
+        assert_eq!(storage.get_user_by_username("bob").unwrap().unwrap().id, bob.id);
+        assert_eq!(storage.get_password_hash(alice.id).unwrap(), Some(String::from("hash")));
+        assert_eq!(storage.set_user_role(bob.id, Role::Moderator).unwrap().role, Role::Moderator);
+
+        let tags = vec![String::from("sqlite"), String::from("rust"), String::from("sqlite")];
+        let first = storage.create_question(alice.id, "Is 100% coverage worth it?", "For a_b tests.", &tags).unwrap();
+        assert_eq!(first.tags, vec![String::from("rust"), String::from("sqlite")]);
+        assert_eq!(storage.get_question(first.id).unwrap().unwrap().tags, first.tags);
+        let second = storage.create_question(bob.id, "Café or coffee?", "Which is 100 times better, abc or xyz?", &[]).unwrap();
+
+        // Newest first, narrowed down by every criterion
+        let ids = |filter: QuestionFilter| storage.list_questions(&filter, &Page::default())
+                                                  .unwrap()
+                                                  .iter()
+                                                  .map(|q| q.id)
+                                                  .collect::<Vec<i32>>();
+        let search = |text: &str| QuestionFilter {
+            search: Some(String::from(text)),
+            ..QuestionFilter::default()
+        };
+        assert_eq!(ids(QuestionFilter::default()), vec![second.id, first.id]);
+        assert_eq!(ids(QuestionFilter { tag: Some(String::from("rust")), ..QuestionFilter::default() }), vec![first.id]);
+        assert_eq!(ids(QuestionFilter { author_id: Some(bob.id), ..QuestionFilter::default() }), vec![second.id]);
+        assert_eq!(ids(search("COVERAGE")), vec![first.id]);
+        assert_eq!(ids(search("100%")), vec![first.id]);
+        assert_eq!(ids(search("a_b")), vec![first.id]);
+        assert_eq!(ids(search("a_c")), Vec::<i32>::new());
+        assert_eq!(ids(search("CAFÉ")), Vec::<i32>::new());
+        assert_eq!(storage.list_questions(&QuestionFilter::default(), &Page { offset: Some(1), limit: Some(1) }).unwrap()[0].id, first.id);
+
+        // Answers only count for the question they were posted to
+        let answer = storage.create_answer(first.id, bob.id, "Rarely.").unwrap();
+        assert!(storage.accept_answer(second.id, answer.id).is_err());
+        assert_eq!(storage.accept_answer(first.id, answer.id).unwrap().accepted_answer_id, Some(answer.id));
+        assert_eq!(ids(QuestionFilter { unanswered: Some(true), ..QuestionFilter::default() }), vec![second.id]);
+        assert!(storage.set_answer_hidden(answer.id, true).unwrap().hidden);
+        assert_eq!(storage.answers_for_question(first.id).unwrap().len(), 1);
+
+        let comment = storage.create_comment(alice.id, None, Some(answer.id), "Thanks!").unwrap();
+        assert_eq!(storage.comments_for_answer(answer.id).unwrap()[0].id, comment.id);
+        assert!(storage.comments_for_question(first.id).unwrap().is_empty());
+
+        assert!(storage.set_question_closed(first.id, true).unwrap().closed);
+        assert!(storage.set_question_locked(first.id, true).unwrap().locked);
+
+        // Revoked keys no longer resolve to their owner
+        let api_key = storage.create_api_key(alice.id, "ci", "rdk_0000", "key-hash").unwrap();
+        assert_eq!(storage.get_user_by_api_key("key-hash").unwrap().unwrap().id, alice.id);
+        assert!(storage.list_api_keys(alice.id).unwrap()[0].last_used_at.is_some());
+        assert!(storage.revoke_api_key(bob.id, api_key.id).is_err());
+        assert!(storage.revoke_api_key(alice.id, api_key.id).unwrap().revoked_at.is_some());
+        assert!(storage.get_user_by_api_key("key-hash").unwrap().is_none());
+    }
+
+    #[test]
+    fn memory_storage_behaves_like_a_backend() {
+        check_backend(&MemoryStorage::new());
+    }
+
+    #[test]
+    fn sqlite_storage_behaves_like_a_backend() {
+        let home = TempDir::new();
+        let database_path = home.path().join(DATABASE_FILE);
+        run_migrations(&mut Connection::open(&database_path).unwrap()).unwrap();
+
+        check_backend(&SqliteStorage::open(&database_path).unwrap());
+    }
+}
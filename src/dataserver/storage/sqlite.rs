@@ -0,0 +1,510 @@
+/**
+ * Storage implementation backed by an embedded SQLite
+ * database, so everything the dataserver holds survives
+ * a restart.
+ */
+use std::path::Path;
+use chrono::{
+    DateTime,
+    Utc
+};
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::{
+    Connection,
+    OptionalExtension,
+    Row,
+    ToSql,
+    params
+};
+
+use crate::dataserver::{
+    migrations::pending_migrations,
+    models::{
+        ApiKey,
+        User,
+        Question,
+        Answer,
+        Comment,
+        QuestionFilter,
+        Page,
+        Role
+    },
+    storage::{
+        PoolStats,
+        Storage
+    }
+};
+
+/// Columns selected for every user query.
+const USER_COLUMNS: &str = "u.id, u.username, u.created_at, u.role";
+
+/// Columns selected for every question query.
+const QUESTION_COLUMNS: &str = "q.id, q.author_id, q.title, q.body, q.accepted_answer_id, q.created_at, q.closed, q.locked";
+
+/// Columns selected for every answer query.
+const ANSWER_COLUMNS: &str = "id, question_id, author_id, body, created_at, hidden";
+
+/// Storage persisting the domain to a SQLite database file.
+pub struct SqliteStorage {
+    pool: Pool<SqliteConnectionManager>
+}
+
+impl SqliteStorage {
+
+    /// Open the database at the given path. The schema is
+    /// managed separately through `migrations`. Connections use
+    /// write-ahead logging so readers do not block the writer.
+    ///
+    /// # Arguments
+    /// * `path` - Location of the database file.
+    ///
+    /// # Examples
+    /// ```
+    /// let storage = SqliteStorage::open(&database_path).unwrap();
+    /// ```
+    pub fn open(path: &Path) -> Result<SqliteStorage, String> {
+        let manager = SqliteConnectionManager::file(path)
+                        .with_init(|conn| conn.execute_batch("PRAGMA foreign_keys = ON; PRAGMA journal_mode = WAL;"));
+        let pool = Pool::new(manager).map_err(|err| err.to_string())?;
+
+        Ok(SqliteStorage {
+            pool
+        })
+    }
+
+    /// Check a connection out of the pool.
+    fn connection(&self) -> Result<r2d2::PooledConnection<SqliteConnectionManager>, String> {
+        self.pool.get().map_err(|err| err.to_string())
+    }
+}
+
+/// Build a user from a row of `USER_COLUMNS`. Unknown roles
+/// fall back to the least privileged one.
+fn user_from_row(row: &Row) -> rusqlite::Result<User> {
+    Ok(User {
+        id: row.get(0)?,
+        username: row.get(1)?,
+        created_at: row.get::<_, DateTime<Utc>>(2)?,
+        role: row.get::<_, String>(3)?.parse().unwrap_or(Role::Member)
+    })
+}
+
+/// Columns selected for every API key query.
+const API_KEY_COLUMNS: &str = "id, user_id, name, prefix, created_at, last_used_at, revoked_at";
+
+/// Build an API key from a row of `API_KEY_COLUMNS`.
+fn api_key_from_row(row: &Row) -> rusqlite::Result<ApiKey> {
+    Ok(ApiKey {
+        id: row.get(0)?,
+        user_id: row.get(1)?,
+        name: row.get(2)?,
+        prefix: row.get(3)?,
+        created_at: row.get(4)?,
+        last_used_at: row.get(5)?,
+        revoked_at: row.get(6)?
+    })
+}
+
+/// Build a question, without its tags, from a row of `QUESTION_COLUMNS`.
+fn question_from_row(row: &Row) -> rusqlite::Result<Question> {
+    Ok(Question {
+        id: row.get(0)?,
+        author_id: row.get(1)?,
+        title: row.get(2)?,
+        body: row.get(3)?,
+        tags: Vec::new(),
+        accepted_answer_id: row.get(4)?,
+        closed: row.get(6)?,
+        locked: row.get(7)?,
+        created_at: row.get(5)?
+    })
+}
+
+/// Build an answer from a row of `ANSWER_COLUMNS`.
+fn answer_from_row(row: &Row) -> rusqlite::Result<Answer> {
+    Ok(Answer {
+        id: row.get(0)?,
+        question_id: row.get(1)?,
+        author_id: row.get(2)?,
+        body: row.get(3)?,
+        hidden: row.get(5)?,
+        created_at: row.get(4)?
+    })
+}
+
+/// Build a comment from a row of `id, author_id, question_id, answer_id, body, created_at`.
+fn comment_from_row(row: &Row) -> rusqlite::Result<Comment> {
+    Ok(Comment {
+        id: row.get(0)?,
+        author_id: row.get(1)?,
+        question_id: row.get(2)?,
+        answer_id: row.get(3)?,
+        body: row.get(4)?,
+        created_at: row.get(5)?
+    })
+}
+
+/// Fill in the tags of a question loaded from the database.
+fn with_tags(conn: &Connection, mut question: Question) -> Result<Question, String> {
+    let mut statement = conn.prepare("SELECT tag FROM question_tags WHERE question_id = ?1 ORDER BY tag")
+                            .map_err(|err| err.to_string())?;
+    question.tags = statement.query_map(params![question.id], |row| row.get(0))
+                             .and_then(|rows| rows.collect())
+                             .map_err(|err| err.to_string())?;
+    Ok(question)
+}
+
+/// Escape the wildcards of `LIKE` in text that must match literally,
+/// for use with `ESCAPE '\'`.
+fn escape_like(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+}
+
+/// Load a single question, with its tags, by ID.
+fn load_question(conn: &Connection, id: i32) -> Result<Option<Question>, String> {
+    let question = conn.query_row(
+                            &format!("SELECT {} FROM questions q WHERE q.id = ?1", QUESTION_COLUMNS),
+                            params![id],
+                            question_from_row)
+                        .optional()
+                        .map_err(|err| err.to_string())?;
+
+    match question {
+        Some(question) => Ok(Some(with_tags(conn, question)?)),
+        None => Ok(None)
+    }
+}
+
+impl Storage for SqliteStorage {
+
+    fn create_user(&self, username: &str, password_hash: &str) -> Result<User, String> {
+        let conn = self.connection()?;
+        let created_at = Utc::now();
+        conn.execute(
+                "INSERT INTO users (username, password_hash, created_at) VALUES (?1, ?2, ?3)",
+                params![username, password_hash, created_at])
+            .map_err(|err| match err {
+                rusqlite::Error::SqliteFailure(code, _) if code.code == rusqlite::ErrorCode::ConstraintViolation =>
+                    format!("Username {} is already taken.", username),
+                err => err.to_string()
+            })?;
+
+        Ok(User {
+            id: conn.last_insert_rowid() as i32,
+            username: String::from(username),
+            role: Role::Member,
+            created_at
+        })
+    }
+
+    fn get_user(&self, id: i32) -> Result<Option<User>, String> {
+        self.connection()?
+            .query_row(&format!("SELECT {} FROM users u WHERE u.id = ?1", USER_COLUMNS), params![id], user_from_row)
+            .optional()
+            .map_err(|err| err.to_string())
+    }
+
+    fn get_user_by_username(&self, username: &str) -> Result<Option<User>, String> {
+        self.connection()?
+            .query_row(&format!("SELECT {} FROM users u WHERE u.username = ?1", USER_COLUMNS), params![username], user_from_row)
+            .optional()
+            .map_err(|err| err.to_string())
+    }
+
+    fn get_password_hash(&self, user_id: i32) -> Result<Option<String>, String> {
+        self.connection()?
+            .query_row("SELECT password_hash FROM users WHERE id = ?1", params![user_id], |row| row.get(0))
+            .optional()
+            .map(|hash| hash.flatten())
+            .map_err(|err| err.to_string())
+    }
+
+    fn set_user_role(&self, id: i32, role: Role) -> Result<User, String> {
+        let conn = self.connection()?;
+        conn.execute("UPDATE users SET role = ?1 WHERE id = ?2", params![role.as_str(), id])
+            .map_err(|err| err.to_string())?;
+
+        conn.query_row(&format!("SELECT {} FROM users u WHERE u.id = ?1", USER_COLUMNS), params![id], user_from_row)
+            .optional()
+            .map_err(|err| err.to_string())?
+            .ok_or_else(|| format!("User {} does not exist.", id))
+    }
+
+    fn create_api_key(&self, user_id: i32, name: &str, prefix: &str, key_hash: &str) -> Result<ApiKey, String> {
+        let conn = self.connection()?;
+        let created_at = Utc::now();
+        conn.execute(
+                "INSERT INTO api_keys (user_id, name, prefix, key_hash, created_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![user_id, name, prefix, key_hash, created_at])
+            .map_err(|err| err.to_string())?;
+
+        Ok(ApiKey {
+            id: conn.last_insert_rowid() as i32,
+            user_id,
+            name: String::from(name),
+            prefix: String::from(prefix),
+            created_at,
+            last_used_at: None,
+            revoked_at: None
+        })
+    }
+
+    fn list_api_keys(&self, user_id: i32) -> Result<Vec<ApiKey>, String> {
+        let conn = self.connection()?;
+        let mut statement = conn.prepare(&format!("SELECT {} FROM api_keys WHERE user_id = ?1 ORDER BY id", API_KEY_COLUMNS))
+                                .map_err(|err| err.to_string())?;
+        statement.query_map(params![user_id], api_key_from_row)
+                 .and_then(|rows| rows.collect())
+                 .map_err(|err| err.to_string())
+    }
+
+    fn revoke_api_key(&self, user_id: i32, id: i32) -> Result<ApiKey, String> {
+        let conn = self.connection()?;
+        conn.execute(
+                "UPDATE api_keys SET revoked_at = ?1 WHERE id = ?2 AND user_id = ?3 AND revoked_at IS NULL",
+                params![Utc::now(), id, user_id])
+            .map_err(|err| err.to_string())?;
+
+        conn.query_row(
+                &format!("SELECT {} FROM api_keys WHERE id = ?1 AND user_id = ?2", API_KEY_COLUMNS),
+                params![id, user_id],
+                api_key_from_row)
+            .optional()
+            .map_err(|err| err.to_string())?
+            .ok_or_else(|| format!("API key {} does not exist.", id))
+    }
+
+    fn get_user_by_api_key(&self, key_hash: &str) -> Result<Option<User>, String> {
+        let conn = self.connection()?;
+        let updated = conn.execute(
+                                "UPDATE api_keys SET last_used_at = ?1 WHERE key_hash = ?2 AND revoked_at IS NULL",
+                                params![Utc::now(), key_hash])
+                          .map_err(|err| err.to_string())?;
+        if updated == 0 {
+            return Ok(None);
+        }
+
+        conn.query_row(
+                &format!("SELECT {} FROM users u JOIN api_keys k ON k.user_id = u.id WHERE k.key_hash = ?1", USER_COLUMNS),
+                params![key_hash],
+                user_from_row)
+            .optional()
+            .map_err(|err| err.to_string())
+    }
+
+    fn create_question(&self, author_id: i32, title: &str, body: &str, tags: &[String]) -> Result<Question, String> {
+        let mut conn = self.connection()?;
+        let transaction = conn.transaction().map_err(|err| err.to_string())?;
+        let created_at = Utc::now();
+
+        transaction.execute(
+                        "INSERT INTO questions (author_id, title, body, created_at) VALUES (?1, ?2, ?3, ?4)",
+                        params![author_id, title, body, created_at])
+                   .map_err(|err| err.to_string())?;
+        let id = transaction.last_insert_rowid() as i32;
+
+        let tags = Question::normalize_tags(tags);
+        for tag in &tags {
+            transaction.execute("INSERT INTO question_tags (question_id, tag) VALUES (?1, ?2)", params![id, tag])
+                       .map_err(|err| err.to_string())?;
+        }
+        transaction.commit().map_err(|err| err.to_string())?;
+
+        Ok(Question {
+            id,
+            author_id,
+            title: String::from(title),
+            body: String::from(body),
+            tags,
+            accepted_answer_id: None,
+            closed: false,
+            locked: false,
+            created_at
+        })
+    }
+
+    fn get_question(&self, id: i32) -> Result<Option<Question>, String> {
+        let conn = self.connection()?;
+        load_question(&conn, id)
+    }
+
+    fn list_questions(&self, filter: &QuestionFilter, page: &Page) -> Result<Vec<Question>, String> {
+        let conn = self.connection()?;
+        let mut query = format!("SELECT {} FROM questions q WHERE 1 = 1", QUESTION_COLUMNS);
+        let mut values: Vec<Box<dyn ToSql>> = Vec::new();
+
+        // Narrow down the query for each given criterion
+        if let Some(tag) = &filter.tag {
+            values.push(Box::new(tag.clone()));
+            query.push_str(&format!(" AND EXISTS (SELECT 1 FROM question_tags t WHERE t.question_id = q.id AND t.tag = ?{})", values.len()));
+        }
+        if let Some(author_id) = filter.author_id {
+            values.push(Box::new(author_id));
+            query.push_str(&format!(" AND q.author_id = ?{}", values.len()));
+        }
+        if let Some(search) = &filter.search {
+            values.push(Box::new(format!("%{}%", escape_like(search))));
+            query.push_str(&format!(" AND (q.title LIKE ?{0} ESCAPE '\\' OR q.body LIKE ?{0} ESCAPE '\\')", values.len()));
+        }
+        if filter.unanswered == Some(true) {
+            query.push_str(" AND q.accepted_answer_id IS NULL");
+        }
+
+        values.push(Box::new(page.limit() as i64));
+        values.push(Box::new(page.offset() as i64));
+        query.push_str(&format!(" ORDER BY q.id DESC LIMIT ?{} OFFSET ?{}", values.len() - 1, values.len()));
+
+        let mut statement = conn.prepare(&query).map_err(|err| err.to_string())?;
+        let questions = statement.query_map(values.iter().map(|v| v.as_ref()), question_from_row)
+                                 .and_then(|rows| rows.collect::<rusqlite::Result<Vec<Question>>>())
+                                 .map_err(|err| err.to_string())?;
+
+        questions.into_iter().map(|q| with_tags(&conn, q)).collect()
+    }
+
+    fn set_question_closed(&self, id: i32, closed: bool) -> Result<Question, String> {
+        let conn = self.connection()?;
+        conn.execute("UPDATE questions SET closed = ?1 WHERE id = ?2", params![closed, id])
+            .map_err(|err| err.to_string())?;
+        load_question(&conn, id)?.ok_or_else(|| format!("Question {} does not exist.", id))
+    }
+
+    fn set_question_locked(&self, id: i32, locked: bool) -> Result<Question, String> {
+        let conn = self.connection()?;
+        conn.execute("UPDATE questions SET locked = ?1 WHERE id = ?2", params![locked, id])
+            .map_err(|err| err.to_string())?;
+        load_question(&conn, id)?.ok_or_else(|| format!("Question {} does not exist.", id))
+    }
+
+    fn accept_answer(&self, question_id: i32, answer_id: i32) -> Result<Question, String> {
+        let conn = self.connection()?;
+        let belongs = conn.query_row(
+                                "SELECT COUNT(*) FROM answers WHERE id = ?1 AND question_id = ?2",
+                                params![answer_id, question_id],
+                                |row| row.get::<_, i64>(0))
+                          .map_err(|err| err.to_string())?;
+        if belongs == 0 {
+            return Err(format!("Answer {} does not belong to question {}.", answer_id, question_id));
+        }
+
+        conn.execute("UPDATE questions SET accepted_answer_id = ?1 WHERE id = ?2", params![answer_id, question_id])
+            .map_err(|err| err.to_string())?;
+        load_question(&conn, question_id)?.ok_or_else(|| format!("Question {} does not exist.", question_id))
+    }
+
+    fn create_answer(&self, question_id: i32, author_id: i32, body: &str) -> Result<Answer, String> {
+        let conn = self.connection()?;
+        if load_question(&conn, question_id)?.is_none() {
+            return Err(format!("Question {} does not exist.", question_id));
+        }
+
+        let created_at = Utc::now();
+        conn.execute(
+                "INSERT INTO answers (question_id, author_id, body, created_at) VALUES (?1, ?2, ?3, ?4)",
+                params![question_id, author_id, body, created_at])
+            .map_err(|err| err.to_string())?;
+
+        Ok(Answer {
+            id: conn.last_insert_rowid() as i32,
+            question_id,
+            author_id,
+            body: String::from(body),
+            hidden: false,
+            created_at
+        })
+    }
+
+    fn get_answer(&self, id: i32) -> Result<Option<Answer>, String> {
+        self.connection()?
+            .query_row(
+                &format!("SELECT {} FROM answers WHERE id = ?1", ANSWER_COLUMNS),
+                params![id],
+                answer_from_row)
+            .optional()
+            .map_err(|err| err.to_string())
+    }
+
+    fn set_answer_hidden(&self, id: i32, hidden: bool) -> Result<Answer, String> {
+        let conn = self.connection()?;
+        conn.execute("UPDATE answers SET hidden = ?1 WHERE id = ?2", params![hidden, id])
+            .map_err(|err| err.to_string())?;
+
+        conn.query_row(&format!("SELECT {} FROM answers WHERE id = ?1", ANSWER_COLUMNS), params![id], answer_from_row)
+            .optional()
+            .map_err(|err| err.to_string())?
+            .ok_or_else(|| format!("Answer {} does not exist.", id))
+    }
+
+    fn answers_for_question(&self, question_id: i32) -> Result<Vec<Answer>, String> {
+        let conn = self.connection()?;
+        let mut statement = conn.prepare(&format!("SELECT {} FROM answers WHERE question_id = ?1 ORDER BY id", ANSWER_COLUMNS))
+                                .map_err(|err| err.to_string())?;
+        statement.query_map(params![question_id], answer_from_row)
+                 .and_then(|rows| rows.collect())
+                 .map_err(|err| err.to_string())
+    }
+
+    fn create_comment(&self, author_id: i32, question_id: Option<i32>, answer_id: Option<i32>, body: &str) -> Result<Comment, String> {
+        let conn = self.connection()?;
+        let created_at = Utc::now();
+        conn.execute(
+                "INSERT INTO comments (author_id, question_id, answer_id, body, created_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![author_id, question_id, answer_id, body, created_at])
+            .map_err(|err| err.to_string())?;
+
+        Ok(Comment {
+            id: conn.last_insert_rowid() as i32,
+            author_id,
+            question_id,
+            answer_id,
+            body: String::from(body),
+            created_at
+        })
+    }
+
+    fn comments_for_question(&self, question_id: i32) -> Result<Vec<Comment>, String> {
+        let conn = self.connection()?;
+        let mut statement = conn.prepare("SELECT id, author_id, question_id, answer_id, body, created_at FROM comments WHERE question_id = ?1 ORDER BY id")
+                                .map_err(|err| err.to_string())?;
+        statement.query_map(params![question_id], comment_from_row)
+                 .and_then(|rows| rows.collect())
+                 .map_err(|err| err.to_string())
+    }
+
+    fn comments_for_answer(&self, answer_id: i32) -> Result<Vec<Comment>, String> {
+        let conn = self.connection()?;
+        let mut statement = conn.prepare("SELECT id, author_id, question_id, answer_id, body, created_at FROM comments WHERE answer_id = ?1 ORDER BY id")
+                                .map_err(|err| err.to_string())?;
+        statement.query_map(params![answer_id], comment_from_row)
+                 .and_then(|rows| rows.collect())
+                 .map_err(|err| err.to_string())
+    }
+
+    fn flush(&self) -> Result<(), String> {
+        // Fold the write-ahead log back into the database file
+        let conn = self.connection()?;
+        conn.execute_batch("PRAGMA wal_checkpoint(TRUNCATE);").map_err(|err| err.to_string())
+    }
+
+    fn pool_stats(&self) -> Option<PoolStats> {
+        let state = self.pool.state();
+        Some(PoolStats {
+            connections: state.connections,
+            idle_connections: state.idle_connections,
+            max_size: self.pool.max_size()
+        })
+    }
+
+    fn ping(&self) -> Result<(), String> {
+        let conn = self.connection()?;
+        conn.query_row("SELECT 1", params![], |row| row.get::<_, i64>(0))
+            .map(|_| ())
+            .map_err(|err| err.to_string())
+    }
+
+    fn pending_migration_count(&self) -> Result<usize, String> {
+        let conn = self.connection()?;
+        pending_migrations(&conn).map(|pending| pending.len())
+    }
+}
@@ -12,55 +12,81 @@ extern crate log;
 extern crate structopt;
 extern crate fern;
 extern crate chrono;
+#[macro_use]
 extern crate juniper;
+#[macro_use]
+extern crate lazy_static;
 extern crate juniper_rocket;
 extern crate rocket;
 extern crate dirs;
+extern crate rusqlite;
+extern crate r2d2;
+extern crate r2d2_sqlite;
+extern crate argon2;
+extern crate hmac;
+extern crate sha2;
+extern crate rand;
+extern crate hex;
+extern crate libc;
+extern crate signal_hook;
+extern crate serde_json;
+extern crate flate2;
+extern crate prometheus;
+extern crate toml;
+extern crate fs2;
+extern crate chacha20poly1305;
 
 pub mod cli;
 pub mod dataserver;
+pub mod settings;
+#[cfg(test)]
+mod testing;
 
 use structopt::StructOpt;
-use cli::Command;
-
-/// Setup the logger.
-///
-/// By default, this will log to stdout and locally
-/// to a file out/output.log for any searching necessary.
-fn setup_logger() -> Result<(), fern::InitError> {
-    fern::Dispatch::new()
-        .format(|out, message, record| {
-            out.finish(format_args!(
-                "{}[{}][{}] {}",
-                chrono::Utc::now().format("[%Y-%m-%d][%H:%M:%S]"),
-                record.target(),
-                record.level(),
-                message
-            ))
-        })
-        .level(log::LevelFilter::Debug)
-        .chain(std::io::stdout())
-        .apply()?;
-    Ok(())
-}
-
+use cli::{
+    Command,
+    logging::LoggingConfig
+};
 
 /// Entry point of the CLI
 fn main() {
-    // Set all known environment variables
-    if cli::environment::set_environment().is_err() {
-        error!("Failed to set process environment.");
+    // A profile given as a flag counts as set in the environment
+    // rd was started in, so servers it spawns use it too
+    let opts = cli::RD::from_args();
+    if let Some(profile) = &opts.profile {
+        std::env::set_var("RD_PROFILE", profile);
     }
 
-    setup_logger().expect("Could not configure logger.");
+    // Set all known environment variables, then whatever rd.toml
+    // sets that the environment and env files leave unset. A profile
+    // that is about to be created counts as empty until then
+    if let Err(msg) = cli::environment::set_environment(opts.cmd.creates_profile()) {
+        eprintln!("Failed to set process environment: {}", msg);
+    }
+    if let Err(msg) = cli::config::set_config_environment() {
+        eprintln!("Ignoring {}: {}", cli::config::CONFIG_FILE, msg);
+    }
+
+    // Dataserver commands log to the file of the instance they act on
+    let instance = match &opts.cmd {
+        Command::Dataserver(cmd) => cmd.instance.clone(),
+        Command::Logs(cmd) => cmd.instance.clone(),
+        _ => cli::environment::current_instance()
+    };
+    cli::logging::setup_logger(&LoggingConfig::resolve(&opts.logging), &instance, opts.cmd.prints_output())
+        .expect("Could not configure logger.");
     
     // Let's fire off the command!!
-    let opts = cli::RD::from_args();
     match opts.cmd {
 
         // For our dataserver...
         Command::Dataserver(cmd) => cli::dataserver::run_dataserver_command(&cmd),
-        Command::Environment(cmd) => cli::environment::run_environment_command(&cmd),
-        Command::Install(cmd) => cli::install_executable(&cmd)
+        Command::Environment(cmd) => cli::environment::run_environment_command(&cmd, opts.profile.as_ref().map(String::as_str)),
+        Command::Apikey(cmd) => cli::apikey::run_apikey_command(&cmd),
+        Command::User(cmd) => cli::user::run_user_command(&cmd),
+        Command::Logs(cmd) => cli::logs::run_logs_command(&cmd),
+        Command::Config(cmd) => cli::config::run_config_command(&cmd, &opts.logging),
+        Command::Install(cmd) => cli::install_executable(&cmd),
+        Command::Completions(cmd) => cli::generate_completions(&cmd)
     }
 }
@@ -0,0 +1,88 @@
+/**
+ * Settings read straight from the environment, shared by
+ * the CLI and the dataserver.
+ */
+use std::{
+    env,
+    str::FromStr
+};
+use log::LevelFilter;
+
+/// Log a warning. Until the logger is set up, as while env files
+/// are first loaded, or with logging turned off, the warning goes
+/// straight to stderr instead.
+///
+/// # Arguments
+/// * `msg` - Warning to give.
+///
+/// # Examples
+/// ```
+/// warn_or_print(&format!("Ignoring invalid {}={:?}", key, value));
+/// ```
+pub fn warn_or_print(msg: &str) {
+    if log::max_level() == LevelFilter::Off {
+        eprintln!("{}", msg);
+    } else {
+        warn!("{}", msg);
+    }
+}
+
+/// Parse the value of a setting, warning and falling back to the
+/// default if it cannot be parsed. Until the logger is set up, or
+/// with logging turned off, warnings go straight to stderr.
+///
+/// # Arguments
+/// * `key` - Environment variable holding the setting.
+/// * `value` - Value of the variable, if it is set.
+/// * `default` - Value used when the variable is unset or invalid.
+///
+/// # Examples
+/// ```
+/// let workers = parse_setting("RD_WORKERS", Some("4"), 3u16);
+/// ```
+pub fn parse_setting<T: FromStr>(key: &str, value: Option<&str>, default: T) -> T where T::Err: ToString {
+    match value {
+        Some(value) => value.trim().parse().unwrap_or_else(|err: T::Err| {
+            warn_or_print(&format!("Ignoring invalid {}={:?}: {}", key, value, err.to_string()));
+            default
+        }),
+        None => default
+    }
+}
+
+/// Read a setting from the environment, warning and falling back
+/// to the default if it cannot be parsed.
+///
+/// # Arguments
+/// * `key` - Environment variable holding the setting.
+/// * `default` - Value used when the variable is unset or invalid.
+///
+/// # Examples
+/// ```
+/// let compress = env_setting("RD_LOG_COMPRESS", true);
+/// ```
+pub fn env_setting<T: FromStr>(key: &str, default: T) -> T where T::Err: ToString {
+    parse_setting(key, env::var(key).ok().as_ref().map(String::as_str), default)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unset_setting_takes_default() {
+        assert_eq!(parse_setting("RD_TEST_UNSET", None, 25u64), 25);
+    }
+
+    #[test]
+    fn setting_is_parsed_ignoring_whitespace() {
+        assert_eq!(parse_setting("RD_TEST_NUMBER", Some(" 40\n"), 25u64), 40);
+        assert_eq!(parse_setting("RD_TEST_FLAG", Some("false"), true), false);
+    }
+
+    #[test]
+    fn invalid_setting_takes_default() {
+        assert_eq!(parse_setting("RD_TEST_INVALID", Some("abc"), 25u64), 25);
+        assert_eq!(parse_setting("RD_TEST_NEGATIVE", Some("-1"), 25u64), 25);
+    }
+}
@@ -0,0 +1,103 @@
+/**
+ * Helpers shared by the tests of every module.
+ */
+use std::{
+    cell::RefCell,
+    env,
+    fs,
+    path::{
+        Path,
+        PathBuf
+    },
+    process,
+    sync::atomic::{
+        AtomicUsize,
+        Ordering
+    }
+};
+
+/// Tells apart the directories created by one test run.
+static NEXT_TEMP_DIR: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    /// RD_HOME of the test running on this thread. Every test runs on
+    /// a thread of its own, so tests running at the same time never
+    /// share a home, and the process environment is left alone.
+    static RD_HOME: RefCell<Option<PathBuf>> = RefCell::new(None);
+}
+
+/// A directory of its own for a single test, removed with everything
+/// in it once dropped.
+pub struct TempDir {
+    path: PathBuf
+}
+
+impl TempDir {
+
+    /// Create a new, empty directory in the system's temporary directory.
+    ///
+    /// # Examples
+    /// ```
+    /// let home = TempDir::new();
+    /// ```
+    pub fn new() -> TempDir {
+        let name = format!("rd-test-{}-{}", process::id(), NEXT_TEMP_DIR.fetch_add(1, Ordering::SeqCst));
+        let path = env::temp_dir().join(name);
+        fs::create_dir_all(&path).expect("Could not create temporary directory.");
+
+        TempDir {
+            path
+        }
+    }
+
+    /// Location of the directory.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// An RD_HOME of its own for a single test, in use by the test
+/// until dropped.
+pub struct TestRdHome {
+    dir: TempDir
+}
+
+impl TestRdHome {
+
+    /// Create an empty RD_HOME and use it for the rest of the test.
+    ///
+    /// # Examples
+    /// ```
+    /// let home = TestRdHome::new();
+    /// ```
+    pub fn new() -> TestRdHome {
+        let dir = TempDir::new();
+        RD_HOME.with(|home| *home.borrow_mut() = Some(dir.path().to_path_buf()));
+
+        TestRdHome {
+            dir
+        }
+    }
+
+    /// Location of the home.
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+}
+
+impl Drop for TestRdHome {
+    fn drop(&mut self) {
+        RD_HOME.with(|home| *home.borrow_mut() = None);
+    }
+}
+
+/// Get the RD_HOME set up by the test running on this thread, if any.
+pub fn test_rd_home() -> Option<PathBuf> {
+    RD_HOME.with(|home| home.borrow().clone())
+}